@@ -0,0 +1,68 @@
+//! Decoding of the envelopes a node only forwards, `LimeCodec` against the
+//! lazy `RawCodec`.
+
+#![feature(test)]
+
+extern crate rust_lime;
+extern crate test;
+extern crate tokio_core;
+
+use test::Bencher;
+use tokio_core::io::{Codec, EasyBuf};
+
+use rust_lime::envelope::{LimeCodec, RawCodec};
+
+/// A message with a sizeable json content, as routed between services.
+fn frame() -> Vec<u8> {
+    let batches: Vec<String> = (0..100)
+        .map(|i| format!(r#"{{ "batch": {}, "purity": 99.1 }}"#, i))
+        .collect();
+    let json = format!(r#"{{
+            "id": 1,
+            "from": "ww@breakingbad.com/lab",
+            "to": "jesse@breakingbad.com",
+            "type": "application/json",
+            "metadata": {{ "trace": "abc" }},
+            "content": {{ "batches": [{}] }}
+        }}"#, batches.join(", "));
+    // A frame is a single line.
+    let mut frame = json.replace("\n", "").into_bytes();
+    frame.push(b'\n');
+    frame
+}
+
+#[bench]
+fn decode_full(b: &mut Bencher) {
+    let frame = frame();
+    b.iter(|| LimeCodec.decode(&mut EasyBuf::from(frame.clone())).unwrap());
+}
+
+#[bench]
+fn decode_lazy(b: &mut Bencher) {
+    let frame = frame();
+    b.iter(|| RawCodec.decode(&mut EasyBuf::from(frame.clone())).unwrap());
+}
+
+#[bench]
+fn forward_full(b: &mut Bencher) {
+    let frame = frame();
+    b.iter(|| {
+        let envelope = LimeCodec.decode(&mut EasyBuf::from(frame.clone()))
+            .unwrap().unwrap();
+        let mut out = Vec::new();
+        LimeCodec.encode(envelope, &mut out).unwrap();
+        out
+    });
+}
+
+#[bench]
+fn forward_lazy(b: &mut Bencher) {
+    let frame = frame();
+    b.iter(|| {
+        let envelope = RawCodec.decode(&mut EasyBuf::from(frame.clone()))
+            .unwrap().unwrap();
+        let mut out = Vec::new();
+        RawCodec.encode(envelope, &mut out).unwrap();
+        out
+    });
+}
@@ -6,33 +6,43 @@ use tokio_core::io::{
 
 use std::io;
 use std::str;
+
+use serde_cbor;
+
 use envelope::{ Envelope, DELIMITER };
+use envelope::ser::{self, ParsePolicy, StrictEnvelope};
+
+/// Largest CBOR frame, the first byte of the length prefix is then always
+/// zero which is how `EnvelopeCodec` tells it apart from json.
+static MAX_CBOR_FRAME: usize = (1 << 24) - 1;
 
 /// Type used by the Node structs for input and output, allowing a user to
 /// set up a Node which communicates over any type of network connection.
-pub type EnvelopeStream<T> = Framed<T, LimeCodec>;
+/// Servers `negotiate` the encoding of each of their connections.
+pub type EnvelopeStream<T> = Framed<T, EnvelopeCodec>;
 pub struct LimeCodec;
 
+fn decode_json(buf: &mut EasyBuf, policy: ParsePolicy)
+        -> Result<Option<Envelope>, io::Error> {
+    match buf.as_slice().iter().position(|&b| b == DELIMITER) {
+        Some(index) => {
+            let buf = buf.drain_to(index + 1);
+            ser::from_slice(buf.as_slice(), policy).map(Some).map_err(|err| {
+                io::Error::new(io::ErrorKind::Other,
+                               format!("Failed to decode object: {}", err))
+            })
+        }
+        None => Ok(None)
+    }
+}
+
 impl Codec for LimeCodec {
     type In = Envelope;
     type Out = Envelope;
 
     fn decode(&mut self, buf: &mut EasyBuf)
             -> Result<Option<Self::In>, io::Error> {
-        match buf.as_slice().iter().position(|&b| b == DELIMITER) {
-            Some(index) => {
-                let buf = buf.drain_to(index + 1);
-                use serde_json::from_slice;
-                if let Ok(e) = from_slice::<Envelope>(buf.as_slice()) {
-                    Ok(Some(e))
-                } else {
-                    Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        "Failed to decode object".to_string()))
-                }
-            }
-            None => Ok(None)
-        }
+        decode_json(buf, ParsePolicy::Lenient)
     }
 
     fn encode(&mut self, msg: Self::Out, buf: &mut Vec<u8>) -> io::Result<()> {
@@ -49,6 +59,137 @@ impl Codec for LimeCodec {
 
 }
 
+/// How envelopes are written on a connection.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    /// `LimeCodec`, the LIME standard.
+    Json,
+    /// `CborCodec`, for internal links where size matters more.
+    Cbor,
+}
+
+/// The same envelopes as `LimeCodec` encoded in CBOR, each prefixed by its
+/// length as a big endian `u32`.
+pub struct CborCodec;
+
+fn decode_cbor(buf: &mut EasyBuf, policy: ParsePolicy)
+        -> Result<Option<Envelope>, io::Error> {
+    if buf.len() < 4 { return Ok(None); }
+    let len = buf.as_slice()[..4].iter()
+        .fold(0, |len, &b| (len << 8) | b as usize);
+    if len > MAX_CBOR_FRAME {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                  "The frame is too large"));
+    }
+    if buf.len() < 4 + len { return Ok(None); }
+
+    let frame = buf.drain_to(4 + len);
+    let bytes = &frame.as_slice()[4..];
+    let envelope = match policy {
+        ParsePolicy::Strict => serde_cbor::from_slice(bytes)
+            .map(|StrictEnvelope(envelope)| envelope),
+        ParsePolicy::Lenient => serde_cbor::from_slice(bytes),
+    };
+    envelope.map(Some).map_err(|err| {
+        io::Error::new(io::ErrorKind::Other,
+                       format!("Failed to decode object: {}", err))
+    })
+}
+
+impl Codec for CborCodec {
+    type In = Envelope;
+    type Out = Envelope;
+
+    fn decode(&mut self, buf: &mut EasyBuf)
+            -> Result<Option<Self::In>, io::Error> {
+        decode_cbor(buf, ParsePolicy::Lenient)
+    }
+
+    fn encode(&mut self, msg: Self::Out, buf: &mut Vec<u8>) -> io::Result<()> {
+        let frame = serde_cbor::to_vec(&msg).map_err(|_| {
+            io::Error::new(io::ErrorKind::Other, "Failed to encode object")
+        })?;
+        if frame.len() > MAX_CBOR_FRAME {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "The frame is too large"));
+        }
+        let len = frame.len() as u32;
+        buf.extend_from_slice(&[(len >> 24) as u8, (len >> 16) as u8,
+                                (len >> 8) as u8, len as u8]);
+        buf.extend_from_slice(&frame);
+        Ok(())
+    }
+}
+
+/// Codec whose encoding is picked per connection.
+///
+/// Clients pick it with `new`. Servers `negotiate` it: the encoding of the
+/// first frame received is kept for the rest of the connection, and json is
+/// written until then. Envelopes are parsed leniently unless another policy
+/// is given with `with_policy`.
+pub struct EnvelopeCodec {
+    encoding: Option<Encoding>,
+    policy: ParsePolicy,
+}
+
+impl EnvelopeCodec {
+    pub fn new(encoding: Encoding) -> Self {
+        EnvelopeCodec {
+            encoding: Some(encoding),
+            policy: ParsePolicy::default(),
+        }
+    }
+
+    pub fn negotiate() -> Self {
+        EnvelopeCodec {
+            encoding: None,
+            policy: ParsePolicy::default(),
+        }
+    }
+
+    pub fn with_policy(mut self, policy: ParsePolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// The encoding of the connection, `None` while still negotiating.
+    pub fn encoding(&self) -> Option<Encoding> {
+        self.encoding
+    }
+
+    pub fn policy(&self) -> ParsePolicy {
+        self.policy
+    }
+}
+
+impl Codec for EnvelopeCodec {
+    type In = Envelope;
+    type Out = Envelope;
+
+    fn decode(&mut self, buf: &mut EasyBuf)
+            -> Result<Option<Self::In>, io::Error> {
+        let encoding = match self.encoding {
+            Some(encoding) => encoding,
+            None if buf.len() == 0 => return Ok(None),
+            // A json envelope never starts with a null byte.
+            None if buf.as_slice()[0] == 0 => Encoding::Cbor,
+            None => Encoding::Json,
+        };
+        self.encoding = Some(encoding);
+        match encoding {
+            Encoding::Json => decode_json(buf, self.policy),
+            Encoding::Cbor => decode_cbor(buf, self.policy),
+        }
+    }
+
+    fn encode(&mut self, msg: Self::Out, buf: &mut Vec<u8>) -> io::Result<()> {
+        match self.encoding.unwrap_or(Encoding::Json) {
+            Encoding::Json => LimeCodec.encode(msg, buf),
+            Encoding::Cbor => CborCodec.encode(msg, buf),
+        }
+    }
+}
+
 pub struct LimeMultiCodec;
 
 impl Codec for LimeMultiCodec {
@@ -1,4 +1,5 @@
-use envelope::{ErrReason, JsonMap, Node, MsgID};
+use envelope::{ErrReason, JsonMap, Metadata, Node, MsgID, Resources,
+    MediaType};
 
 mod ser;
 
@@ -8,19 +9,46 @@ pub struct Command {
     pub from: Option<Node>,
     pub pp: Option<Node>,
     pub id: Option<MsgID>,
-    pub metadata: Option<JsonMap>,
+    pub metadata: Option<Metadata>,
 
     pub method: CommandMethod,
     pub status: Option<CommandStatus>,
 
     pub uri: Option<String>,
-    pub mime_type: Option<String>,
+    pub mime_type: Option<MediaType>,
+    pub resource: Option<Resources>,
+
+    /// Fields unknown to the protocol, see `ParsePolicy::Lenient`.
+    pub extras: JsonMap,
+}
+
+impl Command {
+    /// Creates the response to this command, the id is kept and the `to` /
+    /// `from` fields are swapped so it can be sent straight back.
+    pub fn response(&self, status: CommandStatus) -> Command {
+        Command {
+            to: self.from.clone(),
+            from: self.to.clone(),
+            pp: None,
+            id: self.id,
+            metadata: None,
+
+            method: self.method,
+            status: Some(status),
+
+            uri: None,
+            mime_type: None,
+            resource: None,
+
+            extras: JsonMap::new(),
+        }
+    }
 }
 
 /// Signifies the event which pertains to a previously dealt with message.
 /// Uses 'id' from sent message to determine which one should happen.
 /// TODO: Unique set of 'id's per user or nah?
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum CommandMethod {
     #[serde(rename="get")]          Get,
     #[serde(rename="set")]          Set,
@@ -35,4 +63,3 @@ pub enum CommandStatus {
     Success,
     Failure(ErrReason),
 }
-
@@ -1,6 +1,6 @@
 use serde::ser::{Serialize, Serializer};
-use envelope::{JsonMap, ErrReason, MsgID};
-use envelope::helper::CommandStatusHelper;
+use envelope::{Metadata, ErrReason, MsgID, Resources, MediaType};
+use envelope::helper::{CommandStatusHelper, serialize_with_extras};
 use envelope::command::*;
 
 impl Serialize for Command {
@@ -15,7 +15,7 @@ impl Serialize for Command {
             from: Option<&'a str>,
             pp: Option<&'a str>,
             id: Option<&'a MsgID>,
-            metadata: Option<&'a JsonMap>,
+            metadata: Option<&'a Metadata>,
 
             method: &'a CommandMethod,
             status: Option<&'a CommandStatusHelper>,
@@ -23,7 +23,8 @@ impl Serialize for Command {
 
             uri: Option<&'a str>,
             #[serde(rename="type")]
-            mime_type: Option<&'a str>,
+            mime_type: Option<&'a MediaType>,
+            resource: Option<&'a Resources>,
         }
 
         use envelope::helper::CommandStatusHelper::*;
@@ -36,7 +37,7 @@ impl Serialize for Command {
             None => (None, None)
         };
 
-        CommandHelper {
+        let helper = CommandHelper {
             to: self.to.as_ref().map(|s| &**s),
             from: self.from.as_ref().map(|s| &**s),
             pp: self.pp.as_ref().map(|s| &**s),
@@ -48,8 +49,10 @@ impl Serialize for Command {
             reason: reason,
 
             uri: self.uri.as_ref().map(|s| &**s),
-            mime_type: self.mime_type.as_ref().map(|s| &**s),
-        }.serialize(serializer)
+            mime_type: self.mime_type.as_ref(),
+            resource: self.resource.as_ref(),
+        };
+        serialize_with_extras(&helper, &self.extras, serializer)
     }
 }
 
@@ -0,0 +1,188 @@
+use std::any::Any;
+use std::collections::HashMap;
+
+use serde_json::Value;
+
+use envelope::{Content, MediaType};
+use envelope::resources::*;
+use envelope::message::documents::*;
+
+/// Why some content could not be read as a given document type.
+#[derive(Debug, PartialEq)]
+pub enum DocumentError {
+    /// The content was declared with a different media type.
+    MediaType { expected: String, found: String },
+    /// The content doesn't have the structure of the document.
+    Invalid(String),
+}
+
+/// A typed representation of message or command content, identified by its
+/// media type (ex. `text/plain` or `application/vnd.lime.account+json`).
+pub trait Document: Sized {
+    fn media_type() -> &'static str;
+
+    fn from_content(content: &Content) -> Result<Self, DocumentError>;
+    fn to_content(&self) -> Content;
+}
+
+/// Implements `Document` for a type using its serde implementation.
+macro_rules! impl_Document(
+    ($kind: ty, $media_type: expr) => (
+        impl $crate::envelope::document::Document for $kind {
+            fn media_type() -> &'static str { $media_type }
+
+            fn from_content(content: &$crate::envelope::Content)
+                    -> Result<Self, $crate::envelope::document::DocumentError> {
+                ::serde_json::from_value(content.clone()).map_err(|err| {
+                    $crate::envelope::document::DocumentError::Invalid(
+                        format!("{:?}", err))
+                })
+            }
+
+            fn to_content(&self) -> $crate::envelope::Content {
+                ::serde_json::to_value(self)
+            }
+        }
+    );
+);
+
+/// Content of a `text/plain` message, carried as a json string.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlainText(pub String);
+
+impl Document for PlainText {
+    fn media_type() -> &'static str { "text/plain" }
+
+    fn from_content(content: &Content) -> Result<Self, DocumentError> {
+        match content.as_str() {
+            Some(text) => Ok(PlainText(text.to_owned())),
+            None => Err(DocumentError::Invalid(
+                "text/plain content must be a string".to_owned())),
+        }
+    }
+
+    fn to_content(&self) -> Content {
+        Value::String(self.0.clone())
+    }
+}
+
+/// Content of an `application/json` message, any json value is accepted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonDocument(pub Value);
+
+impl Document for JsonDocument {
+    fn media_type() -> &'static str { "application/json" }
+
+    fn from_content(content: &Content) -> Result<Self, DocumentError> {
+        Ok(JsonDocument(content.clone()))
+    }
+
+    fn to_content(&self) -> Content {
+        self.0.clone()
+    }
+}
+
+impl_Document!(Account, ACCOUNT_MIME);
+impl_Document!(Capability, CAPABILITY_MIME);
+impl_Document!(Presence, PRESENCE_MIME);
+impl_Document!(Contact, CONTACT_MIME);
+impl_Document!(Collection, COLLECTION_MIME);
+impl_Document!(Group, GROUP_MIME);
+impl_Document!(GroupMember, GROUP_MEMBER_MIME);
+impl_Document!(Thread, THREAD_MIME);
+impl_Document!(ThreadMessage, THREAD_MESSAGE_MIME);
+impl_Document!(Delegation, DELEGATION_MIME);
+
+/// Reads `content` as `T`, checking it was declared with `T`'s media type.
+pub fn content_as<T: Document>(mime_type: &MediaType, content: &Content)
+        -> Result<T, DocumentError> {
+    if mime_type.essence() != T::media_type() {
+        return Err(DocumentError::MediaType {
+            expected: T::media_type().to_owned(),
+            found: mime_type.to_string(),
+        });
+    }
+    T::from_content(content)
+}
+
+/// The result of decoding content through a `DocumentRegistry`.
+pub enum Decoded {
+    /// A registered document, downcast it with `downcast`.
+    Document(Box<Any + Send>),
+    /// No document is registered for the media type.
+    Raw(Value),
+}
+
+impl Decoded {
+    pub fn downcast<T: Any>(self) -> Option<T> {
+        match self {
+            Decoded::Document(doc) => doc.downcast::<T>().ok().map(|doc| *doc),
+            Decoded::Raw(_) => None,
+        }
+    }
+}
+
+type Decoder = fn(&Content) -> Result<Box<Any + Send>, DocumentError>;
+
+fn decode<T: Document + Any + Send>(content: &Content)
+        -> Result<Box<Any + Send>, DocumentError> {
+    T::from_content(content).map(|doc| Box::new(doc) as Box<Any + Send>)
+}
+
+/// Maps media types to the document types able to read them.
+///
+/// The registry starts out with the documents defined by this crate, so
+/// applications only need to register their own types.
+pub struct DocumentRegistry {
+    decoders: HashMap<String, Decoder>,
+}
+
+impl DocumentRegistry {
+    pub fn new() -> Self {
+        let mut registry = DocumentRegistry { decoders: HashMap::new() };
+        registry.register::<PlainText>();
+        registry.register::<JsonDocument>();
+        registry.register::<Account>();
+        registry.register::<Capability>();
+        registry.register::<Presence>();
+        registry.register::<Contact>();
+        registry.register::<Collection>();
+        registry.register::<Group>();
+        registry.register::<GroupMember>();
+        registry.register::<Thread>();
+        registry.register::<ThreadMessage>();
+        registry.register::<Delegation>();
+        registry.register::<MediaLink>();
+        registry.register::<WebLink>();
+        registry.register::<ChatState>();
+        registry.register::<Location>();
+        registry.register::<Select>();
+        registry.register::<DocumentContainer>();
+        registry
+    }
+
+    /// Registers `T` for its media type, replacing any previous document.
+    pub fn register<T: Document + Any + Send>(&mut self) {
+        self.decoders.insert(T::media_type().to_owned(), decode::<T>);
+    }
+
+    pub fn is_registered(&self, mime_type: &MediaType) -> bool {
+        self.decoders.contains_key(&mime_type.essence())
+    }
+
+    /// Decodes the content with the document registered for `mime_type`,
+    /// unknown media types are handed back untouched.
+    pub fn decode(&self, mime_type: &MediaType, content: &Content)
+            -> Result<Decoded, DocumentError> {
+        match self.decoders.get(&mime_type.essence()) {
+            Some(decoder) => decoder(content).map(Decoded::Document),
+            None => Ok(Decoded::Raw(content.clone())),
+        }
+    }
+}
+
+impl Default for DocumentRegistry {
+    fn default() -> Self {
+        DocumentRegistry::new()
+    }
+}
@@ -1,7 +1,33 @@
-use serde::{Deserialize, Deserializer};
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
 use serde::de::{Visitor, Error as DeError};
 
-use envelope::{ErrReason};
+use serde_json::{self, Value};
+
+use envelope::{ErrReason, JsonMap, MediaType};
+
+/// Json media types may hold any value, all others must hold a string.
+pub fn content_matches(mime_type: &MediaType, content: &Value) -> bool {
+    mime_type.is_json() || content.is_string()
+}
+
+/// Writes `value`, which must serialize to an object, with the `extras` of
+/// its envelope added to it. Fields of the envelope win over extras.
+pub fn serialize_with_extras<T, S>(value: &T, extras: &JsonMap,
+                                   serializer: &mut S)
+        -> Result<(), S::Error>
+    where T: Serialize, S: Serializer
+{
+    if extras.is_empty() { return value.serialize(serializer); }
+    let mut value = serde_json::to_value(value);
+    if let Value::Object(ref mut map) = value {
+        for (key, extra) in extras {
+            if !map.contains_key(key) {
+                map.insert(key.clone(), extra.clone());
+            }
+        }
+    }
+    value.serialize(serializer)
+}
 
 /// Private helper that reflects the structure of the JSON.
 /// Notification event
@@ -19,9 +45,10 @@ pub enum NotificationEventHelper {
 use envelope::notification::NotificationEvent;
 
 pub fn into_event(helper: NotificationEventHelper,
-                  reason: Option<ErrReason>) -> NotificationEvent {
+                  reason: Option<ErrReason>)
+        -> Result<NotificationEvent, &'static str> {
     use envelope::notification::NotificationEvent::*;
-    match (helper, reason) {
+    Ok(match (helper, reason) {
         (NotificationEventHelper::Accepted, None) => Accepted,
         (NotificationEventHelper::Validated, None) => Validated,
         (NotificationEventHelper::Authorized, None) => Authorized,
@@ -29,13 +56,11 @@ pub fn into_event(helper: NotificationEventHelper,
         (NotificationEventHelper::Received, None) => Received,
         (NotificationEventHelper::Consumed, None) => Consumed,
         (NotificationEventHelper::Failed, Some(reason)) => Failed(reason),
-        (NotificationEventHelper::Failed, None) => {
-            unimplemented!()
-        },
-        (_, Some(_)) => {
-            unimplemented!()
-        }
-    }
+        (NotificationEventHelper::Failed, None) =>
+            return Err("A failed notification must have a reason"),
+        (_, Some(_)) =>
+            return Err("Only failed notifications may have a reason"),
+    })
 }
 
 #[derive(Serialize, Deserialize)]
@@ -47,21 +72,18 @@ pub enum CommandStatusHelper {
 use envelope::command::CommandStatus;
 
 pub fn into_status(helper: Option<CommandStatusHelper>,
-                   reason: Option<ErrReason>) -> Option<CommandStatus> {
+                   reason: Option<ErrReason>)
+        -> Result<Option<CommandStatus>, &'static str> {
     use envelope::command::CommandStatus::*;
-    match (helper, reason) {
+    Ok(match (helper, reason) {
         (Some(CommandStatusHelper::Success), None) => Some(Success),
         (Some(CommandStatusHelper::Failure), Some(rsn)) => Some(Failure(rsn)),
-        (_, Some(_)) => {
-            panic!("Invalid Envelope Format: Non-Failure cmd \
-                    response with ErrReason.")
-        },
-        (Some(CommandStatusHelper::Failure), None) => {
-            panic!("Invalid Envelope Format: Failed cmd \
-                    response with no ErrReason given.")
-        },
-        (None, _) => None, // TODO: Some(reason)?
-    }
+        (Some(CommandStatusHelper::Failure), None) =>
+            return Err("A failed command must have a reason"),
+        (_, Some(_)) =>
+            return Err("Only failed commands may have a reason"),
+        (None, None) => None,
+    })
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -78,9 +100,10 @@ pub enum SessionStateHelper {
 use envelope::session::SessionState;
 
 pub fn into_state(helper: SessionStateHelper,
-                  reason: Option<ErrReason>) -> SessionState {
+                  reason: Option<ErrReason>)
+        -> Result<SessionState, &'static str> {
     use envelope::session::SessionState::*;
-    match (helper, reason) {
+    Ok(match (helper, reason) {
         (SessionStateHelper::New, None) => New,
         (SessionStateHelper::Negotiating, None) => Negotiating,
         (SessionStateHelper::Authenticating, None) => Authenticating,
@@ -88,8 +111,11 @@ pub fn into_state(helper: SessionStateHelper,
         (SessionStateHelper::Finishing, None) => Finishing,
         (SessionStateHelper::Finished, None) => Finished,
         (SessionStateHelper::Failed, Some(rsn)) => Failed(rsn),
-        (_, _) => panic!("No reason given for failure or vise-versa"),
-    }
+        (SessionStateHelper::Failed, None) =>
+            return Err("A failed session must have a reason"),
+        (_, Some(_)) =>
+            return Err("Only failed sessions may have a reason"),
+    })
 }
 
 /// Contains all known fields an Envelope can contain.
@@ -104,18 +130,51 @@ pub enum FieldHelper {
     Encryption,
     Compression,
     Scheme,
+    Authentication,
     EncryptionOptions,
     CompressionOptions,
     SchemeOptions,
     // Extra (sometimes unique) fields 
     Type,
     Uri,
+    Resource,
     Reason,
     Status,
     // Handle unknown fields
     Other(String)
 }
 
+impl<'a> From<&'a str> for FieldHelper {
+    fn from(value: &'a str) -> FieldHelper {
+        use self::FieldHelper::*;
+        match value {
+            "to" => To,
+            "from" => From,
+            "pp" => Pp,
+            "id" => Id,
+            "metadata" => Metadata,
+            "content" => Content,   // Message
+            "event" => Event,       // Notification
+            "method" => Method,     // Command
+            "state" => State,       // Session
+            "encryption" => Encryption,
+            "compression" => Compression,
+            "scheme" => Scheme,
+            "authentication" => Authentication,
+            "encryptionOptions" => EncryptionOptions,
+            "compressionOptions" => CompressionOptions,
+            "schemeOptions" => SchemeOptions,
+            "type" => Type,
+            "uri" => Uri,
+            "resource" => Resource,
+            "reason" => Reason,
+            "status" => Status,
+
+            _ => Other(value.to_owned()),
+        }
+    }
+}
+
 impl Deserialize for FieldHelper {
     fn deserialize<D>(deserializer: &mut D) -> Result<FieldHelper, D::Error>
         where D: Deserializer,
@@ -128,30 +187,7 @@ impl Deserialize for FieldHelper {
             fn visit_str<E>(&mut self, value: &str) -> Result<FieldHelper, E>
                 where E: DeError,
             {
-                use self::FieldHelper::*;
-                Ok(match value {
-                    "to" => To,
-                    "from" => From,
-                    "pp" => Pp,
-                    "id" => Id,
-                    "metadata" => Metadata,
-                    "content" => Content,   // Message
-                    "event" => Event,       // Notification
-                    "method" => Method,     // Command
-                    "state" => State,       // Session
-                    "encryption" => Encryption,
-                    "compression" => Compression,
-                    "scheme" => Scheme,
-                    "encryptionOptions" => EncryptionOptions,
-                    "compressionOptions" => CompressionOptions,
-                    "schemeOptions" => SchemeOptions,
-                    "type" => Type,
-                    "uri" => Uri,
-                    "reason" => Reason,
-                    "status" => Status,
-
-                    _ => Other(value.to_owned()),
-                })
+                Ok(FieldHelper::from(value))
             }
         }
 
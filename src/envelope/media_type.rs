@@ -0,0 +1,168 @@
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use serde::de::{Visitor, Error as DeError};
+
+/// Parsed `type` field of messages and commands, ex.
+/// `application/vnd.lime.account+json` or `text/plain; charset=utf-8`.
+///
+/// Media types are compared by their parsed fields, but written as they
+/// were parsed from as long as those fields weren't changed, so forwarded
+/// envelopes keep the `type` their sender wrote.
+#[derive(Debug, Clone)]
+pub struct MediaType {
+    pub top: String,
+    pub subtype: String,
+    pub suffix: Option<String>,
+    pub parameters: Vec<(String, String)>,
+    original: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct MediaTypeError(pub String);
+
+impl MediaType {
+    pub fn parse(value: &str) -> Result<MediaType, MediaTypeError> {
+        let invalid = || {
+            MediaTypeError(format!("Invalid media type '{}'", value))
+        };
+
+        let mut parts = value.split(';');
+        let essence = parts.next().unwrap().trim().to_lowercase();
+        let (top, subtype) = match essence.find('/') {
+            Some(index) => (&essence[..index], &essence[index + 1..]),
+            None => return Err(invalid()),
+        };
+        if top.is_empty() || subtype.is_empty() || subtype.contains('/') {
+            return Err(invalid());
+        }
+        let (subtype, suffix) = match subtype.rfind('+') {
+            Some(index) => (&subtype[..index],
+                            Some(subtype[index + 1..].to_owned())),
+            None => (subtype, None),
+        };
+
+        let mut parameters = Vec::new();
+        for param in parts.map(str::trim).filter(|p| !p.is_empty()) {
+            let index = param.find('=').ok_or_else(&invalid)?;
+            let name = param[..index].trim().to_lowercase();
+            let value = param[index + 1..].trim().trim_matches('"');
+            parameters.push((name, value.to_owned()));
+        }
+
+        Ok(MediaType {
+            top: top.to_owned(),
+            subtype: subtype.to_owned(),
+            suffix: suffix,
+            parameters: parameters,
+            original: Some(value.to_owned()),
+        })
+    }
+
+    /// The value it was parsed from, unless its fields were changed since.
+    fn original(&self) -> Option<&str> {
+        match self.original {
+            Some(ref original) if MediaType::parse(original)
+                    .map_or(false, |parsed| parsed == *self) =>
+                Some(&**original),
+            _ => None,
+        }
+    }
+
+    /// The media type without its parameters, ex. `text/plain`.
+    pub fn essence(&self) -> String {
+        match self.suffix {
+            Some(ref suffix) =>
+                format!("{}/{}+{}", self.top, self.subtype, suffix),
+            None => format!("{}/{}", self.top, self.subtype),
+        }
+    }
+
+    pub fn parameter(&self, name: &str) -> Option<&str> {
+        self.parameters.iter()
+            .find(|&&(ref key, _)| key == name)
+            .map(|&(_, ref value)| &**value)
+    }
+
+    /// Json media types carry their content as a json value, every other
+    /// type carries it as a plain string.
+    pub fn is_json(&self) -> bool {
+        self.suffix.as_ref().map_or(false, |s| s == "json") ||
+            (self.top == "application" && self.subtype == "json")
+    }
+}
+
+impl FromStr for MediaType {
+    type Err = MediaTypeError;
+
+    fn from_str(s: &str) -> Result<MediaType, MediaTypeError> {
+        MediaType::parse(s)
+    }
+}
+
+impl fmt::Display for MediaType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.essence())?;
+        for &(ref name, ref value) in &self.parameters {
+            write!(f, "; {}={}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+impl PartialEq for MediaType {
+    fn eq(&self, other: &MediaType) -> bool {
+        self.top == other.top && self.subtype == other.subtype &&
+            self.suffix == other.suffix && self.parameters == other.parameters
+    }
+}
+
+impl Eq for MediaType {}
+
+impl Hash for MediaType {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.top.hash(state);
+        self.subtype.hash(state);
+        self.suffix.hash(state);
+        self.parameters.hash(state);
+    }
+}
+
+impl<'a> PartialEq<&'a str> for MediaType {
+    fn eq(&self, other: &&'a str) -> bool {
+        MediaType::parse(other).map_or(false, |other| *self == other)
+    }
+}
+
+impl Serialize for MediaType {
+    fn serialize<S>(&self, serializer: &mut S) -> Result<(), S::Error>
+        where S: Serializer
+    {
+        match self.original() {
+            Some(original) => serializer.serialize_str(original),
+            None => serializer.serialize_str(&self.to_string()),
+        }
+    }
+}
+
+impl Deserialize for MediaType {
+    fn deserialize<D>(deserializer: &mut D) -> Result<MediaType, D::Error>
+        where D: Deserializer,
+    {
+        struct MediaTypeVisitor;
+
+        impl Visitor for MediaTypeVisitor {
+            type Value = MediaType;
+
+            fn visit_str<E>(&mut self, value: &str) -> Result<MediaType, E>
+                where E: DeError,
+            {
+                MediaType::parse(value).map_err(|err| E::custom(err.0))
+            }
+        }
+
+        deserializer.deserialize_str(MediaTypeVisitor)
+    }
+}
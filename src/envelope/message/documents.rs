@@ -0,0 +1,150 @@
+//! The standard LIME documents carried by messages.
+
+use serde_json::Value;
+
+use envelope::{Content, MediaType};
+use envelope::document::{Document, DocumentError};
+
+pub use envelope::resources::{Collection, COLLECTION_MIME};
+
+pub static MEDIA_LINK_MIME: &'static str =
+    "application/vnd.lime.media-link+json";
+pub static WEB_LINK_MIME: &'static str = "application/vnd.lime.web-link+json";
+pub static CHAT_STATE_MIME: &'static str =
+    "application/vnd.lime.chatstate+json";
+pub static LOCATION_MIME: &'static str = "application/vnd.lime.location+json";
+pub static SELECT_MIME: &'static str = "application/vnd.lime.select+json";
+pub static CONTAINER_MIME: &'static str =
+    "application/vnd.lime.container+json";
+
+/// A link to a media file, such as an image or a video.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MediaLink {
+    #[serde(rename="type")]
+    pub mime_type: MediaType,
+    pub uri: String,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(rename="previewUri", skip_serializing_if="Option::is_none")]
+    pub preview_uri: Option<String>,
+    #[serde(rename="previewType", skip_serializing_if="Option::is_none")]
+    pub preview_type: Option<MediaType>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub text: Option<String>,
+}
+
+/// A link to a web page.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WebLink {
+    pub uri: String,
+    #[serde(rename="previewUri", skip_serializing_if="Option::is_none")]
+    pub preview_uri: Option<String>,
+    #[serde(rename="previewType", skip_serializing_if="Option::is_none")]
+    pub preview_type: Option<MediaType>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub text: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum State {
+    #[serde(rename="starting")]     Starting,
+    #[serde(rename="composing")]    Composing,
+    #[serde(rename="paused")]       Paused,
+    #[serde(rename="deleting")]     Deleting,
+    #[serde(rename="gone")]         Gone,
+}
+
+/// What the other side of a conversation is currently doing.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatState {
+    pub state: State,
+}
+
+/// A geographic position.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Location {
+    pub latitude: f64,
+    pub longitude: f64,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub altitude: Option<f64>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub accuracy: Option<f64>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub course: Option<f64>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub speed: Option<f64>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub text: Option<String>,
+}
+
+/// One of the choices of a `Select`, `value` is sent back when chosen.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SelectOption {
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub order: Option<u32>,
+    pub text: String,
+    #[serde(rename="type", skip_serializing_if="Option::is_none")]
+    pub mime_type: Option<MediaType>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub value: Option<Value>,
+}
+
+/// A question offering a list of options to pick from.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Select {
+    pub text: String,
+    pub options: Vec<SelectOption>,
+}
+
+/// Wraps any document along with its media type, used to hold documents of
+/// different types in a single `Collection`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DocumentContainer {
+    #[serde(rename="type")]
+    pub mime_type: MediaType,
+    pub value: Content,
+}
+
+impl DocumentContainer {
+    pub fn new<T: Document>(doc: &T) -> Self {
+        DocumentContainer {
+            mime_type: MediaType::parse(T::media_type())
+                .expect("Invalid document media type"),
+            value: doc.to_content(),
+        }
+    }
+
+    pub fn document_as<T: Document>(&self) -> Result<T, DocumentError> {
+        ::envelope::document::content_as(&self.mime_type, &self.value)
+    }
+}
+
+impl Collection {
+    /// A collection of documents which all have the same type.
+    pub fn of<T: Document>(docs: &[T]) -> Self {
+        Collection::new(T::media_type(),
+                        docs.iter().map(Document::to_content).collect())
+    }
+
+    /// Reads every item as `T`, failing if the collection holds another type.
+    pub fn items_as<T: Document>(&self) -> Result<Vec<T>, DocumentError> {
+        if self.item_type != T::media_type() {
+            return Err(DocumentError::MediaType {
+                expected: T::media_type().to_owned(),
+                found: self.item_type.clone(),
+            });
+        }
+        self.items.iter().map(T::from_content).collect()
+    }
+}
+
+impl_Document!(MediaLink, MEDIA_LINK_MIME);
+impl_Document!(WebLink, WEB_LINK_MIME);
+impl_Document!(ChatState, CHAT_STATE_MIME);
+impl_Document!(Location, LOCATION_MIME);
+impl_Document!(Select, SELECT_MIME);
+impl_Document!(DocumentContainer, CONTAINER_MIME);
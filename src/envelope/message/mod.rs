@@ -1,25 +1,80 @@
+use std::str::FromStr;
+
 use serde_json::{ Value };
 
-use envelope::{JsonMap, Node, MsgID};
+use envelope::{JsonMap, Metadata, Node, MsgID, MediaType, is_group};
+use envelope::document::{self, Decoded, Document, DocumentError,
+    DocumentRegistry};
 
 mod ser;
+pub mod documents;
+
+pub use self::documents::*;
 
 pub type Content = Value;
 
-#[derive(Debug, Serialize)]
+pub use envelope::metadata::KIND_KEY;
+
+#[derive(Debug, Clone)]
 pub struct Message {
     pub to: Option<Node>,
     pub from: Option<Node>,
     pub pp: Option<Node>,
     pub id: Option<MsgID>,
-    pub metadata: Option<JsonMap>,
+    pub metadata: Option<Metadata>,
 
-    pub mime_type: String,
+    pub mime_type: MediaType,
     pub content: Content,
+
+    /// Fields unknown to the protocol, see `ParsePolicy::Lenient`.
+    pub extras: JsonMap,
 }
 
 impl Message {
+    /// Creates a message holding the given document.
+    pub fn with_document<T: Document>(to: Option<Node>, doc: &T) -> Self {
+        Message {
+            to: to,
+            from: None,
+            pp: None,
+            id: None,
+            metadata: None,
+
+            mime_type: MediaType::parse(T::media_type())
+                .expect("Invalid document media type"),
+            content: doc.to_content(),
+
+            extras: JsonMap::new(),
+        }
+    }
+
+    /// Reads the content as `T`, failing if the message declares another
+    /// media type, ex. `msg.content_as::<PlainText>()`.
+    pub fn content_as<T: Document>(&self) -> Result<T, DocumentError> {
+        document::content_as(&self.mime_type, &self.content)
+    }
+
+    /// Resolves what kind of message this is. The `#message.kind` metadata
+    /// wins if present, otherwise the kind follows from the destination:
+    /// groups get group chats, identities get chats and anything else (ex. a
+    /// domain or no destination at all) is a normal message.
+    pub fn kind(&self) -> MessageType {
+        let declared = self.metadata.as_ref().and_then(Metadata::kind);
+        if let Some(kind) = declared { return kind; }
 
+        match self.to {
+            Some(ref to) if is_group(to) => MessageType::Groupchat,
+            Some(ref to) if to.contains('@') => MessageType::Chat,
+            _ => MessageType::Normal,
+        }
+    }
+
+    /// Reads the content with whichever document is registered for the
+    /// message's media type.
+    pub fn document(&self, registry: &DocumentRegistry)
+            -> Result<Decoded, DocumentError> {
+        registry.decode(&self.mime_type, &self.content)
+    }
 }
 
 // TODO : Import this
@@ -30,10 +85,41 @@ impl Message {
                //"content");
 
 /// TODO: Figure out other possible message types.
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum MessageType {
     Normal,
     Chat,
     Groupchat,
+    /// Reports a problem with a previous message, never notified about.
     Error
 }
 
+#[derive(Debug, PartialEq)]
+pub struct MessageTypeError(pub String);
+
+impl MessageType {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            MessageType::Normal => "normal",
+            MessageType::Chat => "chat",
+            MessageType::Groupchat => "groupchat",
+            MessageType::Error => "error",
+        }
+    }
+}
+
+impl FromStr for MessageType {
+    type Err = MessageTypeError;
+
+    fn from_str(kind: &str) -> Result<MessageType, MessageTypeError> {
+        match kind {
+            "normal" => Ok(MessageType::Normal),
+            "chat" => Ok(MessageType::Chat),
+            "groupchat" => Ok(MessageType::Groupchat),
+            "error" => Ok(MessageType::Error),
+            _ => Err(MessageTypeError(
+                format!("Unknown message type '{}'", kind))),
+        }
+    }
+}
+
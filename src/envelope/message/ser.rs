@@ -0,0 +1,45 @@
+use serde::ser::{Serialize, Serializer};
+use envelope::{Metadata, MsgID, MediaType, Content};
+use envelope::helper::serialize_with_extras;
+use envelope::message::*;
+
+impl Serialize for Message {
+    fn serialize<S>(&self, serializer: &mut S) -> Result<(), S::Error>
+        where S: Serializer
+    {
+        /// Private helper that reflects the structure of the output JSON.
+        /// Messages are the most common envelope, so unset fields are left
+        /// out rather than sent as `null`.
+        #[derive(Serialize)]
+        struct MessageHelper<'a> {
+            #[serde(skip_serializing_if="Option::is_none")]
+            to: Option<&'a str>,
+            #[serde(skip_serializing_if="Option::is_none")]
+            from: Option<&'a str>,
+            #[serde(skip_serializing_if="Option::is_none")]
+            pp: Option<&'a str>,
+            #[serde(skip_serializing_if="Option::is_none")]
+            id: Option<&'a MsgID>,
+            #[serde(skip_serializing_if="Option::is_none")]
+            metadata: Option<&'a Metadata>,
+
+            #[serde(rename="type")]
+            mime_type: &'a MediaType,
+            content: &'a Content,
+        }
+
+        let helper = MessageHelper {
+            to: self.to.as_ref().map(|s| &**s),
+            from: self.from.as_ref().map(|s| &**s),
+            pp: self.pp.as_ref().map(|s| &**s),
+            id: self.id.as_ref(),
+            metadata: self.metadata.as_ref().and_then(|meta| {
+                if meta.is_empty() { None } else { Some(meta) }
+            }),
+
+            mime_type: &self.mime_type,
+            content: &self.content,
+        };
+        serialize_with_extras(&helper, &self.extras, serializer)
+    }
+}
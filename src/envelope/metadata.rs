@@ -0,0 +1,198 @@
+//! Metadata carried by every envelope.
+//!
+//! The protocol only allows string values. Keys starting with `#` are
+//! reserved for the protocol and the server, the well-known ones have typed
+//! accessors and any other key is kept as is, so that envelopes forwarded by
+//! the server don't lose what clients put in there.
+//!
+//! Only `#message.replaceVariables` and `#envelope.timestamp` come from the
+//! protocol. `#message.kind`, `#envelope.originalId`, `#trace.id` and the
+//! `#group.*` keys are extensions of this server: other nodes forward them
+//! like any unknown key, so clients can't expect them from other servers.
+
+use std::fmt;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+
+use envelope::{JsonMap, MsgID, Node, TimeStamp};
+use envelope::message::MessageType;
+
+/// Forces the kind of a message, see `Message::kind`. Extension.
+pub static KIND_KEY: &'static str = "#message.kind";
+/// Asks the server to replace `${variables}` in the content of a message.
+pub static REPLACE_VARIABLES_KEY: &'static str = "#message.replaceVariables";
+/// Id of the envelope this one was derived from, ex. by a forward.
+/// Extension.
+pub static ORIGINAL_ID_KEY: &'static str = "#envelope.originalId";
+/// Identifies the trace an envelope belongs to, across nodes. Extension.
+pub static TRACE_ID_KEY: &'static str = "#trace.id";
+/// When the server received the envelope, see `TimeStamp`.
+pub static TIMESTAMP_KEY: &'static str = "#envelope.timestamp";
+/// Notification metadata with the number of group members reached.
+/// Extension.
+pub static GROUP_DISPATCHED_KEY: &'static str = "#group.dispatched";
+/// Notification metadata with the group members which weren't reached.
+/// Extension.
+pub static GROUP_FAILED_KEY: &'static str = "#group.failed";
+
+/// A metadata value which isn't a string.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetadataError {
+    pub key: String,
+}
+
+impl fmt::Display for MetadataError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "The metadata value of '{}' must be a string", self.key)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metadata {
+    map: JsonMap,
+}
+
+impl Metadata {
+    pub fn new() -> Self {
+        Metadata::default()
+    }
+
+    /// The value of `key`, if it is a string.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.map.get(key).and_then(Value::as_str)
+    }
+
+    pub fn insert<K, V>(&mut self, key: K, value: V) -> Option<Value>
+        where K: Into<String>, V: Into<String>
+    {
+        self.map.insert(key.into(), Value::String(value.into()))
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<Value> {
+        self.map.remove(key)
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.map.contains_key(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Every entry, including those whose value isn't a string.
+    pub fn as_map(&self) -> &JsonMap {
+        &self.map
+    }
+
+    /// Fails on the first value which isn't a string, as required by the
+    /// protocol. Such values are kept when received, the setters never
+    /// produce them.
+    pub fn validate(&self) -> Result<(), MetadataError> {
+        match self.map.iter().find(|&(_, value)| !value.is_string()) {
+            Some((key, _)) => Err(MetadataError { key: key.clone() }),
+            None => Ok(()),
+        }
+    }
+
+    pub fn kind(&self) -> Option<MessageType> {
+        self.get(KIND_KEY).and_then(|kind| kind.parse().ok())
+    }
+
+    pub fn set_kind(&mut self, kind: MessageType) {
+        self.insert(KIND_KEY, kind.as_str());
+    }
+
+    pub fn replace_variables(&self) -> bool {
+        self.get(REPLACE_VARIABLES_KEY) == Some("true")
+    }
+
+    pub fn set_replace_variables(&mut self, replace: bool) {
+        self.insert(REPLACE_VARIABLES_KEY, replace.to_string());
+    }
+
+    pub fn original_id(&self) -> Option<MsgID> {
+        self.get(ORIGINAL_ID_KEY).and_then(|id| id.parse().ok())
+    }
+
+    pub fn set_original_id(&mut self, id: MsgID) {
+        self.insert(ORIGINAL_ID_KEY, id.to_string());
+    }
+
+    pub fn trace_id(&self) -> Option<&str> {
+        self.get(TRACE_ID_KEY)
+    }
+
+    pub fn set_trace_id<T: Into<String>>(&mut self, trace: T) {
+        self.insert(TRACE_ID_KEY, trace);
+    }
+
+    /// When the server received the envelope, `None` if it is missing or
+    /// not a valid timestamp.
+    pub fn timestamp(&self) -> Option<TimeStamp> {
+        self.get(TIMESTAMP_KEY).and_then(|time| time.parse().ok())
+    }
+
+    pub fn set_timestamp(&mut self, timestamp: TimeStamp) {
+        self.insert(TIMESTAMP_KEY, timestamp.to_string());
+    }
+
+    /// Sets the timestamp of any envelope, ex.
+    /// `Metadata::stamp(&mut msg.metadata, TimeStamp::now())`, replacing
+    /// the one claimed by the sender if any.
+    pub fn stamp(metadata: &mut Option<Metadata>, timestamp: TimeStamp) {
+        if metadata.is_none() {
+            *metadata = Some(Metadata::new());
+        }
+        metadata.as_mut().unwrap().set_timestamp(timestamp);
+    }
+
+    pub fn group_dispatched(&self) -> Option<usize> {
+        self.get(GROUP_DISPATCHED_KEY).and_then(|count| count.parse().ok())
+    }
+
+    pub fn set_group_dispatched(&mut self, count: usize) {
+        self.insert(GROUP_DISPATCHED_KEY, count.to_string());
+    }
+
+    /// The members of the group which weren't reached, comma separated.
+    pub fn group_failed(&self) -> Vec<Node> {
+        self.get(GROUP_FAILED_KEY)
+            .map(|failed| failed.split(',')
+                 .filter(|member| !member.is_empty())
+                 .map(str::to_owned)
+                 .collect())
+            .unwrap_or_else(Vec::new)
+    }
+
+    pub fn set_group_failed(&mut self, members: &[Node]) {
+        self.insert(GROUP_FAILED_KEY, members.join(","));
+    }
+}
+
+impl From<JsonMap> for Metadata {
+    fn from(map: JsonMap) -> Self {
+        Metadata { map: map }
+    }
+}
+
+impl Serialize for Metadata {
+    fn serialize<S>(&self, serializer: &mut S) -> Result<(), S::Error>
+        where S: Serializer
+    {
+        self.map.serialize(serializer)
+    }
+}
+
+impl Deserialize for Metadata {
+    fn deserialize<D>(deserializer: &mut D) -> Result<Self, D::Error>
+        where D: Deserializer
+    {
+        JsonMap::deserialize(deserializer).map(Metadata::from)
+    }
+}
@@ -2,11 +2,15 @@ use serde_json::{ Map, Value };
 
 /// -- Global Constants --
 pub static DELIMITER : u8 = b'\n' as u8;
-type JsonMap = Map<String, Value>;
+/// Nodes whose domain starts with this are groups, ex. `bb@groups.lime.com`.
+pub static GROUP_DOMAIN_PREFIX: &'static str = "groups.";
+pub type JsonMap = Map<String, Value>;
 
 // Envelope types
 #[macro_use]
 pub mod macros;
+#[macro_use]
+pub mod document;
 
 pub mod message;
 pub mod notification;
@@ -16,12 +20,19 @@ pub mod session;
 // Types heald by envelopes
 pub mod reason;
 pub mod resources;
+pub mod media_type;
+pub mod metadata;
+pub mod timestamp;
+pub mod raw;
 
 mod ser;
 mod codec;
 mod helper;
 
-pub use self::codec::{LimeCodec, EnvelopeStream};
+pub use self::codec::{LimeCodec, CborCodec, EnvelopeCodec, Encoding,
+    EnvelopeStream};
+pub use self::ser::{ParsePolicy, StrictEnvelope, from_slice};
+pub use self::raw::{RawEnvelope, RawCodec};
 
 pub use self::message::{Message, Content};
 pub use self::notification::{Notification, NotificationEvent};
@@ -29,16 +40,56 @@ pub use self::command::Command;
 pub use self::session::*;
 
 pub use self::reason::Reason as ErrReason;
+pub use self::media_type::MediaType;
+pub use self::metadata::Metadata;
+pub use self::timestamp::TimeStamp;
+pub use self::document::{Document, DocumentRegistry, DocumentError};
 
 pub type Node = String;
 pub type UserID = String;
 pub type Resources = Value;
 pub type MsgID = u64;
-pub type TimeStamp = u64;
+
+/// Strips the instance from a node, ex. `ww@breakingbad.com/home` becomes
+/// `ww@breakingbad.com`.
+pub fn identity(node: &str) -> &str {
+    match node.find('/') {
+        Some(index) => &node[..index],
+        None => node,
+    }
+}
+
+/// The domain of a node, ex. `ww@breakingbad.com/home` gives `breakingbad.com`.
+pub fn domain(node: &str) -> &str {
+    let id = identity(node);
+    match id.find('@') {
+        Some(index) => &id[index + 1..],
+        None => id,
+    }
+}
+
+pub fn is_group(node: &str) -> bool {
+    domain(node).starts_with(GROUP_DOMAIN_PREFIX)
+}
 
 /// Known / supported types of envelopes.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum EnvelopeType {
-    Message, Notification, Command, Session
+    #[serde(rename="message")]      Message,
+    #[serde(rename="notification")] Notification,
+    #[serde(rename="command")]      Command,
+    #[serde(rename="session")]      Session,
+}
+
+impl EnvelopeType {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            EnvelopeType::Message => "message",
+            EnvelopeType::Notification => "notification",
+            EnvelopeType::Command => "command",
+            EnvelopeType::Session => "session",
+        }
+    }
 }
 
 /// Outlines the kinds of envelopes one can receive.
@@ -53,6 +104,34 @@ pub enum Envelope {
 }
 
 impl Envelope {
+    pub fn from(&self) -> Option<&Node> {
+        use self::Envelope::*;
+        match *self {
+            Message(ref val) => val.from.as_ref(),
+            Notification(ref val) => val.from.as_ref(),
+            Command(ref val) => val.from.as_ref(),
+            Session(ref val) => val.from.as_ref(),
+            Unknown(_) => None,
+        }
+    }
+
+    pub fn pp(&self) -> Option<&Node> {
+        use self::Envelope::*;
+        match *self {
+            Message(ref val) => val.pp.as_ref(),
+            Notification(ref val) => val.pp.as_ref(),
+            Command(ref val) => val.pp.as_ref(),
+            Session(ref val) => val.pp.as_ref(),
+            Unknown(_) => None,
+        }
+    }
+
+    /// The node which actually sent the envelope, which is the `pp` for
+    /// envelopes sent on behalf of the node in `from`.
+    pub fn sender(&self) -> Option<&Node> {
+        self.pp().or_else(|| self.from())
+    }
+
     pub fn id(&self) -> Option<MsgID> {
         use self::Envelope::*;
         match *self {
@@ -1,22 +1,40 @@
-use envelope::{ErrReason, JsonMap, Node, MsgID};
+use envelope::{ErrReason, JsonMap, Metadata, Node, MsgID};
 
 mod ser;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Notification {
     pub to: Option<Node>,
     pub from: Option<Node>,
     pub pp: Option<Node>,
     pub id: MsgID,
-    pub metadata: Option<JsonMap>,
+    pub metadata: Option<Metadata>,
 
     pub event: NotificationEvent,
+
+    /// Fields unknown to the protocol, see `ParsePolicy::Lenient`.
+    pub extras: JsonMap,
+}
+
+impl Notification {
+    /// Notification for the envelope with the given id, addressed to `to`.
+    pub fn new(id: MsgID, to: Option<Node>, event: NotificationEvent) -> Self {
+        Notification {
+            to: to,
+            from: None,
+            pp: None,
+            id: id,
+            metadata: None,
+            event: event,
+            extras: JsonMap::new(),
+        }
+    }
 }
 
 /// Signifies the event which pertains to a previously dealt with message.
 /// Uses 'id' from sent message to determine which one should happen.
 /// TODO: Unique set of 'id's per user or nah?
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum NotificationEvent {
     Accepted,
     Validated,
@@ -1,7 +1,7 @@
 // Thanks to github user 'dtolnay' for help with the following code...
 use serde::ser::{Serialize, Serializer};
-use envelope::{JsonMap, ErrReason, MsgID};
-use envelope::helper::NotificationEventHelper;
+use envelope::{Metadata, ErrReason, MsgID};
+use envelope::helper::{NotificationEventHelper, serialize_with_extras};
 use envelope::notification::*;
 
 impl Serialize for Notification {
@@ -16,7 +16,7 @@ impl Serialize for Notification {
             from: Option<&'a str>,
             pp: Option<&'a str>,
             id: &'a MsgID,
-            metadata: Option<&'a JsonMap>,
+            metadata: Option<&'a Metadata>,
 
             event: NotificationEventHelper,
             reason: Option<&'a ErrReason>,
@@ -34,7 +34,7 @@ impl Serialize for Notification {
             NotificationEvent::Failed(ref reason) => (Failed, Some(reason)),
         };
 
-        NotificationHelper {
+        let helper = NotificationHelper {
             to: self.to.as_ref().map(|s| &**s),
             from: self.from.as_ref().map(|s| &**s),
             pp: self.pp.as_ref().map(|s| &**s),
@@ -43,6 +43,7 @@ impl Serialize for Notification {
 
             event: event,
             reason: reason,
-        }.serialize(serializer)
+        };
+        serialize_with_extras(&helper, &self.extras, serializer)
     }
 }
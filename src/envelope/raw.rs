@@ -0,0 +1,170 @@
+//! Lazy decoding, for nodes which only route envelopes.
+//!
+//! Only the headers the router looks at are parsed, `content`, `resource`
+//! and `metadata` are skipped without being built and the envelope is
+//! forwarded as the bytes it was received as.
+
+use std::io;
+
+use serde::{Deserialize, Deserializer};
+use serde::de::{Visitor, MapVisitor, Error as DeError};
+use serde::de::impls::IgnoredAny;
+use serde_json;
+use tokio_core::io::{Codec, EasyBuf};
+
+use envelope::{Envelope, EnvelopeType, MsgID, Node, DELIMITER};
+
+/// What routing needs to know about an envelope.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Headers {
+    pub kind: EnvelopeType,
+    pub id: Option<MsgID>,
+    pub to: Option<Node>,
+    pub from: Option<Node>,
+    pub pp: Option<Node>,
+}
+
+impl Headers {
+    /// See `Envelope::sender`.
+    pub fn sender(&self) -> Option<&Node> {
+        self.pp.as_ref().or(self.from.as_ref())
+    }
+}
+
+impl Deserialize for Headers {
+    fn deserialize<D>(deserializer: &mut D) -> Result<Self, D::Error>
+        where D: Deserializer
+    {
+        struct HeadersVisitor;
+
+        impl Visitor for HeadersVisitor {
+            type Value = Headers;
+
+            fn visit_map<V>(&mut self, mut vis: V) -> Result<Headers, V::Error>
+                where V: MapVisitor,
+            {
+                let mut kind = None;
+                let mut id = None;
+                let mut to = None;
+                let mut from = None;
+                let mut pp = None;
+
+                use envelope::helper::FieldHelper::*;
+                while let Some(field) = vis.visit_key()? {
+                    let found = match field {
+                        To => { to = vis.visit_value()?; continue; }
+                        From => { from = vis.visit_value()?; continue; }
+                        Pp => { pp = vis.visit_value()?; continue; }
+                        Id => { id = vis.visit_value()?; continue; }
+                        Content => EnvelopeType::Message,
+                        Event => EnvelopeType::Notification,
+                        Method => EnvelopeType::Command,
+                        State => EnvelopeType::Session,
+                        _ => {
+                            vis.visit_value::<IgnoredAny>()?;
+                            continue;
+                        }
+                    };
+                    vis.visit_value::<IgnoredAny>()?;
+                    if kind.is_some() && kind != Some(found) {
+                        return Err(V::Error::custom(
+                            "The envelope has fields of several kinds"));
+                    }
+                    kind = Some(found);
+                }
+                vis.end()?;
+
+                let kind = match kind {
+                    Some(kind) => kind,
+                    None => return Err(V::Error::custom(
+                        "The kind of the envelope is unknown")),
+                };
+                Ok(Headers {
+                    kind: kind,
+                    id: id,
+                    to: to,
+                    from: from,
+                    pp: pp,
+                })
+            }
+        }
+
+        deserializer.deserialize_map(HeadersVisitor)
+    }
+}
+
+/// An envelope as received, with its headers parsed.
+///
+/// It is forwarded exactly as received, so none of what the `Dispatcher`
+/// sets on the envelopes it routes is: not the `from` of the sending
+/// session, the `pp` of a delegation nor the server's timestamp. Only links
+/// between nodes trusting each other to have set them should forward raw
+/// envelopes, the others `decode` them first.
+#[derive(Debug, Clone)]
+pub struct RawEnvelope {
+    pub headers: Headers,
+    bytes: EasyBuf,
+}
+
+impl RawEnvelope {
+    /// Parses the headers of the json envelope in `bytes`, which must not
+    /// include the delimiter.
+    pub fn parse(bytes: EasyBuf) -> Result<Self, serde_json::Error> {
+        let headers = serde_json::from_slice(bytes.as_slice())?;
+        Ok(RawEnvelope {
+            headers: headers,
+            bytes: bytes,
+        })
+    }
+
+    /// The json of the envelope, exactly as received.
+    pub fn as_bytes(&self) -> &[u8] {
+        self.bytes.as_slice()
+    }
+
+    /// The part of the buffer it was received in holding the envelope.
+    pub fn into_buf(self) -> EasyBuf {
+        self.bytes
+    }
+
+    /// Builds the whole envelope, for the nodes which handle it.
+    pub fn decode(&self) -> Result<Envelope, serde_json::Error> {
+        serde_json::from_slice(self.as_bytes())
+    }
+}
+
+impl PartialEq for RawEnvelope {
+    fn eq(&self, other: &RawEnvelope) -> bool {
+        self.headers == other.headers && self.as_bytes() == other.as_bytes()
+    }
+}
+
+/// Same frames as `LimeCodec`, decoded lazily into `RawEnvelope`s.
+pub struct RawCodec;
+
+impl Codec for RawCodec {
+    type In = RawEnvelope;
+    type Out = RawEnvelope;
+
+    fn decode(&mut self, buf: &mut EasyBuf)
+            -> Result<Option<Self::In>, io::Error> {
+        match buf.as_slice().iter().position(|&b| b == DELIMITER) {
+            Some(index) => {
+                // The envelope keeps its part of the buffer, without the
+                // delimiter, instead of a copy.
+                let bytes = buf.drain_to(index + 1).drain_to(index);
+                RawEnvelope::parse(bytes).map(Some).map_err(|_| {
+                    io::Error::new(io::ErrorKind::Other,
+                                   "Failed to decode object")
+                })
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn encode(&mut self, msg: Self::Out, buf: &mut Vec<u8>) -> io::Result<()> {
+        buf.extend_from_slice(msg.as_bytes());
+        buf.push(DELIMITER);
+        Ok(())
+    }
+}
@@ -1,18 +1,40 @@
 
 /// When an Error occurs, this will exist.
 /// TODO: Use this for other structs aside from just Notification.
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Reason {
     pub code: ReasonCode,
     pub description: Option<String>
 }
 
+impl Reason {
+    pub fn new(code: ReasonCode, description: &str) -> Reason {
+        Reason {
+            code: code,
+            description: Some(description.to_owned()),
+        }
+    }
+}
+
 // TODO : Complete this
 enum_number!(
 ReasonCode {
     GeneralError = 0,
     SessionRegistrationError = 12,
     SessionAuthenticationFailed = 13,
+    SessionInvalidActionForState = 15,
+    SessionNegotiationInvalidOptions = 17,
+    ValidationError = 21,
+    AuthorizationUnauthorizedSender = 31,
+    AuthorizationQuotaThresholdExceeded = 33,
+    RoutingDestinationNotFound = 41,
+    CommandProcessingError = 60,
+    CommandResourceNotSupported = 61,
+    CommandMethodNotSupported = 62,
+    CommandInvalidArgument = 63,
+    CommandNotAllowed = 65,
+    CommandResourceNotFound = 66,
+    MessageUnsupportedType = 71,
 });
 
 // TODO: Implement this.
@@ -22,4 +44,3 @@ ReasonCode {
         //from_.unwrap_or(ReasonCode::GeneralError)
     //}
 //}
-
@@ -0,0 +1,24 @@
+use serde_json::Value;
+
+pub static COLLECTION_MIME: &'static str =
+    "application/vnd.lime.collection+json";
+
+/// A list of resources of the same type, returned by `get` commands on
+/// container uris such as `/contacts`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Collection {
+    pub total: usize,
+    #[serde(rename="itemType")]
+    pub item_type: String,
+    pub items: Vec<Value>,
+}
+
+impl Collection {
+    pub fn new(item_type: &str, items: Vec<Value>) -> Self {
+        Collection {
+            total: items.len(),
+            item_type: item_type.to_owned(),
+            items: items,
+        }
+    }
+}
@@ -0,0 +1,32 @@
+use envelope::UserID;
+
+pub static CONTACT_MIME: &'static str = "application/vnd.lime.contact+json";
+
+/// An entry of a user's roster.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Contact {
+    pub identity: UserID,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub name: Option<String>,
+    /// Set until the contact adds the owner of the roster back.
+    #[serde(rename="isPending", skip_serializing_if="Option::is_none")]
+    pub is_pending: Option<bool>,
+    #[serde(rename="shareAccountInfo", skip_serializing_if="Option::is_none")]
+    pub share_account_info: Option<bool>,
+    #[serde(rename="sharePresence", skip_serializing_if="Option::is_none")]
+    pub share_presence: Option<bool>,
+}
+
+impl Contact {
+    /// Whether the owner of the roster shares its presence with the
+    /// contact, which is the default.
+    pub fn shares_presence(&self) -> bool {
+        self.share_presence.unwrap_or(true)
+    }
+
+    /// Whether the owner of the roster shares its account with the contact,
+    /// which is the default.
+    pub fn shares_account_info(&self) -> bool {
+        self.share_account_info.unwrap_or(true)
+    }
+}
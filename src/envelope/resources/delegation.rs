@@ -0,0 +1,22 @@
+use envelope::{EnvelopeType, Node};
+
+pub static DELEGATION_MIME: &'static str =
+    "application/vnd.lime.delegation+json";
+
+/// Allows `target` to send envelopes with the identity of the owner in
+/// `from`, ex. a bot answering messages for a user.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Delegation {
+    /// The identity, or a single instance, the delegation is given to.
+    pub target: Node,
+    /// The envelopes the target may send, every type if empty.
+    #[serde(rename="envelopeTypes", default,
+            skip_serializing_if="Vec::is_empty")]
+    pub envelope_types: Vec<EnvelopeType>,
+}
+
+impl Delegation {
+    pub fn allows(&self, kind: EnvelopeType) -> bool {
+        self.envelope_types.is_empty() || self.envelope_types.contains(&kind)
+    }
+}
@@ -0,0 +1,43 @@
+use envelope::Node;
+
+pub static GROUP_MIME: &'static str = "application/vnd.lime.group+json";
+pub static GROUP_MEMBER_MIME: &'static str =
+    "application/vnd.lime.groupmember+json";
+
+/// A group address, messages sent to it are delivered to every member.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Group {
+    /// Address of the group, its domain must start with `groups.`.
+    pub identity: Node,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub owner: Option<Node>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum GroupRole {
+    #[serde(rename="member")]       Member,
+    #[serde(rename="moderator")]    Moderator,
+    #[serde(rename="owner")]        Owner,
+}
+
+impl GroupRole {
+    /// Whether the role allows adding and removing members.
+    pub fn can_manage(&self) -> bool {
+        *self != GroupRole::Member
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GroupMember {
+    pub address: Node,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub role: Option<GroupRole>,
+}
+
+impl GroupMember {
+    pub fn role(&self) -> GroupRole {
+        self.role.unwrap_or(GroupRole::Member)
+    }
+}
@@ -1,31 +1,123 @@
 
 pub mod uri;
+pub mod presence;
+pub mod contact;
+pub mod collection;
+pub mod group;
+pub mod thread;
+pub mod delegation;
+
+pub use self::presence::{Presence, PresenceStatus, RoutingRule, PRESENCE_MIME};
+pub use self::contact::{Contact, CONTACT_MIME};
+pub use self::collection::{Collection, COLLECTION_MIME};
+pub use self::group::{Group, GroupMember, GroupRole, GROUP_MIME,
+    GROUP_MEMBER_MIME};
+pub use self::thread::{Direction, Thread, ThreadMessage, THREAD_MIME,
+    THREAD_MESSAGE_MIME};
+pub use self::delegation::{Delegation, DELEGATION_MIME};
+
+pub static ACCOUNT_MIME: &'static str = "application/vnd.lime.account+json";
+pub static CAPABILITY_MIME: &'static str =
+    "application/vnd.lime.capability+json";
 
 pub enum Resource {
     Account(Account),
     Capability(Capability),
+    Presence(Presence),
+    Contact(Contact),
+    Group(Group),
 }
 
 /// Represents the user information as a series of options.
-#[derive(Debug)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct Account {
-    full_name: Option<String>,
-    address: Option<String>,
-    city: Option<String>,
-    email: Option<String>,
-    phone_number: Option<String>,
-    photo_uri: Option<String>,
-    cell_phone_number: Option<String>,
-    is_temporary: Option<bool>,
-    password: Option<String>,
-    old_password: Option<String>,
-    inbox_size: Option<String>,
-    allow_guest_sender: Option<bool>,
-    allow_unknown_sender: Option<bool>,
-    store_message_content: Option<bool>,
+    #[serde(rename="fullName", skip_serializing_if="Option::is_none")]
+    pub full_name: Option<String>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub address: Option<String>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub city: Option<String>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub email: Option<String>,
+    #[serde(rename="phoneNumber", skip_serializing_if="Option::is_none")]
+    pub phone_number: Option<String>,
+    #[serde(rename="photoUri", skip_serializing_if="Option::is_none")]
+    pub photo_uri: Option<String>,
+    #[serde(rename="cellPhoneNumber", skip_serializing_if="Option::is_none")]
+    pub cell_phone_number: Option<String>,
+    #[serde(rename="isTemporary", skip_serializing_if="Option::is_none")]
+    pub is_temporary: Option<bool>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub password: Option<String>,
+    #[serde(rename="oldPassword", skip_serializing_if="Option::is_none")]
+    pub old_password: Option<String>,
+    #[serde(rename="inboxSize", skip_serializing_if="Option::is_none")]
+    pub inbox_size: Option<u32>,
+    #[serde(rename="allowGuestSender", skip_serializing_if="Option::is_none")]
+    pub allow_guest_sender: Option<bool>,
+    #[serde(rename="allowUnknownSender", skip_serializing_if="Option::is_none")]
+    pub allow_unknown_sender: Option<bool>,
+    #[serde(rename="storeMessageContent",
+            skip_serializing_if="Option::is_none")]
+    pub store_message_content: Option<bool>,
+}
+
+impl Account {
+    /// Overwrites every field which is set in `other`, leaving the rest.
+    /// Password fields are never merged, they are handled separately.
+    pub fn merge(&mut self, other: Account) {
+        macro_rules! merge {
+            ($($field:ident),*) => {
+                $( if other.$field.is_some() { self.$field = other.$field; } )*
+            }
+        }
+        merge!(full_name, address, city, email, phone_number, photo_uri,
+               cell_phone_number, is_temporary, inbox_size,
+               allow_guest_sender, allow_unknown_sender,
+               store_message_content);
+    }
 }
 
+/// The content and resource types a node is able to handle, an empty list
+/// means anything is accepted.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct Capability {
+    #[serde(rename="contentTypes", default)]
+    pub content_types: Vec<String>,
+    #[serde(rename="resourceTypes", default)]
+    pub resource_types: Vec<String>,
+}
+
+impl Capability {
+    pub fn supports_content(&self, mime_type: &str) -> bool {
+        supports(&self.content_types, mime_type)
+    }
 
+    pub fn supports_resource(&self, mime_type: &str) -> bool {
+        supports(&self.resource_types, mime_type)
+    }
+
+    /// The types supported by both nodes, used to answer a negotiation.
+    pub fn intersect(&self, other: &Capability) -> Capability {
+        fn common(ours: &[String], theirs: &[String]) -> Vec<String> {
+            if ours.is_empty() { return theirs.to_vec(); }
+            theirs.iter().filter(|t| supports(ours, t)).cloned().collect()
+        }
+        Capability {
+            content_types: common(&self.content_types, &other.content_types),
+            resource_types: common(&self.resource_types, &other.resource_types),
+        }
+    }
 }
 
+/// Matches a media type against a list, `*/*` and `text/*` style wildcards
+/// included. Parameters such as `; charset=utf-8` are ignored.
+fn supports(types: &[String], mime_type: &str) -> bool {
+    if types.is_empty() { return true; }
+    let mime_type = mime_type.split(';').next().unwrap().trim();
+    types.iter().any(|t| {
+        let t = t.split(';').next().unwrap().trim();
+        t == "*/*" || t == mime_type || (t.ends_with("/*") &&
+            mime_type.starts_with(&t[..t.len() - 1]))
+    })
+}
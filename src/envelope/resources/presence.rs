@@ -0,0 +1,55 @@
+use envelope::Node;
+
+pub static PRESENCE_MIME: &'static str = "application/vnd.lime.presence+json";
+
+/// Availability of a node, also used to decide whether it is routable.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum PresenceStatus {
+    #[serde(rename="unavailable")]  Unavailable,
+    #[serde(rename="available")]    Available,
+    #[serde(rename="busy")]         Busy,
+    #[serde(rename="away")]         Away,
+    #[serde(rename="invisible")]    Invisible,
+}
+
+/// Decides which envelopes are delivered to an instance of an identity.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum RoutingRule {
+    /// Only envelopes addressed to the instance itself.
+    #[serde(rename="instance")]     Instance,
+    /// Also envelopes addressed to the identity, without an instance.
+    #[serde(rename="identity")]     Identity,
+    /// Also envelopes addressed to any other instance of the identity.
+    #[serde(rename="promiscuous")]  Promiscuous,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Presence {
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub status: Option<PresenceStatus>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub message: Option<String>,
+    #[serde(rename="routingRule", skip_serializing_if="Option::is_none")]
+    pub routing_rule: Option<RoutingRule>,
+    #[serde(rename="lastSeen", skip_serializing_if="Option::is_none")]
+    pub last_seen: Option<String>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub priority: Option<i32>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub instances: Option<Vec<Node>>,
+}
+
+impl Presence {
+    /// Whether the node wants envelopes at all, `invisible` nodes still do.
+    pub fn is_routable(&self) -> bool {
+        match self.status {
+            Some(PresenceStatus::Unavailable) | None => false,
+            Some(_) => true,
+        }
+    }
+
+    /// Nodes which never set a routing rule only get their own envelopes.
+    pub fn routing_rule(&self) -> RoutingRule {
+        self.routing_rule.unwrap_or(RoutingRule::Instance)
+    }
+}
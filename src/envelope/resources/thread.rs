@@ -0,0 +1,39 @@
+use envelope::{Content, MediaType, MsgID, Node, TimeStamp};
+
+pub static THREAD_MIME: &'static str = "application/vnd.lime.thread+json";
+pub static THREAD_MESSAGE_MIME: &'static str =
+    "application/vnd.lime.threadmessage+json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Direction {
+    #[serde(rename="sent")]     Sent,
+    #[serde(rename="received")] Received,
+}
+
+/// A message of a conversation, as seen by one of its two participants.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ThreadMessage {
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub id: Option<MsgID>,
+    /// Position of the message in the thread, from 1, assigned by the server
+    /// in the order it stored them. Pages of the thread are requested with
+    /// it since clients may reuse their ids.
+    #[serde(default)]
+    pub sequence: u64,
+    pub direction: Direction,
+    #[serde(rename="type")]
+    pub mime_type: MediaType,
+    pub content: Content,
+    /// When the server received the message.
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub date: Option<TimeStamp>,
+}
+
+/// Summary of the conversation with another identity.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Thread {
+    pub identity: Node,
+    pub total: usize,
+    #[serde(rename="lastMessage")]
+    pub last_message: ThreadMessage,
+}
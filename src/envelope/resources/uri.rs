@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+
+/// Values captured from the `{name}` segments of a `UriTemplate`.
+pub type UriParams = HashMap<String, String>;
+
+/// A parsed LIME resource uri, ex. `lime://domain.com/contacts?$take=10`.
+///
+/// Only the path and query are kept, the scheme and authority are dropped
+/// since commands are always handled by the node they are addressed to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Uri {
+    pub path: String,
+    pub query: HashMap<String, String>,
+}
+
+impl Uri {
+    pub fn parse(uri: &str) -> Uri {
+        let rest = match uri.find("://") {
+            Some(index) => {
+                let rest = &uri[index + 3..];
+                match rest.find('/') {
+                    Some(index) => &rest[index..],
+                    None => "/",
+                }
+            }
+            None => uri,
+        };
+
+        let (path, query) = match rest.find('?') {
+            Some(index) => (&rest[..index], &rest[index + 1..]),
+            None => (rest, ""),
+        };
+
+        let query = query.split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| match pair.find('=') {
+                Some(index) =>
+                    (pair[..index].to_owned(), pair[index + 1..].to_owned()),
+                None => (pair.to_owned(), String::new()),
+            })
+            .collect();
+
+        Uri {
+            path: path.to_owned(),
+            query: query,
+        }
+    }
+
+    /// Path segments, ignoring leading, trailing and repeated slashes.
+    pub fn segments(&self) -> Vec<&str> {
+        self.path.split('/').filter(|s| !s.is_empty()).collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    Param(String),
+}
+
+/// Pattern used to match command uris, ex. `/contacts/{identity}`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UriTemplate {
+    template: String,
+    segments: Vec<Segment>,
+}
+
+impl UriTemplate {
+    pub fn new(template: &str) -> UriTemplate {
+        let segments = template.split('/')
+            .filter(|s| !s.is_empty())
+            .map(|s| {
+                if s.starts_with('{') && s.ends_with('}') {
+                    Segment::Param(s[1..s.len() - 1].to_owned())
+                } else {
+                    Segment::Literal(s.to_owned())
+                }
+            })
+            .collect();
+
+        UriTemplate {
+            template: template.to_owned(),
+            segments: segments,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.template
+    }
+
+    /// Returns the captured parameters if the uri matches this template.
+    pub fn matches(&self, uri: &Uri) -> Option<UriParams> {
+        let segments = uri.segments();
+        if segments.len() != self.segments.len() { return None; }
+
+        let mut params = UriParams::new();
+        for (segment, value) in self.segments.iter().zip(segments) {
+            match *segment {
+                Segment::Literal(ref lit) => if lit != value { return None; },
+                Segment::Param(ref name) => {
+                    params.insert(name.clone(), value.to_owned());
+                }
+            }
+        }
+        Some(params)
+    }
+}
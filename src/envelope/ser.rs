@@ -1,12 +1,16 @@
 // SerDe section
 
 use serde::{Serialize, Serializer, Deserialize, Deserializer};
-use serde::de::{Visitor, MapVisitor};
-use serde_json::Map;
+use serde::de::{Visitor, MapVisitor, Error as DeError};
+use serde_json::{self, Map, Value};
 
 use envelope::{
     Envelope,
+    EnvelopeType,
+    JsonMap,
     Message,
+    MediaType,
+    Metadata,
     Notification,
     Command,
     Session, 
@@ -14,138 +18,282 @@ use envelope::{
 
 use envelope::helper::*;
 
-/// Deserialization implementation distinguishes the specific type of 'frame'
-/// being received.
-impl Deserialize for Envelope {
-    fn deserialize<D>(deserializer: &mut D) -> Result<Self, D::Error>
-        where D: Deserializer
+/// How envelopes which don't follow the protocol to the letter are parsed.
+/// Envelopes missing a required field, holding a value of the wrong type or
+/// mixing the fields of several kinds of envelopes are always rejected.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParsePolicy {
+    /// Also rejects unknown fields, fields of another kind of envelope,
+    /// repeated fields and metadata values which aren't strings, for
+    /// conformance testing.
+    Strict,
+    /// Keeps unknown fields and fields of another kind of envelope in the
+    /// `extras` of the envelope, which are written back when it is
+    /// forwarded. The last value of a repeated field wins. Envelopes of an
+    /// unknown kind are parsed as `Envelope::Unknown`.
+    Lenient,
+}
+
+impl Default for ParsePolicy {
+    fn default() -> Self {
+        ParsePolicy::Lenient
+    }
+}
+
+/// An envelope parsed with `ParsePolicy::Strict`, envelopes are otherwise
+/// parsed leniently.
+pub struct StrictEnvelope(pub Envelope);
+
+/// Parses the json envelope in `bytes` with the given policy.
+pub fn from_slice(bytes: &[u8], policy: ParsePolicy)
+        -> Result<Envelope, serde_json::Error> {
+    match policy {
+        ParsePolicy::Strict => serde_json::from_slice(bytes)
+            .map(|StrictEnvelope(envelope)| envelope),
+        ParsePolicy::Lenient => serde_json::from_slice(bytes),
+    }
+}
+
+static COMMON_FIELDS: &'static [&'static str] =
+    &["to", "from", "pp", "id", "metadata"];
+static MESSAGE_FIELDS: &'static [&'static str] = &["type", "content"];
+static NOTIFICATION_FIELDS: &'static [&'static str] = &["event", "reason"];
+static COMMAND_FIELDS: &'static [&'static str] =
+    &["method", "status", "reason", "uri", "type", "resource"];
+static SESSION_FIELDS: &'static [&'static str] =
+    &["state", "reason", "encryption", "compression", "scheme",
+      "authentication", "encryptionOptions", "compressionOptions",
+      "schemeOptions"];
+
+/// Whether the field belongs to envelopes of this kind.
+fn is_field_of(kind: EnvelopeType, field: &str) -> bool {
+    let fields = match kind {
+        EnvelopeType::Message => MESSAGE_FIELDS,
+        EnvelopeType::Notification => NOTIFICATION_FIELDS,
+        EnvelopeType::Command => COMMAND_FIELDS,
+        EnvelopeType::Session => SESSION_FIELDS,
+    };
+    COMMON_FIELDS.contains(&field) || fields.contains(&field)
+}
+
+/// Removes the field and reads it as `T`, `null` counts as missing.
+fn take<T, E>(fields: &mut JsonMap, name: &str) -> Result<Option<T>, E>
+    where T: Deserialize, E: DeError
+{
+    match fields.remove(name) {
+        Some(value) => serde_json::from_value(value).map_err(|err| {
+            E::custom(format!("Invalid field '{}': {}", name, err))
+        }),
+        None => Ok(None),
+    }
+}
+
+fn required<T, E>(value: Option<T>, name: &str, kind: EnvelopeType)
+        -> Result<T, E>
+    where E: DeError
+{
+    value.ok_or_else(|| {
+        E::custom(format!("Missing field '{}' in a {}", name, kind.as_str()))
+    })
+}
+
+struct EnvelopeVisitor {
+    policy: ParsePolicy,
+}
+
+impl Visitor for EnvelopeVisitor {
+    type Value = Envelope;
+
+    fn visit_map<V>(&mut self, mut vis: V) -> Result<Envelope, V::Error>
+        where V: MapVisitor,
     {
-        struct EnvelopeVisitor;
-
-        impl Visitor for EnvelopeVisitor {
-            type Value = Envelope;
-
-            fn visit_map<V>(&mut self, mut vis: V)
-                            -> Result<Envelope, V::Error>
-                where V: MapVisitor,
-            {
-                let mut to          = None;
-                let mut from        = None;
-                let mut pp          = None;
-                let mut id          = None;
-                let mut metadata    = None;
-
-                let mut content     = None;
-                let mut event       = None;
-                let mut method      = None;
-                let mut state       = None;
-
-                let mut status      = None;
-                let mut encryption  = None;
-                let mut compression = None;
-                let mut scheme      = None;
-                let mut e_options   = None;
-                let mut c_options   = None;
-                let mut s_options   = None;
-
-                let mut mime_type   = None;
-                let mut uri         = None;
-                let mut reason      = None;
-                let mut other       = Map::new();
-
-                use envelope::helper::FieldHelper::*;
-                while let Some(field) = vis.visit_key()? {
-                    match field {
-                        To => to = Some(vis.visit_value()?),
-                        From => from = Some(vis.visit_value()?),
-                        Pp => pp = Some(vis.visit_value()?),
-                        Id => id = Some(vis.visit_value()?),
-                        Metadata => metadata = Some(vis.visit_value()?),
-
-                        Content => content = Some(vis.visit_value()?),
-                        Event => event = Some(vis.visit_value()?),
-                        Method => method = Some(vis.visit_value()?),
-                        State => state = Some(vis.visit_value()?),
-
-                        Encryption => encryption = Some(vis.visit_value()?),
-                        Compression => compression = Some(vis.visit_value()?),
-                        Scheme => scheme = Some(vis.visit_value()?),
-                        EncryptionOptions => e_options = Some(vis.visit_value()?),
-                        CompressionOptions => c_options = Some(vis.visit_value()?),
-                        SchemeOptions => s_options = Some(vis.visit_value()?),
-
-                        Type => mime_type = Some(vis.visit_value()?),
-                        Uri => uri = Some(vis.visit_value()?),
-                        Reason => reason = Some(vis.visit_value()?),
-                        Status => status = Some(vis.visit_value()?),
-                        Other(key) => {
-                            other.insert(key, vis.visit_value()?);
-                        }
-                    }
+        let strict = self.policy == ParsePolicy::Strict;
+        let mut fields = Map::new();
+        let mut extras = Map::new();
+        let mut kind = None;
+
+        while let Some(key) = vis.visit_key::<String>()? {
+            let value: Value = vis.visit_value()?;
+            if strict && (fields.contains_key(&key) ||
+                          extras.contains_key(&key)) {
+                return Err(V::Error::custom(
+                    format!("Duplicate field '{}'", key)));
+            }
+            let found = match FieldHelper::from(&*key) {
+                FieldHelper::Other(_) if strict => {
+                    return Err(V::Error::custom(
+                        format!("Unknown field '{}'", key)));
                 }
-                vis.end()?;
-
-                // TODO: Match all fields which are at some point required.
-                Ok(match (content, event, method, state, id, mime_type) {
-                    (Some(content), None, None, None, id, Some(mime_type)) => {
-                        Envelope::Message(Message {
-                            to: to,
-                            from: from,
-                            pp: pp,
-                            id: id,
-                            metadata: metadata,
-                            mime_type: mime_type,
-                            content: content,
-                        })
-                    }
-                    (None, Some(event), None, None, Some(id), None) => {
-                        let event = into_event(event, reason);
-                        Envelope::Notification(Notification {
-                            to: to,
-                            from: from,
-                            pp: pp,
-                            id: id,
-                            metadata: metadata,
-                            event: event,
-                        })
-                    }
-                    (None, None, Some(method), None, id, mime_type) => {
-                        let status = into_status(status, reason);
-                        Envelope::Command(Command {
-                            to: to,
-                            from: from,
-                            pp: pp,
-                            id: id,
-                            metadata: metadata,
-                            mime_type: mime_type,
-                            method: method,
-                            status: status,
-                            uri: uri,
-                        })
-                    }
-                    (None, None, None, Some(state), Some(id), None) => {
-                        let state = into_state(state, reason);
-                        Envelope::Session(Session {
-                            to: to,
-                            from: from,
-                            pp: pp,
-                            id: id,
-                            metadata: metadata,
-                            state: state,
-                            encryption_options: e_options,
-                            compression_options: c_options,
-                            scheme_options: s_options,
-                            encryption: encryption,
-                            compression: compression,
-                            scheme: scheme,
-                        })
+                FieldHelper::Other(_) => {
+                    extras.insert(key, value);
+                    continue;
+                }
+                FieldHelper::Content => EnvelopeType::Message,
+                FieldHelper::Event => EnvelopeType::Notification,
+                FieldHelper::Method => EnvelopeType::Command,
+                FieldHelper::State => EnvelopeType::Session,
+                _ => {
+                    fields.insert(key, value);
+                    continue;
+                }
+            };
+            if kind.is_some() && kind != Some(found) {
+                return Err(V::Error::custom(
+                    "The envelope has fields of several kinds"));
+            }
+            kind = Some(found);
+            fields.insert(key, value);
+        }
+        vis.end()?;
+
+        let kind = match kind {
+            Some(kind) => kind,
+            // Kept whole, with any field the envelope has, so that it can
+            // still be forwarded or told apart by its id.
+            None if !strict => {
+                extras.extend(fields);
+                return Ok(Envelope::Unknown(extras));
+            }
+            None => return Err(V::Error::custom(
+                "The kind of the envelope is unknown")),
+        };
+        let stray: Vec<String> = fields.keys()
+            .filter(|field| !is_field_of(kind, field))
+            .cloned()
+            .collect();
+        for field in stray {
+            if strict {
+                return Err(V::Error::custom(format!(
+                    "Field '{}' is not allowed in a {}",
+                    field, kind.as_str())));
+            }
+            let value = fields.remove(&field).unwrap();
+            extras.insert(field, value);
+        }
+
+        let to = take(&mut fields, "to")?;
+        let from = take(&mut fields, "from")?;
+        let pp = take(&mut fields, "pp")?;
+        let id = take(&mut fields, "id")?;
+        let metadata: Option<Metadata> = take(&mut fields, "metadata")?;
+        if let Some(ref metadata) = metadata {
+            if strict {
+                metadata.validate()
+                    .map_err(|err| V::Error::custom(err.to_string()))?;
+            }
+        }
+        let reason = take(&mut fields, "reason")?;
+
+        Ok(match kind {
+            EnvelopeType::Message => {
+                let mime_type: MediaType =
+                    required(take(&mut fields, "type")?, "type", kind)?;
+                let content =
+                    required(take(&mut fields, "content")?, "content", kind)?;
+                if !content_matches(&mime_type, &content) {
+                    return Err(V::Error::custom(format!(
+                        "Content does not match type '{}'", mime_type)));
+                }
+                Envelope::Message(Message {
+                    to: to,
+                    from: from,
+                    pp: pp,
+                    id: id,
+                    metadata: metadata,
+                    mime_type: mime_type,
+                    content: content,
+                    extras: extras,
+                })
+            }
+            EnvelopeType::Notification => {
+                let event =
+                    required(take(&mut fields, "event")?, "event", kind)?;
+                Envelope::Notification(Notification {
+                    to: to,
+                    from: from,
+                    pp: pp,
+                    id: required(id, "id", kind)?,
+                    metadata: metadata,
+                    event: into_event(event, reason)
+                        .map_err(V::Error::custom)?,
+                    extras: extras,
+                })
+            }
+            EnvelopeType::Command => {
+                let method =
+                    required(take(&mut fields, "method")?, "method", kind)?;
+                let mime_type: Option<MediaType> =
+                    take(&mut fields, "type")?;
+                let resource: Option<Value> = take(&mut fields, "resource")?;
+                match (mime_type.as_ref(), resource.as_ref()) {
+                    (Some(mime_type), Some(resource))
+                        if !content_matches(mime_type, resource) => {
+                        return Err(V::Error::custom(format!(
+                            "Resource does not match type '{}'",
+                            mime_type)));
                     }
-                    _ => unimplemented!(), // take care of this at some point
+                    _ => (),
+                }
+                Envelope::Command(Command {
+                    to: to,
+                    from: from,
+                    pp: pp,
+                    id: id,
+                    metadata: metadata,
+                    mime_type: mime_type,
+                    method: method,
+                    status: into_status(take(&mut fields, "status")?, reason)
+                        .map_err(V::Error::custom)?,
+                    uri: take(&mut fields, "uri")?,
+                    resource: resource,
+                    extras: extras,
                 })
             }
-        }
+            EnvelopeType::Session => {
+                let state =
+                    required(take(&mut fields, "state")?, "state", kind)?;
+                Envelope::Session(Session {
+                    to: to,
+                    from: from,
+                    pp: pp,
+                    id: required(id, "id", kind)?,
+                    metadata: metadata,
+                    state: into_state(state, reason)
+                        .map_err(V::Error::custom)?,
+                    encryption_options:
+                        take(&mut fields, "encryptionOptions")?,
+                    compression_options:
+                        take(&mut fields, "compressionOptions")?,
+                    scheme_options: take(&mut fields, "schemeOptions")?,
+                    encryption: take(&mut fields, "encryption")?,
+                    compression: take(&mut fields, "compression")?,
+                    scheme: take(&mut fields, "scheme")?,
+                    authentication: take(&mut fields, "authentication")?,
+                    extras: extras,
+                })
+            }
+        })
+    }
+}
 
-        deserializer.deserialize_map(EnvelopeVisitor)
+/// Deserialization distinguishes the specific type of 'frame' being
+/// received, with `ParsePolicy::Lenient`.
+impl Deserialize for Envelope {
+    fn deserialize<D>(deserializer: &mut D) -> Result<Self, D::Error>
+        where D: Deserializer
+    {
+        deserializer.deserialize_map(
+            EnvelopeVisitor { policy: ParsePolicy::Lenient })
+    }
+}
+
+impl Deserialize for StrictEnvelope {
+    fn deserialize<D>(deserializer: &mut D) -> Result<Self, D::Error>
+        where D: Deserializer
+    {
+        deserializer.deserialize_map(
+            EnvelopeVisitor { policy: ParsePolicy::Strict })
+            .map(StrictEnvelope)
     }
 }
 
@@ -157,11 +305,9 @@ impl Serialize for Envelope {
         match *self {
             Message(ref val)      => val.serialize(serializer),
             Notification(ref val) => val.serialize(serializer),
-            //Command(ref val)        => val.serialize(serializer),
-            //SessionReq(ref val)     => val.serialize(serializer),
-            //SessionRes(ref val)     => val.serialize(serializer),
-            //Unknown(ref val)        => val.serialize(serializer),
-            _ => unimplemented!()
+            Command(ref val)      => val.serialize(serializer),
+            Session(ref val)      => val.serialize(serializer),
+            Unknown(ref val)      => val.serialize(serializer),
         }
     }
 }
@@ -1,6 +1,8 @@
+use std::str::FromStr;
+
 use serde_json::{ Value };
 
-use envelope::{ErrReason, JsonMap, Node, MsgID};
+use envelope::{ErrReason, JsonMap, Metadata, Node, MsgID};
 
 // TODO: How to parse the session? seems real complicated currently. 
 
@@ -13,7 +15,7 @@ pub struct Session {
     pub from: Option<Node>, // mandatory for clients during auth
     pub pp: Option<Node>,
     pub id: MsgID,
-    pub metadata: Option<JsonMap>,
+    pub metadata: Option<Metadata>,
 
     pub state: SessionState,
 
@@ -24,21 +26,121 @@ pub struct Session {
     pub encryption: Option<String>,
     pub compression: Option<String>,
     pub scheme: Option<Value>,
+    /// Credentials for the `scheme`, `{ "password": <base64> }` for `plain`.
+    pub authentication: Option<Value>,
+
+    /// Fields unknown to the protocol, see `ParsePolicy::Lenient`.
+    pub extras: JsonMap,
+}
+
+impl Session {
+    /// A session envelope in `state`, without any of the options.
+    pub fn new(id: MsgID, state: SessionState) -> Self {
+        Session {
+            to: None,
+            from: None,
+            pp: None,
+            id: id,
+            metadata: None,
+            state: state,
+            encryption_options: None,
+            compression_options: None,
+            scheme_options: None,
+            encryption: None,
+            compression: None,
+            scheme: None,
+            authentication: None,
+            extras: JsonMap::new(),
+        }
+    }
 }
 
+/// An option of the negotiation the server doesn't know.
+#[derive(Debug, PartialEq)]
+pub struct UnknownOption(pub String);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum EncryptionOptions {
     #[serde(rename="none")] Nil,
     #[serde(rename="tls")]  Tls,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum CompressionOptions {
     #[serde(rename="none")] Nil,
     #[serde(rename="gzip")] GZip,
 }
 
+impl EncryptionOptions {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            EncryptionOptions::Nil => "none",
+            EncryptionOptions::Tls => "tls",
+        }
+    }
+}
+
+impl FromStr for EncryptionOptions {
+    type Err = UnknownOption;
+
+    fn from_str(s: &str) -> Result<EncryptionOptions, UnknownOption> {
+        match s {
+            "none" => Ok(EncryptionOptions::Nil),
+            "tls" => Ok(EncryptionOptions::Tls),
+            _ => Err(UnknownOption(s.to_owned())),
+        }
+    }
+}
+
+impl CompressionOptions {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            CompressionOptions::Nil => "none",
+            CompressionOptions::GZip => "gzip",
+        }
+    }
+}
+
+impl FromStr for CompressionOptions {
+    type Err = UnknownOption;
+
+    fn from_str(s: &str) -> Result<CompressionOptions, UnknownOption> {
+        match s {
+            "none" => Ok(CompressionOptions::Nil),
+            "gzip" => Ok(CompressionOptions::GZip),
+            _ => Err(UnknownOption(s.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum SchemeOptions {
     #[serde(rename="guest")] Guest,
     #[serde(rename="plain")] Plain,
+    #[serde(rename="transport")] Transport,
+}
+
+impl SchemeOptions {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            SchemeOptions::Guest => "guest",
+            SchemeOptions::Plain => "plain",
+            SchemeOptions::Transport => "transport",
+        }
+    }
+}
+
+impl FromStr for SchemeOptions {
+    type Err = UnknownOption;
+
+    fn from_str(s: &str) -> Result<SchemeOptions, UnknownOption> {
+        match s {
+            "guest" => Ok(SchemeOptions::Guest),
+            "plain" => Ok(SchemeOptions::Plain),
+            "transport" => Ok(SchemeOptions::Transport),
+            _ => Err(UnknownOption(s.to_owned())),
+        }
+    }
 }
 
 #[derive(Debug, PartialEq)]
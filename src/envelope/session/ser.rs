@@ -1,6 +1,6 @@
 use serde::ser::{Serialize, Serializer};
-use envelope::{JsonMap, ErrReason, MsgID};
-use envelope::helper::SessionStateHelper;
+use envelope::{Metadata, ErrReason, MsgID};
+use envelope::helper::{SessionStateHelper, serialize_with_extras};
 use envelope::session::*;
 
 use serde_json::Value;
@@ -17,7 +17,7 @@ impl Serialize for Session {
             from: Option<&'a str>,
             pp: Option<&'a str>,
             id: &'a MsgID,
-            metadata: Option<&'a JsonMap>,
+            metadata: Option<&'a Metadata>,
 
             state: &'a SessionStateHelper,
 
@@ -25,6 +25,7 @@ impl Serialize for Session {
             encryption: Option<&'a str>,
             compression: Option<&'a str>,
             scheme: Option<&'a Value>,
+            authentication: Option<&'a Value>,
             #[serde(rename="encryptionOptions")]
             encryption_options: Option<&'a Vec<String>>,
             #[serde(rename="compressionOptions")]
@@ -45,7 +46,7 @@ impl Serialize for Session {
             SessionState::Failed(ref r)  => (Failed, Some(r)),
         };
 
-        SessionHelper {
+        let helper = SessionHelper {
             to: self.to.as_ref().map(|s| &**s),
             from: self.from.as_ref().map(|s| &**s),
             pp: self.pp.as_ref().map(|s| &**s),
@@ -61,7 +62,9 @@ impl Serialize for Session {
             encryption: self.encryption.as_ref().map(|s| &**s),
             compression: self.compression.as_ref().map(|s| &**s),
             scheme: self.scheme.as_ref(),
-        }.serialize(serializer)
+            authentication: self.authentication.as_ref(),
+        };
+        serialize_with_extras(&helper, &self.extras, serializer)
     }
 }
 
@@ -0,0 +1,221 @@
+//! Points in time as written in envelopes, ISO-8601 in UTC with
+//! milliseconds, ex. `2016-11-20T18:42:07.123Z`.
+
+use std::cmp;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use serde::de::{Visitor, Error as DeError};
+
+static SECONDS_PER_DAY: i64 = 86400;
+
+/// Milliseconds since the unix epoch, earlier times can't be represented.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TimeStamp {
+    millis: u64,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct TimeStampError(pub String);
+
+impl fmt::Display for TimeStampError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for TimeStampError {
+    fn description(&self) -> &str {
+        &self.0
+    }
+}
+
+impl TimeStamp {
+    pub fn now() -> Self {
+        TimeStamp::from(SystemTime::now())
+    }
+
+    pub fn from_millis(millis: u64) -> Self {
+        TimeStamp { millis: millis }
+    }
+
+    pub fn as_millis(&self) -> u64 {
+        self.millis
+    }
+
+    pub fn to_system_time(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(self.millis)
+    }
+
+    /// Parses `YYYY-MM-DDTHH:MM:SS`, with an optional fraction of second of
+    /// which only the milliseconds are kept, followed by `Z` or an offset
+    /// such as `+02:00`.
+    pub fn parse(value: &str) -> Result<TimeStamp, TimeStampError> {
+        let invalid = || {
+            TimeStampError(format!("Invalid timestamp '{}'", value))
+        };
+
+        let bytes = value.as_bytes();
+        if bytes.len() < 20 || bytes[4] != b'-' || bytes[7] != b'-' ||
+                (bytes[10] != b'T' && bytes[10] != b't') ||
+                bytes[13] != b':' || bytes[16] != b':' {
+            return Err(invalid());
+        }
+        let field = |start: usize, end: usize| {
+            digits(&bytes[start..end]).ok_or_else(&invalid)
+        };
+        let (year, month, day) = (field(0, 4)?, field(5, 7)?, field(8, 10)?);
+        let (hour, minute, second) =
+            (field(11, 13)?, field(14, 16)?, field(17, 19)?);
+        if month < 1 || month > 12 || day < 1 ||
+                day > days_in_month(year, month) ||
+                hour > 23 || minute > 59 || second > 59 {
+            return Err(invalid());
+        }
+
+        let mut rest = &bytes[19..];
+        let mut millis = 0;
+        if rest[0] == b'.' {
+            let len = rest[1..].iter().take_while(|&&b| is_digit(b)).count();
+            if len == 0 { return Err(invalid()); }
+            let kept = &rest[1..1 + cmp::min(len, 3)];
+            millis = digits(kept).unwrap() * 10i64.pow(3 - kept.len() as u32);
+            rest = &rest[1 + len..];
+        }
+
+        let offset = if rest == &b"Z"[..] || rest == &b"z"[..] {
+            0
+        } else if rest.len() == 6 && (rest[0] == b'+' || rest[0] == b'-') &&
+                rest[3] == b':' {
+            let hours = digits(&rest[1..3]).ok_or_else(&invalid)?;
+            let minutes = digits(&rest[4..6]).ok_or_else(&invalid)?;
+            if hours > 23 || minutes > 59 { return Err(invalid()); }
+            let offset = (hours * 60 + minutes) * 60;
+            if rest[0] == b'-' { -offset } else { offset }
+        } else {
+            return Err(invalid());
+        };
+
+        let seconds = days_from_civil(year, month, day) * SECONDS_PER_DAY +
+            hour * 3600 + minute * 60 + second - offset;
+        if seconds < 0 {
+            return Err(TimeStampError(
+                format!("The timestamp '{}' is before 1970", value)));
+        }
+        Ok(TimeStamp::from_millis(seconds as u64 * 1000 + millis as u64))
+    }
+}
+
+fn is_digit(b: u8) -> bool {
+    b >= b'0' && b <= b'9'
+}
+
+fn digits(bytes: &[u8]) -> Option<i64> {
+    if bytes.is_empty() { return None; }
+    bytes.iter().fold(Some(0), |acc, &b| match acc {
+        Some(n) if is_digit(b) => Some(n * 10 + (b - b'0') as i64),
+        _ => None,
+    })
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since the unix epoch of a date of the proleptic gregorian calendar,
+/// counting years from march so that leap days end them.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = (if year >= 0 { year } else { year - 399 }) / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 })
+                       + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 +
+        day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Inverse of `days_from_civil`, for days after the epoch.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 -
+                       day_of_era / 146096) / 365;
+    let day_of_year = day_of_era -
+        (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+impl From<SystemTime> for TimeStamp {
+    /// Times before the unix epoch are clamped to it.
+    fn from(time: SystemTime) -> Self {
+        let elapsed = time.duration_since(UNIX_EPOCH)
+            .unwrap_or_else(|_| Duration::from_secs(0));
+        TimeStamp::from_millis(elapsed.as_secs() * 1000 +
+                               elapsed.subsec_nanos() as u64 / 1_000_000)
+    }
+}
+
+impl FromStr for TimeStamp {
+    type Err = TimeStampError;
+
+    fn from_str(s: &str) -> Result<TimeStamp, TimeStampError> {
+        TimeStamp::parse(s)
+    }
+}
+
+impl fmt::Display for TimeStamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let seconds = (self.millis / 1000) as i64;
+        let (year, month, day) = civil_from_days(seconds / SECONDS_PER_DAY);
+        let time = seconds % SECONDS_PER_DAY;
+        write!(f, "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+               year, month, day, time / 3600, time % 3600 / 60, time % 60,
+               self.millis % 1000)
+    }
+}
+
+impl Serialize for TimeStamp {
+    fn serialize<S>(&self, serializer: &mut S) -> Result<(), S::Error>
+        where S: Serializer
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl Deserialize for TimeStamp {
+    fn deserialize<D>(deserializer: &mut D) -> Result<TimeStamp, D::Error>
+        where D: Deserializer,
+    {
+        struct TimeStampVisitor;
+
+        impl Visitor for TimeStampVisitor {
+            type Value = TimeStamp;
+
+            fn visit_str<E>(&mut self, value: &str) -> Result<TimeStamp, E>
+                where E: DeError,
+            {
+                TimeStamp::parse(value).map_err(|err| E::custom(err.0))
+            }
+        }
+
+        deserializer.deserialize_str(TimeStampVisitor)
+    }
+}
@@ -7,10 +7,20 @@ extern crate futures;
 extern crate tokio_core;
 extern crate tokio_service;
 extern crate tokio_proto;
+extern crate httparse;
 
 extern crate serde;
 extern crate serde_json;
 extern crate serde_urlencoded;
+extern crate serde_cbor;
+extern crate sha1;
+extern crate ring;
+extern crate rand;
+extern crate base64;
+#[cfg(unix)]
+extern crate tokio_uds;
+#[cfg(unix)]
+extern crate libc;
 #[macro_use]
 extern crate serde_derive;
 
@@ -20,6 +30,7 @@ pub mod user;
 #[macro_use]
 pub mod envelope; // protocol src
 pub mod utils;
+pub mod transport;
 
 use envelope::Envelope as Envelope;
 
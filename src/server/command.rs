@@ -0,0 +1,157 @@
+use std::sync::Arc;
+
+use envelope::{Command, ErrReason, MediaType, Node, Resources};
+use envelope::command::{CommandMethod, CommandStatus};
+use envelope::reason::ReasonCode;
+use envelope::resources::uri::{Uri, UriTemplate, UriParams};
+
+/// Everything a handler needs to know about an incoming command.
+pub struct Request<'a> {
+    /// The authenticated node which sent the command.
+    pub caller: &'a Node,
+    pub command: &'a Command,
+    pub uri: Uri,
+    pub params: UriParams,
+}
+
+/// The resource returned by a handler, `None` for commands which only
+/// return a status (ex. `set` or `delete`).
+#[derive(Debug, Default)]
+pub struct Response {
+    pub mime_type: Option<MediaType>,
+    pub resource: Option<Resources>,
+}
+
+impl Response {
+    pub fn empty() -> Response {
+        Response::default()
+    }
+
+    pub fn resource(mime_type: &str, resource: Resources) -> Response {
+        Response {
+            mime_type: Some(MediaType::parse(mime_type)
+                .expect("Invalid resource media type")),
+            resource: Some(resource),
+        }
+    }
+}
+
+pub type CommandResult = Result<Response, ErrReason>;
+
+/// Implemented by anything which can answer a command for a given resource.
+///
+/// Closures of the form `Fn(&Request) -> CommandResult` implement this
+/// already, so simple handlers don't need their own struct.
+pub trait CommandHandler: Send + Sync {
+    fn handle(&self, req: &Request) -> CommandResult;
+}
+
+impl<F> CommandHandler for F
+    where F: Fn(&Request) -> CommandResult + Send + Sync
+{
+    fn handle(&self, req: &Request) -> CommandResult {
+        self(req)
+    }
+}
+
+struct Route {
+    method: CommandMethod,
+    template: UriTemplate,
+    handler: Arc<CommandHandler>,
+}
+
+/// Dispatches commands to the handler registered for their method and uri.
+///
+/// Routes are tried in the order they were registered, the first one whose
+/// template and method match handles the command.
+#[derive(Default)]
+pub struct CommandRouter {
+    routes: Vec<Route>,
+}
+
+impl CommandRouter {
+    pub fn new() -> Self {
+        CommandRouter { routes: Vec::new() }
+    }
+
+    /// Registers a handler for a method on a uri template, ex.
+    /// `router.register(Get, "/contacts/{identity}", handler)`.
+    pub fn register<H>(&mut self, method: CommandMethod, template: &str,
+                       handler: H)
+        where H: CommandHandler + 'static
+    {
+        self.register_all(&[method], template, handler);
+    }
+
+    /// Same as `register`, sharing a single handler between several methods.
+    pub fn register_all<H>(&mut self, methods: &[CommandMethod],
+                           template: &str, handler: H)
+        where H: CommandHandler + 'static
+    {
+        let handler: Arc<CommandHandler> = Arc::new(handler);
+        let template = UriTemplate::new(template);
+        for method in methods {
+            self.routes.push(Route {
+                method: *method,
+                template: template.clone(),
+                handler: handler.clone(),
+            });
+        }
+    }
+
+    /// Handles the command, always producing a response to send back to the
+    /// caller. Unknown resources and unsupported methods produce a `Failure`.
+    pub fn dispatch(&self, caller: &Node, command: &Command) -> Command {
+        let mut response = self.handle(caller, command);
+        if response.to.is_none() {
+            response.to = Some(caller.clone());
+        }
+        response
+    }
+
+    fn handle(&self, caller: &Node, command: &Command) -> Command {
+        let uri = match command.uri {
+            Some(ref uri) => Uri::parse(uri),
+            None => return failure(command, ReasonCode::CommandInvalidArgument,
+                                   "The command has no uri"),
+        };
+
+        let mut found = false;
+        for route in &self.routes {
+            let params = match route.template.matches(&uri) {
+                Some(params) => params,
+                None => continue,
+            };
+            found = true;
+            if route.method != command.method { continue; }
+
+            let req = Request {
+                caller: caller,
+                command: command,
+                uri: uri,
+                params: params,
+            };
+            return match route.handler.handle(&req) {
+                Ok(res) => {
+                    let mut response = command.response(CommandStatus::Success);
+                    response.mime_type = res.mime_type;
+                    response.resource = res.resource;
+                    response
+                }
+                Err(reason) => command.response(CommandStatus::Failure(reason)),
+            };
+        }
+
+        if found {
+            failure(command, ReasonCode::CommandMethodNotSupported,
+                    "The method is not supported for this resource")
+        } else {
+            failure(command, ReasonCode::CommandResourceNotSupported,
+                    "The resource is not supported")
+        }
+    }
+}
+
+fn failure(command: &Command, code: ReasonCode, description: &str) -> Command {
+    command.response(CommandStatus::Failure(ErrReason::new(code, description)))
+}
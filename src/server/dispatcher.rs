@@ -0,0 +1,509 @@
+use std::io::Error as IoError;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use futures::{Async, Poll};
+
+use envelope::{Node, Envelope, Message, Command, Notification,
+    NotificationEvent, ErrReason, EnvelopeType, JsonMap, Metadata, MsgID,
+    TimeStamp, identity, is_group};
+use envelope::command::{CommandMethod, CommandStatus};
+use envelope::message::MessageType;
+use envelope::reason::ReasonCode;
+use envelope::resources::Direction;
+use envelope::resources::uri::Uri;
+
+use super::{ArcMut, EnvStream, NodeMap};
+use super::command::CommandRouter;
+use super::node::ClientSink;
+use super::router::Router;
+use super::store::MessageStore;
+use super::resources::threads::MessageArchive;
+use super::resources::subscriptions::{self, SubscriptionStore};
+use super::resources::delegations;
+
+pub use envelope::metadata::{GROUP_DISPATCHED_KEY, GROUP_FAILED_KEY};
+
+/// Handles the envelopes sent by a node, whether it holds a session or goes
+/// through the HTTP gateway: messages and notifications are routed to the
+/// connected peers and commands are answered.
+pub struct Dispatcher<S> {
+    peers: NodeMap<S>,
+    commands: Arc<CommandRouter>,
+    router: Router,
+    offline: ArcMut<Box<MessageStore>>,
+    archive: ArcMut<MessageArchive>,
+    subscriptions: ArcMut<SubscriptionStore>,
+}
+
+impl<S> Clone for Dispatcher<S> {
+    fn clone(&self) -> Self {
+        Dispatcher {
+            peers: self.peers.clone(),
+            commands: self.commands.clone(),
+            router: self.router.clone(),
+            offline: self.offline.clone(),
+            archive: self.archive.clone(),
+            subscriptions: self.subscriptions.clone(),
+        }
+    }
+}
+
+impl<S: EnvStream> Dispatcher<S> {
+    pub fn new(peers: NodeMap<S>, commands: Arc<CommandRouter>, router: Router,
+               offline: ArcMut<Box<MessageStore>>,
+               archive: ArcMut<MessageArchive>,
+               subscriptions: ArcMut<SubscriptionStore>) -> Self {
+        Dispatcher {
+            peers: peers,
+            commands: commands,
+            router: router,
+            offline: offline,
+            archive: archive,
+            subscriptions: subscriptions,
+        }
+    }
+
+    /// The node an envelope is sent as and its `pp`, the session which
+    /// actually sent it. Sessions may only use another identity in `from`
+    /// if that identity delegated them the envelope type, and never claim
+    /// to send per another node.
+    fn sender(&self, session: &Node, from: Option<&Node>, pp: Option<&Node>,
+              kind: EnvelopeType)
+            -> Result<(Node, Option<Node>), ErrReason> {
+        if let Some(pp) = pp {
+            if identity(pp) != identity(session) {
+                return Err(ErrReason::new(
+                    ReasonCode::AuthorizationUnauthorizedSender,
+                    "The pp of the envelope is not the session's node"));
+            }
+        }
+        match from {
+            None => Ok((session.clone(), None)),
+            Some(from) if identity(from) == identity(session) =>
+                Ok((from.clone(), None)),
+            Some(from) => {
+                if self.router.can_send_as(session, from, kind) {
+                    Ok((from.clone(), Some(session.clone())))
+                } else {
+                    Err(ErrReason::new(
+                        ReasonCode::AuthorizationUnauthorizedSender,
+                        "The session may not send on behalf of this node"))
+                }
+            }
+        }
+    }
+
+    /// Answers the command, both are stamped: the command with when it was
+    /// received, the response with when it was answered.
+    pub fn dispatch(&self, session: &Node, mut cmd: Command) -> Command {
+        Metadata::stamp(&mut cmd.metadata, TimeStamp::now());
+        let mut res = self.answer(session, cmd);
+        Metadata::stamp(&mut res.metadata, TimeStamp::now());
+        res
+    }
+
+    /// Answers the command, as the identity in `from` for delegated ones,
+    /// which handlers get with the session that sent them as their `pp`.
+    /// Private resources are only ever managed by their owner, see
+    /// `delegations::PRIVATE`.
+    fn answer(&self, session: &Node, mut cmd: Command) -> Command {
+        let sender = validate(cmd.metadata.as_ref())
+            .and_then(|_| self.sender(session, cmd.from.as_ref(),
+                                      cmd.pp.as_ref(), EnvelopeType::Command))
+            .and_then(|(from, pp)| {
+                let path = cmd.uri.as_ref().map(|uri| Uri::parse(uri).path);
+                match (pp, path) {
+                    (Some(_), Some(ref path))
+                            if !delegations::is_delegable(path) =>
+                        Err(ErrReason::new(
+                            ReasonCode::AuthorizationUnauthorizedSender,
+                            "The resource can't be managed on behalf of \
+                             its owner")),
+                    (pp, _) => Ok((from, pp)),
+                }
+            });
+        let (from, pp) = match sender {
+            Ok(sender) => sender,
+            Err(reason) => {
+                let mut res = cmd.response(CommandStatus::Failure(reason));
+                res.to = Some(session.clone());
+                return res;
+            }
+        };
+
+        cmd.pp = pp.clone();
+        let mut res = self.commands.dispatch(&from, &cmd);
+        if pp.is_some() {
+            res.to = pp;
+        }
+        if res.status == Some(CommandStatus::Success) {
+            self.notify_observers(&from, &cmd);
+            self.presence_changed(&from, &cmd);
+        }
+        res
+    }
+
+    /// Messages for an identity without any routable instance are kept
+    /// offline, even while it holds sessions, so they are delivered to the
+    /// first of its sessions setting a routable presence.
+    fn presence_changed(&self, node: &Node, cmd: &Command) {
+        let presence = cmd.uri.as_ref()
+            .map_or(false, |uri| Uri::parse(uri).path == "/presence");
+        if presence && cmd.method == CommandMethod::Set &&
+                self.router.is_routable(node) {
+            // The messages are left in the store if they can't be read.
+            let _ = self.deliver_offline(node);
+        }
+    }
+
+    /// Pushes the new state of a resource the caller changed to the nodes
+    /// subscribed to it, deleted resources are observed without content.
+    fn notify_observers(&self, owner: &Node, cmd: &Command) {
+        match cmd.method {
+            CommandMethod::Set | CommandMethod::Delete => {}
+            _ => return,
+        }
+        let path = match cmd.uri {
+            Some(ref uri) => Uri::parse(uri).path,
+            None => return,
+        };
+        let subscribers = self.subscriptions.lock().unwrap()
+            .subscribers(owner, &path);
+        if subscribers.is_empty() { return; }
+
+        let current = self.commands.dispatch(owner, &Command {
+            to: None,
+            from: None,
+            pp: None,
+            id: None,
+            metadata: None,
+            method: CommandMethod::Get,
+            status: None,
+            uri: cmd.uri.clone(),
+            mime_type: None,
+            resource: None,
+            extras: JsonMap::new(),
+        });
+        let (mime_type, resource) = match current.status {
+            Some(CommandStatus::Success) =>
+                (current.mime_type, current.resource),
+            _ => (None, None),
+        };
+
+        let mut peers = self.peers.lock().unwrap();
+        for node in subscribers {
+            if let Some(sink) = peers.get_mut(&node) {
+                let observe = subscriptions::observe(
+                    &node, owner, &path,
+                    mime_type.clone(), resource.clone());
+                sink.send_envelope(Envelope::Command(observe));
+            }
+        }
+    }
+
+    /// Makes `session` reachable, envelopes routed to it are sent on `sink`.
+    pub fn add_peer(&self, session: Node, sink: ClientSink<S>) {
+        self.peers.lock().unwrap().insert(session, sink);
+    }
+
+    /// Sends the answer to an envelope of `session` back to it.
+    pub fn reply(&self, session: &Node, envelope: Envelope) {
+        if let Some(sink) = self.peers.lock().unwrap().get_mut(session) {
+            sink.send_envelope(envelope);
+        }
+    }
+
+    /// Sends what is still queued for `session`, from the task of its
+    /// session.
+    pub fn flush(&self, session: &Node) -> Poll<(), IoError> {
+        match self.peers.lock().unwrap().get_mut(session) {
+            Some(sink) => sink.poll_flush(),
+            None => Ok(Async::Ready(())),
+        }
+    }
+
+    /// Releases what the session holds on the server once it is finishing,
+    /// its subscriptions, presence and capabilities are dropped and it no
+    /// longer receives envelopes.
+    pub fn finish(&self, session: &Node) {
+        self.subscriptions.lock().unwrap().remove_subscriber(session);
+        self.router.forget(session);
+        self.peers.lock().unwrap().remove(session);
+    }
+
+    /// Delivers the message to the instances picked by the router, the
+    /// returned notification tells the sender whether it was dispatched.
+    /// The message is stamped with when it was received, which is kept if
+    /// it is stored offline or archived.
+    pub fn route_message(&self, session: &Node, mut msg: Message)
+            -> Option<Notification> {
+        Metadata::stamp(&mut msg.metadata, TimeStamp::now());
+        let kind = msg.kind();
+        let sender = validate(msg.metadata.as_ref())
+            .and_then(|_| self.sender(session, msg.from.as_ref(),
+                                      msg.pp.as_ref(), EnvelopeType::Message));
+        let (from, pp) = match sender {
+            Ok(sender) => sender,
+            Err(_) if kind == MessageType::Error => return None,
+            Err(reason) => return msg.id.map(|id| {
+                notification(id, session.clone(),
+                             NotificationEvent::Failed(reason))
+            }),
+        };
+        msg.from = Some(from.clone());
+        msg.pp = pp;
+
+        let (result, metadata) = match msg.to {
+            Some(ref to) if kind == MessageType::Groupchat && is_group(to) => {
+                let (result, report) = self.deliver_group(&from, to, &msg);
+                (result.map(|_| NotificationEvent::Dispatched), Some(report))
+            }
+            _ => (self.deliver(&from, &msg, kind), None),
+        };
+        if result.is_ok() {
+            self.archive_message(&from, &msg, kind);
+        }
+        let event = match result {
+            Ok(event) => event,
+            Err(reason) => NotificationEvent::Failed(reason),
+        };
+
+        // Error messages are never answered, even if they could not be
+        // delivered, otherwise two nodes could keep bouncing errors.
+        if kind == MessageType::Error { return None; }
+        msg.id.map(|id| {
+            let mut notification = Notification::new(id, Some(from), event);
+            notification.metadata = metadata;
+            Metadata::stamp(&mut notification.metadata, TimeStamp::now());
+            notification
+        })
+    }
+
+    /// Forwards a notification, ex. `received` or `consumed`, to the sender
+    /// of the message it is about. Notifications are never answered and
+    /// those which can't be delivered, or which the destination doesn't
+    /// accept from the sender, are dropped.
+    pub fn route_notification(&self, session: &Node,
+                              mut notification: Notification) {
+        if validate(notification.metadata.as_ref()).is_err() { return; }
+        let (from, pp) = match self.sender(session,
+                                           notification.from.as_ref(),
+                                           notification.pp.as_ref(),
+                                           EnvelopeType::Notification) {
+            Ok(sender) => sender,
+            Err(_) => return,
+        };
+        let to = match notification.to {
+            Some(ref to) => to.clone(),
+            None => return,
+        };
+        if !self.router.is_authorized(&from, &to) { return; }
+        notification.from = Some(from);
+        notification.pp = pp;
+        Metadata::stamp(&mut notification.metadata, TimeStamp::now());
+
+        let mut peers = self.peers.lock().unwrap();
+        for node in self.router.destinations(&to, peers.keys()) {
+            if let Some(sink) = peers.get_mut(&node) {
+                sink.send_envelope(notification.clone().into());
+            }
+        }
+    }
+
+    /// Adds a one-on-one message to the history of both participants, as
+    /// long as they opted in to it.
+    fn archive_message(&self, from: &str, msg: &Message, kind: MessageType) {
+        let to = match msg.to {
+            Some(ref to) if to.contains('@') && !is_group(to) => to,
+            _ => return,
+        };
+        if kind == MessageType::Error || kind == MessageType::Groupchat {
+            return;
+        }
+
+        let mut archive = self.archive.lock().unwrap();
+        if self.router.stores_content(from) {
+            archive.archive(from, to, Direction::Sent, msg);
+        }
+        if self.router.stores_content(to) {
+            archive.archive(to, from, Direction::Received, msg);
+        }
+    }
+
+    /// Group chats are delivered to every accepting instance, all other
+    /// kinds only to the instance picked by the router. Messages for a
+    /// registered identity without any routable instance are kept until one
+    /// is, in which case the message is only `accepted` for now.
+    fn deliver(&self, from: &str, msg: &Message, kind: MessageType)
+            -> Result<NotificationEvent, ErrReason> {
+        let to = msg.to.as_ref().ok_or_else(destination_not_found)?;
+        if !self.router.is_authorized(from, to) {
+            return Err(ErrReason::new(
+                ReasonCode::AuthorizationUnauthorizedSender,
+                "The sender is not a contact of the destination"));
+        }
+
+        let mut peers = self.peers.lock().unwrap();
+        let destinations = match kind {
+            MessageType::Groupchat => self.router.fan_out(to, peers.keys()),
+            _ => self.router.destinations(to, peers.keys()),
+        };
+        if destinations.is_empty() && to.contains('@') && !is_group(to) {
+            return self.store_offline(to, msg);
+        }
+        self.send_to(&mut peers, destinations, msg)
+            .map(|_| NotificationEvent::Dispatched)
+    }
+
+    fn store_offline(&self, to: &str, msg: &Message)
+            -> Result<NotificationEvent, ErrReason> {
+        let size = self.router.inbox_size(to)
+            .ok_or_else(destination_not_found)?;
+        let mut offline = self.offline.lock().unwrap();
+        if offline.len(to) >= size as usize {
+            return Err(ErrReason::new(
+                ReasonCode::AuthorizationQuotaThresholdExceeded,
+                "The destination's inbox is full"));
+        }
+        offline.store(to, msg.clone()).map_err(|_| {
+            ErrReason::new(ReasonCode::GeneralError,
+                           "The message could not be stored")
+        })?;
+        Ok(NotificationEvent::Accepted)
+    }
+
+    /// Delivers, in order, the messages kept while the identity was offline
+    /// and tells their senders they were `dispatched`.
+    ///
+    /// Must be called once the session is `established`, after its sink was
+    /// added to the peers, otherwise the messages are left in the store.
+    /// Sessions do so on their first poll.
+    pub fn deliver_offline(&self, session: &Node) -> Result<(), IoError> {
+        let mut peers = self.peers.lock().unwrap();
+        if !peers.contains_key(session) { return Ok(()); }
+        let messages = self.offline.lock().unwrap().take(session)?;
+
+        for msg in messages {
+            peers.get_mut(session).unwrap()
+                .send_envelope(Envelope::Message(msg.clone()));
+            self.notify_dispatched(&mut peers, &msg);
+        }
+        Ok(())
+    }
+
+    /// Hands over the messages kept for the identity of a node which polls
+    /// for them instead of holding a session, ex. through the HTTP gateway.
+    pub fn take_offline(&self, node: &Node) -> Result<Vec<Message>, IoError> {
+        let mut peers = self.peers.lock().unwrap();
+        let messages = self.offline.lock().unwrap().take(node)?;
+        for msg in &messages {
+            self.notify_dispatched(&mut peers, msg);
+        }
+        Ok(messages)
+    }
+
+    /// Tells the sender of a message which was kept offline that it finally
+    /// got `dispatched`.
+    fn notify_dispatched(&self, peers: &mut HashMap<Node, ClientSink<S>>,
+                         msg: &Message) {
+        if msg.kind() == MessageType::Error { return; }
+        let (id, from) = match (msg.id, msg.from.as_ref()) {
+            (Some(id), Some(from)) => (id, from),
+            _ => return,
+        };
+        let notification = notification(id, from.clone(),
+                                        NotificationEvent::Dispatched);
+        for node in self.router.destinations(from, peers.keys()) {
+            if let Some(sink) = peers.get_mut(&node) {
+                sink.send_envelope(notification.clone().into());
+            }
+        }
+    }
+
+    /// Delivers the message to every member of the group but the sender.
+    ///
+    /// The sender gets a single notification, `dispatched` if at least one
+    /// member received the message, with the number of members reached and
+    /// those which weren't in its metadata.
+    fn deliver_group(&self, from: &str, group: &str, msg: &Message)
+            -> (Result<(), ErrReason>, Metadata) {
+        let mut report = Metadata::new();
+        let members = match self.router.members(group) {
+            Some(ref members) if members.iter()
+                    .any(|m| m == identity(from)) => members.clone(),
+            Some(_) => return (Err(ErrReason::new(
+                ReasonCode::AuthorizationUnauthorizedSender,
+                "The sender is not a member of the group")), report),
+            None => return (Err(destination_not_found()), report),
+        };
+
+        let mut peers = self.peers.lock().unwrap();
+        let mut dispatched = 0;
+        let mut failed = Vec::new();
+        for member in members.iter().filter(|m| *m != identity(from)) {
+            let destinations = self.router.destinations(member, peers.keys());
+            match self.send_to(&mut peers, destinations, msg) {
+                Ok(()) => dispatched += 1,
+                Err(_) => failed.push(member.clone()),
+            }
+        }
+
+        report.set_group_dispatched(dispatched);
+        if !failed.is_empty() {
+            report.set_group_failed(&failed);
+        }
+        if dispatched == 0 && !failed.is_empty() {
+            (Err(destination_not_found()), report)
+        } else {
+            (Ok(()), report)
+        }
+    }
+
+    /// Sends the message to the destinations able to handle its type.
+    fn send_to(&self, peers: &mut HashMap<Node, ClientSink<S>>,
+               destinations: Vec<Node>, msg: &Message)
+            -> Result<(), ErrReason> {
+        if destinations.is_empty() { return Err(destination_not_found()); }
+
+        let mime_type = msg.mime_type.to_string();
+        let destinations: Vec<Node> = destinations.into_iter()
+            .filter(|node| self.router.accepts_content(node, &mime_type))
+            .collect();
+        if destinations.is_empty() {
+            return Err(ErrReason::new(
+                ReasonCode::MessageUnsupportedType,
+                "The destination does not support the message type"));
+        }
+
+        for node in &destinations {
+            if let Some(sink) = peers.get_mut(node) {
+                sink.send_envelope(Envelope::Message(msg.clone()));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A notification from the server, stamped with when its event happened.
+fn notification(id: MsgID, to: Node, event: NotificationEvent)
+        -> Notification {
+    let mut notification = Notification::new(id, Some(to), event);
+    Metadata::stamp(&mut notification.metadata, TimeStamp::now());
+    notification
+}
+
+/// Envelopes whose metadata breaks the protocol are refused, see
+/// `Metadata::validate`.
+fn validate(metadata: Option<&Metadata>) -> Result<(), ErrReason> {
+    match metadata.map(Metadata::validate) {
+        Some(Err(err)) =>
+            Err(ErrReason::new(ReasonCode::ValidationError, &err.to_string())),
+        _ => Ok(()),
+    }
+}
+
+fn destination_not_found() -> ErrReason {
+    ErrReason::new(ReasonCode::RoutingDestinationNotFound,
+                   "The message destination was not found")
+}
@@ -1,11 +1,18 @@
+use std::io::{self, Read, Write};
 use std::io::Error as IoError;
+use std::mem;
+use std::str::FromStr;
 
-use futures::{Future, Poll, Stream, Sink, Async};
-use tokio_core::{net, io};
+use futures::{Async, BoxFuture, Future, Poll, Stream};
+use tokio_core::io::{Codec, EasyBuf, Io};
+use tokio_core::net::TcpStream;
 
-use envelope::{ LimeCodec, Session, Envelope };
-use super::{EnvStream};
-use super::node::Authentication;
+use envelope::{Envelope, EnvelopeCodec, EnvelopeStream, ErrReason, MsgID,
+    Session, SessionState};
+use envelope::session::{EncryptionOptions, CompressionOptions};
+use envelope::reason::ReasonCode;
+use transport::Transport;
+use super::{BoxEnvStream, EnvStream};
 
 /// A future which evaluates to an `EnvStream`.
 ///
@@ -13,9 +20,10 @@ use super::node::Authentication;
 /// trait, which is used to produce 'ClientConnection' structs. These are
 /// then passed to an 'Authentication' struct.
 pub trait Handshake {
+    type Transport: Transport;
     type Stream: EnvStream;
 
-    fn take_stream(&mut self, tcp: net::TcpStream);
+    fn take_stream(&mut self, io: Self::Transport);
 
     /// A 'Session' envelope is provided if a new one arrives, otherwise the
     /// value was simply poll'd.
@@ -25,6 +33,12 @@ pub trait Handshake {
     /// simple until further development can occur.
     fn update_handshake(&mut self) -> Poll<Option<Self::Stream>, IoError>;
     // TODO: Fix this ambiguous Request / Response situation.
+
+    /// Id of the session being negotiated, once the client sent it. The
+    /// `Authentication` waits for a `new` session otherwise.
+    fn session_id(&self) -> Option<MsgID> {
+        None
+    }
 }
 
 /// TODO: Change this to a Stream implementation
@@ -37,3 +51,276 @@ impl<S: EnvStream> Stream for Handshake<Stream=S> {
     }
 }
 
+/// Turns the `WouldBlock` of a non blocking io into `NotReady`.
+pub fn would_block<T>(res: io::Result<T>) -> Poll<T, io::Error> {
+    match res {
+        Ok(value) => Ok(Async::Ready(value)),
+        Err(ref err) if err.kind() == io::ErrorKind::WouldBlock =>
+            Ok(Async::NotReady),
+        Err(err) => Err(err),
+    }
+}
+
+/// Writes `data` from `pos` on, which is where a previous call stopped.
+pub fn write_all<W: Write>(io: &mut W, data: &[u8], pos: &mut usize)
+        -> Poll<(), io::Error> {
+    while *pos < data.len() {
+        match would_block(io.write(&data[*pos..]))? {
+            Async::Ready(0) => return Err(io::Error::new(
+                io::ErrorKind::WriteZero, "Closed during the handshake")),
+            Async::Ready(n) => *pos += n,
+            Async::NotReady => return Ok(Async::NotReady),
+        }
+    }
+    would_block(io.flush())
+}
+
+fn invalid(description: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, description.to_owned())
+}
+
+enum Negotiation<T> {
+    /// Waiting for the `new` session of the client.
+    New,
+    /// The options were offered, waiting for those the client picked.
+    Offered,
+    /// The transport switches to the options once the last session sent is
+    /// written.
+    Upgrade(EncryptionOptions, CompressionOptions),
+    Upgrading(BoxFuture<T, IoError>),
+    /// The client is told why in a failed session, the handshake then
+    /// fails once it is written.
+    Failing(ReasonCode, &'static str),
+    Failed(&'static str),
+}
+
+/// Server side of the session negotiation, on any transport.
+///
+/// The encryption and compression options of the transport are offered to
+/// the client, unless `none` is all it has, and the transport is upgraded
+/// to those the client picked. The stream is then handed over, ready for
+/// the `Authentication`. Until then envelopes are read one byte at a time,
+/// so that nothing sent after the negotiation is consumed. The encoding of
+/// the `new` session, see `EnvelopeCodec::negotiate`, is kept for the rest
+/// of the connection.
+pub struct TcpHandshake<T = TcpStream> {
+    conn: Option<T>,
+    state: Negotiation<T>,
+    session_id: Option<MsgID>,
+    codec: EnvelopeCodec,
+    encryption_options: Vec<EncryptionOptions>,
+    compression_options: Vec<CompressionOptions>,
+    read_buf: EasyBuf,
+    write_buf: Vec<u8>,
+    written: usize,
+}
+
+/// The option picked by the client, if it was offered.
+fn picked<O: FromStr + PartialEq>(choice: Option<&String>, offered: &[O])
+        -> Option<O> {
+    match choice.and_then(|choice| choice.parse().ok()) {
+        Some(option) => if offered.contains(&option) { Some(option) }
+                        else { None },
+        None => None,
+    }
+}
+
+impl<T: Transport> TcpHandshake<T> {
+    pub fn new() -> Self {
+        TcpHandshake {
+            conn: None,
+            state: Negotiation::New,
+            session_id: None,
+            codec: EnvelopeCodec::negotiate(),
+            encryption_options: Vec::new(),
+            compression_options: Vec::new(),
+            read_buf: EasyBuf::new(),
+            write_buf: Vec::new(),
+            written: 0,
+        }
+    }
+
+    fn read_session(&mut self) -> Poll<Session, IoError> {
+        loop {
+            match self.codec.decode(&mut self.read_buf)? {
+                Some(Envelope::Session(session)) =>
+                    return Ok(Async::Ready(session)),
+                Some(_) =>
+                    return Err(invalid("Only sessions can be negotiated")),
+                None => (),
+            }
+            let mut byte = [0];
+            let conn = self.conn.as_mut().unwrap();
+            match would_block(conn.read(&mut byte))? {
+                Async::Ready(0) => return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Closed during the handshake")),
+                Async::Ready(_) => self.read_buf.get_mut().push(byte[0]),
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
+    }
+
+    fn send(&mut self, session: Session) -> Result<(), IoError> {
+        self.codec.encode(Envelope::Session(session), &mut self.write_buf)
+    }
+
+    /// Answers the `new` session, skipping to the upgrade when there is no
+    /// option to pick.
+    fn offer(&mut self, session: Session) -> Result<Negotiation<T>, IoError> {
+        if session.state != SessionState::New {
+            return Ok(Negotiation::Failing(
+                ReasonCode::SessionInvalidActionForState,
+                "The session must start as new"));
+        }
+        self.session_id = Some(session.id);
+        if self.encryption_options == [EncryptionOptions::Nil] &&
+                self.compression_options == [CompressionOptions::Nil] {
+            return Ok(Negotiation::Upgrade(EncryptionOptions::Nil,
+                                           CompressionOptions::Nil));
+        }
+
+        let mut offer = Session::new(session.id, SessionState::Negotiating);
+        offer.encryption_options = Some(self.encryption_options.iter()
+            .map(|option| option.as_str().to_owned()).collect());
+        offer.compression_options = Some(self.compression_options.iter()
+            .map(|option| option.as_str().to_owned()).collect());
+        self.send(offer)?;
+        Ok(Negotiation::Offered)
+    }
+
+    /// Confirms the options picked by the client, if they were offered.
+    fn confirm(&mut self, session: Session)
+            -> Result<Negotiation<T>, IoError> {
+        if session.state != SessionState::Negotiating {
+            return Ok(Negotiation::Failing(
+                ReasonCode::SessionInvalidActionForState,
+                "The options must be negotiated first"));
+        }
+        let encryption = picked(session.encryption.as_ref(),
+                                &self.encryption_options);
+        let compression = picked(session.compression.as_ref(),
+                                 &self.compression_options);
+        let (encryption, compression) = match (encryption, compression) {
+            (Some(encryption), Some(compression)) =>
+                (encryption, compression),
+            _ => return Ok(Negotiation::Failing(
+                ReasonCode::SessionNegotiationInvalidOptions,
+                "The options picked were not offered")),
+        };
+
+        let mut confirmation = Session::new(session.id,
+                                            SessionState::Negotiating);
+        confirmation.encryption = Some(encryption.as_str().to_owned());
+        confirmation.compression = Some(compression.as_str().to_owned());
+        self.send(confirmation)?;
+        Ok(Negotiation::Upgrade(encryption, compression))
+    }
+}
+
+impl<T: Transport> Default for TcpHandshake<T> {
+    fn default() -> Self {
+        TcpHandshake::new()
+    }
+}
+
+impl<T: Transport> Handshake for TcpHandshake<T> {
+    type Transport = T;
+    type Stream = EnvelopeStream<T>;
+
+    fn take_stream(&mut self, io: T) {
+        self.encryption_options = io.encryption_options();
+        self.compression_options = io.compression_options();
+        self.conn = Some(io);
+        self.state = Negotiation::New;
+    }
+
+    fn update_handshake(&mut self) -> Poll<Option<Self::Stream>, IoError> {
+        loop {
+            if let Negotiation::Upgrading(ref mut upgrade) = self.state {
+                let conn = match upgrade.poll()? {
+                    Async::Ready(conn) => conn,
+                    Async::NotReady => return Ok(Async::NotReady),
+                };
+                let codec = mem::replace(&mut self.codec,
+                                         EnvelopeCodec::negotiate());
+                return Ok(Async::Ready(Some(conn.framed(codec))));
+            }
+            let written = match self.conn.as_mut() {
+                Some(conn) =>
+                    write_all(conn, &self.write_buf, &mut self.written)?,
+                None => return Ok(Async::Ready(None)),
+            };
+            if written.is_not_ready() { return Ok(Async::NotReady); }
+
+            let next = match self.state {
+                Negotiation::New => {
+                    let session = match self.read_session()? {
+                        Async::Ready(session) => session,
+                        Async::NotReady => return Ok(Async::NotReady),
+                    };
+                    self.offer(session)?
+                }
+                Negotiation::Offered => {
+                    let session = match self.read_session()? {
+                        Async::Ready(session) => session,
+                        Async::NotReady => return Ok(Async::NotReady),
+                    };
+                    self.confirm(session)?
+                }
+                Negotiation::Upgrade(encryption, compression) => {
+                    let conn = self.conn.take().unwrap();
+                    Negotiation::Upgrading(conn.upgrade(encryption,
+                                                        compression))
+                }
+                Negotiation::Failing(code, description) => {
+                    let reason = ErrReason::new(code, description);
+                    self.send(Session::new(self.session_id.unwrap_or(0),
+                                           SessionState::Failed(reason)))?;
+                    Negotiation::Failed(description)
+                }
+                Negotiation::Failed(description) =>
+                    return Err(invalid(description)),
+                Negotiation::Upgrading(_) => unreachable!(),
+            };
+            self.state = next;
+        }
+    }
+
+    fn session_id(&self) -> Option<MsgID> {
+        self.session_id
+    }
+}
+
+/// Hands the streams of `H` over boxed, so that the clients of different
+/// transports can share a `LimeServer<BoxEnvStream>`.
+pub struct Boxed<H>(pub H);
+
+impl<H: Default> Default for Boxed<H> {
+    fn default() -> Self {
+        Boxed(H::default())
+    }
+}
+
+impl<H> Handshake for Boxed<H>
+    where H: Handshake, H::Stream: Send + 'static
+{
+    type Transport = H::Transport;
+    type Stream = BoxEnvStream;
+
+    fn take_stream(&mut self, io: H::Transport) {
+        self.0.take_stream(io)
+    }
+
+    fn update_handshake(&mut self) -> Poll<Option<BoxEnvStream>, IoError> {
+        match self.0.update_handshake()? {
+            Async::Ready(stream) => Ok(Async::Ready(
+                stream.map(|stream| Box::new(stream) as BoxEnvStream))),
+            Async::NotReady => Ok(Async::NotReady),
+        }
+    }
+
+    fn session_id(&self) -> Option<MsgID> {
+        self.0.session_id()
+    }
+}
@@ -0,0 +1,232 @@
+//! A server with in-process clients for tests.
+//!
+//! Clients are connected over `transport::memory` and go through the same
+//! handshake and `Authentication` as any other, and what a client sends is
+//! fully handled by the server before `Harness::send` returns, so tests can
+//! script exchanges, the negotiation of the session included, and check
+//! what each client received without any timing involved.
+
+use std::io::Error as IoError;
+
+use base64;
+use futures::{future, stream, Async, Future, Sink, Stream};
+use serde_json::Value;
+use tokio_core::io::Io;
+use tokio_core::reactor::Core;
+
+use envelope::{Encoding, Envelope, EnvelopeCodec, EnvelopeStream, JsonMap,
+    Node, Session};
+use envelope::session::SessionState;
+use transport::{Incoming, Listener, MemoryAddr, MemoryListener, MemoryStream,
+    Transport};
+use user::UserStore;
+
+use super::{ArcMut, Authentication, ClientConnection, ClientSession,
+    Dispatcher, LimeServer};
+use super::handshake::{Handshake, TcpHandshake};
+
+type Conn = EnvelopeStream<MemoryStream>;
+
+/// Server end of a connection, as far as its session got.
+enum Server {
+    Handshake(TcpHandshake<MemoryStream>),
+    Authentication(Authentication<Conn>),
+    Session(ClientSession<Conn>),
+    /// The session failed or finished.
+    Closed,
+}
+
+struct Client {
+    /// Known once the session is established.
+    node: Option<Node>,
+    /// The client end of the connection, dropped on `disconnect`.
+    conn: Option<Conn>,
+    server: Server,
+}
+
+impl Client {
+    /// Moves the server end forward until it waits for the client again.
+    fn poll(&mut self, users: &ArcMut<UserStore>,
+            dispatcher: &Dispatcher<Conn>) {
+        loop {
+            let next = match self.server {
+                Server::Handshake(ref mut handshake) =>
+                    match handshake.update_handshake() {
+                        Ok(Async::Ready(Some(stream))) =>
+                            Server::Authentication(Authentication::new(
+                                ClientConnection::new(stream),
+                                handshake.session_id(), users.clone())),
+                        Ok(Async::NotReady) => return,
+                        Ok(Async::Ready(None)) | Err(_) => Server::Closed,
+                    },
+                Server::Authentication(ref mut authentication) =>
+                    match authentication.poll() {
+                        Ok(Async::Ready((node, io))) => {
+                            self.node = Some(node.clone());
+                            Server::Session(ClientSession::establish(
+                                node, io, dispatcher.clone()))
+                        }
+                        Ok(Async::NotReady) => return,
+                        Err(_) => Server::Closed,
+                    },
+                Server::Session(ref mut session) =>
+                    match session.poll().expect("The session failed") {
+                        Async::Ready(()) => Server::Closed,
+                        Async::NotReady => return,
+                    },
+                Server::Closed => return,
+            };
+            self.server = next;
+        }
+    }
+}
+
+pub struct Harness {
+    server: LimeServer<Conn>,
+    core: Core,
+    addr: MemoryAddr,
+    accepted: stream::Wait<Incoming<MemoryStream>>,
+    clients: Vec<Client>,
+}
+
+impl Harness {
+    /// Starts a server without any client, users must be registered before
+    /// they `connect`.
+    pub fn new() -> Self {
+        let server = LimeServer::new(&"127.0.0.1:0".parse().unwrap());
+        Harness::with_server(server)
+    }
+
+    /// Same as `new`, with a server whose command handlers were already
+    /// registered.
+    pub fn with_server(server: LimeServer<Conn>) -> Self {
+        let listener = MemoryListener::bind();
+        Harness {
+            server: server,
+            core: Core::new().expect("The reactor started"),
+            addr: listener.local_addr().expect("The listener is bound"),
+            accepted: listener.incoming().wait(),
+            clients: Vec::new(),
+        }
+    }
+
+    /// Opens a connection for one more client, without a session, returns
+    /// the index of the client. Its session is then negotiated by the
+    /// sessions it sends.
+    pub fn open(&mut self) -> usize {
+        let conn = MemoryStream::connect(&self.addr, &self.core.handle())
+            .wait().expect("The listener is alive");
+        let (server_conn, _) = self.accepted.next()
+            .expect("The listener is alive")
+            .expect("The connection was accepted");
+        let mut handshake = TcpHandshake::new();
+        handshake.take_stream(server_conn);
+
+        self.clients.push(Client {
+            node: None,
+            conn: Some(conn.framed(EnvelopeCodec::new(Encoding::Json))),
+            server: Server::Handshake(handshake),
+        });
+        self.clients.len() - 1
+    }
+
+    /// Opens a connection for `node` and authenticates it with the `plain`
+    /// scheme, returns the index of the client once its session is
+    /// established. The messages kept while its identity was offline are
+    /// then delivered.
+    pub fn connect(&mut self, node: &str, password: &str) -> usize {
+        let client = self.open();
+        self.send(client, Session::new(1, SessionState::New).into());
+        match self.receive(client) {
+            Some(Envelope::Session(ref session))
+                if session.state == SessionState::Authenticating => (),
+            other => panic!("Expected the schemes, got {:?}", other),
+        }
+
+        let mut authentication = JsonMap::new();
+        authentication.insert("password".to_owned(),
+                              Value::String(base64::encode(password
+                                                           .as_bytes())));
+        let mut session = Session::new(1, SessionState::Authenticating);
+        session.from = Some(node.to_owned());
+        session.scheme = Some(Value::String("plain".to_owned()));
+        session.authentication = Some(Value::Object(authentication));
+        self.send(client, session.into());
+        match self.receive(client) {
+            Some(Envelope::Session(ref session))
+                if session.state == SessionState::Established => (),
+            other => panic!("{} was not authenticated, got {:?}",
+                            node, other),
+        }
+        client
+    }
+
+    pub fn server(&self) -> &LimeServer<Conn> {
+        &self.server
+    }
+
+    /// The node of `client`, once its session is established.
+    pub fn node(&self, client: usize) -> &Node {
+        self.clients[client].node.as_ref()
+            .expect("The session is established")
+    }
+
+    /// Sends the envelope from `client` and lets the server handle it.
+    pub fn send(&mut self, client: usize, envelope: Envelope) {
+        {
+            let conn = self.clients[client].conn.as_mut()
+                .expect("The client was disconnected");
+            future::lazy(|| {
+                conn.start_send(envelope)?;
+                conn.poll_complete()
+            }).wait().expect("The connection was closed");
+        }
+        self.run(client);
+    }
+
+    /// The oldest envelope received by `client` it didn't take yet.
+    pub fn receive(&mut self, client: usize) -> Option<Envelope> {
+        let conn = match self.clients[client].conn.as_mut() {
+            Some(conn) => conn,
+            None => return None,
+        };
+        match future::lazy(|| conn.poll()).wait()
+                .expect("The connection failed") {
+            Async::Ready(envelope) => envelope,
+            Async::NotReady => None,
+        }
+    }
+
+    /// Every envelope received by `client` it didn't take yet, in order.
+    pub fn receive_all(&mut self, client: usize) -> Vec<Envelope> {
+        let mut envelopes = Vec::new();
+        while let Some(envelope) = self.receive(client) {
+            envelopes.push(envelope);
+        }
+        envelopes
+    }
+
+    /// Closes the connection of `client`, its session finishes.
+    pub fn disconnect(&mut self, client: usize) {
+        self.clients[client].conn = None;
+        self.run(client);
+    }
+
+    /// Polls the server end of `client` until it waits for the client
+    /// again.
+    fn run(&mut self, client: usize) {
+        let users = self.server.registry();
+        let dispatcher = self.server.dispatcher();
+        let client = &mut self.clients[client];
+        future::lazy(|| {
+            client.poll(&users, &dispatcher);
+            Ok::<(), IoError>(())
+        }).wait().expect("The client was polled");
+    }
+}
+
+impl Default for Harness {
+    fn default() -> Self {
+        Harness::new()
+    }
+}
@@ -0,0 +1,333 @@
+//! HTTP gateway, for services which can't hold a persistent connection.
+//!
+//! - `POST /messages`, `POST /notifications` and `POST /commands` take the
+//!   json of the envelope as their body. Messages are answered with their
+//!   notification and commands with their response, synchronously.
+//! - `GET /messages` returns the messages addressed to the caller since its
+//!   last poll, which are kept like those of any offline identity.
+//!
+//! Callers authenticate with the same schemes as sessions, in the
+//! `Authorization` header:
+//!
+//! - `Basic`, with the node and password of the `plain` scheme.
+//! - `Transport <node>`, for local connections whose system user may
+//!   authenticate as the node with the `transport` scheme.
+//!
+//! Callers act as the node they authenticated as, or as the `http` instance
+//! when they only give their identity. Requests are parsed by `httparse`,
+//! serve the gateway with `tokio_proto::TcpServer` and `HttpProto`, or bind
+//! `HttpProto` to any other `Transport`.
+
+use std::io;
+
+use base64;
+use futures::{future, BoxFuture, Future};
+use httparse;
+use serde::Serialize;
+use serde_json;
+use tokio_core::io::{Codec, EasyBuf, Framed, Io};
+use tokio_proto::pipeline::ServerProto;
+use tokio_service::Service;
+
+use envelope::{Envelope, Node, SchemeOptions, identity};
+use transport::{PeerCredentials, Transport};
+use user::UserStore;
+
+use super::{ArcMut, EnvStream};
+use super::dispatcher::Dispatcher;
+
+/// Instance given to the callers which only authenticate their identity.
+pub static HTTP_INSTANCE: &'static str = "http";
+
+static MAX_REQUEST_SIZE: usize = 1 << 20;
+const MAX_HEADERS: usize = 32;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpRequest {
+    pub method: String,
+    /// The request target, query string included.
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    /// Who runs the peer of the connection, see
+    /// `Transport::peer_credentials`.
+    pub credentials: Option<PeerCredentials>,
+}
+
+impl HttpRequest {
+    /// Value of the first header with this name, ignoring its case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        let name = name.to_lowercase();
+        self.headers.iter()
+            .find(|&&(ref key, _)| key.to_lowercase() == name)
+            .map(|&(_, ref value)| &**value)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub reason: &'static str,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    pub fn empty(status: u16, reason: &'static str) -> Self {
+        HttpResponse {
+            status: status,
+            reason: reason,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn json<T: Serialize>(value: &T) -> Self {
+        let mut res = HttpResponse::empty(200, "OK");
+        res.headers.push(("Content-Type".to_owned(),
+                          "application/json".to_owned()));
+        res.body = serde_json::to_vec(value).expect("Invalid json value");
+        res
+    }
+}
+
+fn invalid(description: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, description.to_owned())
+}
+
+/// HTTP/1.1 requests with a `Content-Length` body, chunked bodies aren't
+/// supported. Requests are given the credentials of the connection.
+pub struct HttpCodec {
+    credentials: Option<PeerCredentials>,
+}
+
+impl HttpCodec {
+    pub fn new(credentials: Option<PeerCredentials>) -> Self {
+        HttpCodec { credentials: credentials }
+    }
+}
+
+impl Codec for HttpCodec {
+    type In = HttpRequest;
+    type Out = HttpResponse;
+
+    fn decode(&mut self, buf: &mut EasyBuf)
+            -> Result<Option<Self::In>, io::Error> {
+        let (head_len, mut req) = {
+            let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+            let mut head = httparse::Request::new(&mut headers);
+            let head_len = match head.parse(buf.as_slice()) {
+                Ok(httparse::Status::Complete(len)) => len,
+                Ok(httparse::Status::Partial)
+                        if buf.len() > MAX_REQUEST_SIZE =>
+                    return Err(invalid("The request head is too large")),
+                Ok(httparse::Status::Partial) => return Ok(None),
+                Err(err) => return Err(invalid(&err.to_string())),
+            };
+            let headers = head.headers.iter().map(|header| {
+                (header.name.to_owned(),
+                 String::from_utf8_lossy(header.value).into_owned())
+            }).collect();
+            (head_len, HttpRequest {
+                method: head.method.unwrap_or("").to_owned(),
+                path: head.path.unwrap_or("").to_owned(),
+                headers: headers,
+                body: Vec::new(),
+                credentials: self.credentials,
+            })
+        };
+
+        if req.header("transfer-encoding").is_some() {
+            return Err(invalid("Chunked bodies are not supported"));
+        }
+        let body_len = match req.header("content-length") {
+            Some(len) => len.parse::<usize>()
+                .map_err(|_| invalid("Invalid Content-Length"))?,
+            None => 0,
+        };
+        if head_len + body_len > MAX_REQUEST_SIZE {
+            return Err(invalid("The request is too large"));
+        }
+        if buf.len() < head_len + body_len { return Ok(None); }
+
+        let request = buf.drain_to(head_len + body_len);
+        req.body = request.as_slice()[head_len..].to_vec();
+        Ok(Some(req))
+    }
+
+    fn encode(&mut self, msg: Self::Out, buf: &mut Vec<u8>) -> io::Result<()> {
+        buf.extend_from_slice(format!("HTTP/1.1 {} {}\r\n", msg.status,
+                                      msg.reason).as_bytes());
+        for &(ref name, ref value) in &msg.headers {
+            buf.extend_from_slice(format!("{}: {}\r\n", name, value)
+                                  .as_bytes());
+        }
+        buf.extend_from_slice(format!("Content-Length: {}\r\n\r\n",
+                                      msg.body.len()).as_bytes());
+        buf.extend_from_slice(&msg.body);
+        Ok(())
+    }
+}
+
+/// Pipelined HTTP/1.1 protocol used to serve an `HttpGateway`.
+pub struct HttpProto;
+
+impl<T: Transport> ServerProto<T> for HttpProto {
+    type Request = HttpRequest;
+    type Response = HttpResponse;
+    type Transport = Framed<T, HttpCodec>;
+    type BindTransport = io::Result<Self::Transport>;
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        let credentials = io.peer_credentials();
+        Ok(io.framed(HttpCodec::new(credentials)))
+    }
+}
+
+/// Injects the envelopes posted over HTTP into the same dispatcher as the
+/// sessions, see the module documentation for the endpoints.
+pub struct HttpGateway<S> {
+    users: ArcMut<UserStore>,
+    dispatcher: Dispatcher<S>,
+}
+
+impl<S> Clone for HttpGateway<S> {
+    fn clone(&self) -> Self {
+        HttpGateway {
+            users: self.users.clone(),
+            dispatcher: self.dispatcher.clone(),
+        }
+    }
+}
+
+/// Offers the schemes the request may use, like the server does when a
+/// session starts.
+fn unauthorized(req: &HttpRequest) -> HttpResponse {
+    let mut res = HttpResponse::empty(401, "Unauthorized");
+    res.headers.push(("WWW-Authenticate".to_owned(),
+                      "Basic realm=\"lime\"".to_owned()));
+    if req.credentials.is_some() {
+        res.headers.push(("WWW-Authenticate".to_owned(),
+                          "Transport realm=\"lime\"".to_owned()));
+    }
+    res
+}
+
+fn bad_request() -> HttpResponse {
+    HttpResponse::empty(400, "Bad Request")
+}
+
+/// The node and password of `Basic` credentials.
+fn basic(credentials: &str) -> Option<(Node, String)> {
+    let credentials = match base64::decode(credentials) {
+        Ok(bytes) => match String::from_utf8(bytes) {
+            Ok(credentials) => credentials,
+            Err(_) => return None,
+        },
+        Err(_) => return None,
+    };
+    credentials.find(':').map(|index| {
+        (credentials[..index].to_owned(), credentials[index + 1..].to_owned())
+    })
+}
+
+impl<S: EnvStream> HttpGateway<S> {
+    pub fn new(users: ArcMut<UserStore>, dispatcher: Dispatcher<S>) -> Self {
+        HttpGateway {
+            users: users,
+            dispatcher: dispatcher,
+        }
+    }
+
+    /// The node of the caller, if its credentials are valid, see the
+    /// module documentation for the schemes.
+    fn authenticate(&self, req: &HttpRequest) -> Option<Node> {
+        let authorization = match req.header("authorization") {
+            Some(value) => value.trim(),
+            None => return None,
+        };
+        let (scheme, credentials) = match authorization.find(' ') {
+            Some(index) => (authorization[..index].to_lowercase(),
+                            authorization[index + 1..].trim()),
+            None => return None,
+        };
+        let (scheme, node, password) = match &*scheme {
+            "basic" => match basic(credentials) {
+                Some((node, password)) =>
+                    (SchemeOptions::Plain, node, Some(password)),
+                None => return None,
+            },
+            "transport" =>
+                (SchemeOptions::Transport, credentials.to_owned(), None),
+            _ => return None,
+        };
+        let node = if node.contains('/') {
+            node
+        } else {
+            format!("{}/{}", node, HTTP_INSTANCE)
+        };
+
+        let users = self.users.lock().unwrap();
+        let authenticated = users.get(identity(&node)).map_or(false, |user| {
+            user.authenticate(scheme, password.as_ref().map(|p| &**p),
+                              req.credentials.as_ref())
+        });
+        if authenticated { Some(node) } else { None }
+    }
+
+    pub fn handle(&self, req: &HttpRequest) -> HttpResponse {
+        let node = match self.authenticate(req) {
+            Some(node) => node,
+            None => return unauthorized(req),
+        };
+        let path = req.path.split('?').next().unwrap();
+
+        let envelope = if req.method == "POST" {
+            match serde_json::from_slice::<Envelope>(&req.body) {
+                Ok(envelope) => Some(envelope),
+                Err(_) => return bad_request(),
+            }
+        } else { None };
+
+        match (&*req.method, path, envelope) {
+            ("GET", "/messages", _) => {
+                match self.dispatcher.take_offline(&node) {
+                    Ok(messages) => HttpResponse::json(&messages),
+                    Err(_) => HttpResponse::empty(500,
+                                                  "Internal Server Error"),
+                }
+            }
+            ("POST", "/messages", Some(Envelope::Message(msg))) => {
+                match self.dispatcher.route_message(&node, msg) {
+                    Some(notification) => HttpResponse::json(&notification),
+                    None => HttpResponse::empty(202, "Accepted"),
+                }
+            }
+            ("POST", "/notifications",
+             Some(Envelope::Notification(notification))) => {
+                self.dispatcher.route_notification(&node, notification);
+                HttpResponse::empty(202, "Accepted")
+            }
+            ("POST", "/commands", Some(Envelope::Command(cmd))) => {
+                HttpResponse::json(&self.dispatcher.dispatch(&node, cmd))
+            }
+            ("POST", "/messages", _) | ("POST", "/notifications", _) |
+            ("POST", "/commands", _) => bad_request(),
+            (_, "/messages", _) | (_, "/notifications", _) |
+            (_, "/commands", _) =>
+                HttpResponse::empty(405, "Method Not Allowed"),
+            _ => HttpResponse::empty(404, "Not Found"),
+        }
+    }
+}
+
+impl<S: EnvStream> Service for HttpGateway<S> {
+    type Request = HttpRequest;
+    type Response = HttpResponse;
+    type Error = io::Error;
+    type Future = BoxFuture<HttpResponse, io::Error>;
+
+    fn call(&self, req: HttpRequest) -> Self::Future {
+        future::ok(self.handle(&req)).boxed()
+    }
+}
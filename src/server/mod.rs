@@ -1,23 +1,53 @@
 pub mod node;
 pub mod handshake;
+pub mod command;
+pub mod resources;
+pub mod router;
+pub mod dispatcher;
+pub mod store;
+pub mod websocket;
+pub mod http;
+pub mod harness;
 
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::convert::{From};
-use std::io::Error as IoError;
+use std::io::{Error as IoError, ErrorKind};
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 
-use futures::{stream, Stream, Sink, future, Future, Poll, BoxFuture};
+use futures::{stream, Stream, Sink, future, Async, Future, Poll, BoxFuture};
 
 use tokio_core::io::{Framed, Io};
 use tokio_core::net;
 use tokio_core::reactor;
+#[cfg(unix)]
+use tokio_uds::{UnixListener, UnixStream};
 
 // the locals
 use envelope::{Node, LimeCodec, EnvelopeStream, Envelope};
+use user::UserStore;
+use transport::{Listener, Transport};
 
 // TODO : Refactor to make sense
 pub use self::node::*;
+pub use self::handshake::{Boxed, Handshake, TcpHandshake};
+pub use self::command::CommandRouter;
+pub use self::router::Router;
+pub use self::dispatcher::{Dispatcher, GROUP_DISPATCHED_KEY,
+    GROUP_FAILED_KEY};
+pub use self::store::{MessageStore, MemoryStore, FileStore};
+use self::http::HttpGateway;
+use self::resources::account::AccountHandler;
+use self::resources::presence::{PresenceHandler, PresenceStore};
+use self::resources::contacts::ContactsHandler;
+use self::resources::capability::{CapabilityHandler, CapabilityStore};
+use self::resources::groups::{GroupsHandler, GroupStore};
+use self::resources::threads::{ThreadsHandler, MessageArchive};
+use self::resources::subscriptions::{SubscriptionsHandler,
+    SubscriptionStore};
+use self::resources::delegations::{DelegationsHandler, DelegationStore};
+use envelope::resources::Capability;
 
 pub trait EnvStream: Stream<Item=Envelope, Error=IoError> +
                      Sink<SinkItem=Envelope, SinkError=IoError> {}
@@ -25,9 +55,12 @@ pub trait EnvStream: Stream<Item=Envelope, Error=IoError> +
 impl<S> EnvStream for S where S: Stream<Item=Envelope, Error=IoError> +
                                  Sink<SinkItem=Envelope, SinkError=IoError> {}
 
+/// Stream of a client whatever its transport, see `handshake::Boxed`.
+pub type BoxEnvStream = Box<EnvStream + Send>;
+
 // TODO: Put a Mutex around that ClientSink!
 type NodeMap<S> = Arc<Mutex<HashMap<Node, node::ClientSink<S>>>>;
-type ArcMut<T> = Arc<Mutex<T>>;
+pub type ArcMut<T> = Arc<Mutex<T>>;
 
 /// Generally it will be used to accept incoming connections.
 /// 'L' will be any type of listener, which produces a stream of
@@ -35,35 +68,234 @@ type ArcMut<T> = Arc<Mutex<T>>;
 ///
 /// TODO: Figure out a way to handle online users, is a HashMap optimal?
 pub struct LimeServer<S> {
-    addr: SocketAddr,
+    addr: Option<SocketAddr>,
+    unix_path: Option<PathBuf>,
     users: NodeMap<S>,
     num_threads: usize,
     handles: Vec<reactor::Remote>, // where each handle should be a 
+    registry: ArcMut<UserStore>,
+    presence: ArcMut<PresenceStore>,
+    capabilities: ArcMut<CapabilityStore>,
+    groups: ArcMut<GroupStore>,
+    offline: ArcMut<Box<MessageStore>>,
+    archive: ArcMut<MessageArchive>,
+    subscriptions: ArcMut<SubscriptionStore>,
+    delegations: ArcMut<DelegationStore>,
+    commands: Arc<CommandRouter>,
 }
 
 /// Implementation of the LimeServer. Provides functionality for accepting
 /// connections, and providing Nodes in an un-authenticated state.
 impl<S: EnvStream> LimeServer<S>
 {
-    /// Creates a new server from a TcpListener.
-    /// TODO: Try to figure out Websockets, HTTP etc.
+    /// Creates a new server from a TcpListener, browser clients go through
+    /// `websocket::WsHandshake` instead of `TcpHandshake` and services
+    /// without a session through the `http_gateway`.
     pub fn new(addr: &SocketAddr) -> Self {
+        LimeServer::with_addr(Some(addr.clone()))
+    }
+
+    /// Creates a server only accepting local clients, on the unix socket at
+    /// `path`.
+    #[cfg(unix)]
+    pub fn unix<P: Into<PathBuf>>(path: P) -> Self {
+        LimeServer::with_addr(None).with_unix_socket(path)
+    }
+
+    fn with_addr(addr: Option<SocketAddr>) -> Self {
+        let registry = Arc::new(Mutex::new(UserStore::new()));
+        let presence = Arc::new(Mutex::new(PresenceStore::new()));
+        let capabilities = Arc::new(Mutex::new(CapabilityStore::new()));
+        let groups = Arc::new(Mutex::new(GroupStore::new()));
+        let archive = Arc::new(Mutex::new(MessageArchive::new()));
+        let subscriptions = Arc::new(Mutex::new(SubscriptionStore::new()));
+        let delegations = Arc::new(Mutex::new(DelegationStore::new()));
+        let mut commands = CommandRouter::new();
+        AccountHandler::new(registry.clone()).register(&mut commands);
+        PresenceHandler::new(presence.clone()).register(&mut commands);
+        ContactsHandler::new(registry.clone(), subscriptions.clone())
+            .register(&mut commands);
+        CapabilityHandler::new(Capability::default(), capabilities.clone())
+            .register(&mut commands);
+        GroupsHandler::new(groups.clone()).register(&mut commands);
+        ThreadsHandler::new(archive.clone()).register(&mut commands);
+        SubscriptionsHandler::new(subscriptions.clone(), registry.clone())
+            .register(&mut commands);
+        DelegationsHandler::new(delegations.clone()).register(&mut commands);
+
         LimeServer {
-            addr: addr.clone(),
+            addr: addr,
+            unix_path: None,
             users: Arc::new(Mutex::new(HashMap::new())),
             num_threads: 1,
             handles: Vec::new(), // where each handle should be a 
+            registry: registry,
+            presence: presence,
+            capabilities: capabilities,
+            groups: groups,
+            offline: Arc::new(Mutex::new(Box::new(MemoryStore::new()))),
+            archive: archive,
+            subscriptions: subscriptions,
+            delegations: delegations,
+            commands: Arc::new(commands),
+        }
+    }
+
+    /// Accepts local clients on the unix socket at `path` as well. They can
+    /// authenticate with the `transport` scheme, as the users whose
+    /// `local_uid` runs them.
+    #[cfg(unix)]
+    pub fn with_unix_socket<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.unix_path = Some(path.into());
+        self
+    }
+
+    /// Binds the unix socket of the server, if it has one. A socket left
+    /// behind by a previous run is replaced.
+    #[cfg(unix)]
+    pub fn bind_unix(&self, handle: &reactor::Handle)
+            -> Result<Option<UnixListener>, IoError> {
+        use std::fs;
+        use std::os::unix::fs::FileTypeExt;
+
+        let path = match self.unix_path {
+            Some(ref path) => path,
+            None => return Ok(None),
+        };
+        if let Ok(metadata) = fs::symlink_metadata(path) {
+            if metadata.file_type().is_socket() {
+                fs::remove_file(path)?;
+            }
         }
+        UnixListener::bind(path, handle).map(Some)
+    }
+
+    /// The registered users, shared with the built in command handlers.
+    pub fn registry(&self) -> ArcMut<UserStore> {
+        self.registry.clone()
+    }
+
+    /// Sets where messages for offline identities are kept, in memory unless
+    /// told otherwise.
+    pub fn with_message_store<M>(mut self, store: M) -> Self
+        where M: MessageStore + 'static
+    {
+        self.offline = Arc::new(Mutex::new(Box::new(store)));
+        self
+    }
+
+    /// Router deciding which instance receives envelopes for an identity.
+    pub fn router(&self) -> Router {
+        Router::new(self.presence.clone(), self.registry.clone(),
+                    self.capabilities.clone(), self.groups.clone(),
+                    self.delegations.clone())
+    }
+
+    /// Router used to answer commands sent by established sessions, handlers
+    /// for application specific resources are registered here before `run`.
+    pub fn commands(&mut self) -> &mut CommandRouter {
+        Arc::get_mut(&mut self.commands)
+            .expect("Commands must be registered before the server runs")
+    }
+
+    /// Handles the envelopes of a node once it is authenticated, shared by
+    /// every session and the HTTP gateway.
+    pub fn dispatcher(&self) -> Dispatcher<S> {
+        Dispatcher::new(self.users.clone(), self.commands.clone(),
+                        self.router(), self.offline.clone(),
+                        self.archive.clone(), self.subscriptions.clone())
+    }
+
+    /// Gateway letting services post envelopes over HTTP instead of
+    /// holding a session.
+    pub fn http_gateway(&self) -> HttpGateway<S> {
+        HttpGateway::new(self.registry.clone(), self.dispatcher())
+    }
+
+    /// Adds `node` to the peers with an already established session over
+    /// `io`, the returned session handles what it sends once spawned and
+    /// starts by delivering the messages kept while it was offline.
+    pub fn establish(&self, node: Node, io: S) -> ClientSession<S> {
+        ClientSession::establish(node, io, self.dispatcher())
+    }
+
+    /// Accepts the clients of `listener`, spawning their sessions on
+    /// `handle` once `H` negotiated them and they authenticated. A client
+    /// failing to do so only loses its own connection.
+    pub fn serve<L, H>(&self, listener: L, handle: &reactor::Handle)
+            -> Box<Future<Item=(), Error=IoError>>
+        where L: Listener,
+              H: Handshake<Transport=L::Transport, Stream=S> + Default +
+                 'static,
+              S: 'static
+    {
+        let registry = self.registry.clone();
+        let dispatcher = self.dispatcher();
+        let handle = handle.clone();
+        Box::new(listener.incoming().for_each(move |(conn, _)| {
+            // Known before the handshake, which may wrap the transport.
+            let credentials = conn.peer_credentials();
+            let mut handshake = H::default();
+            handshake.take_stream(conn);
+
+            let (registry, dispatcher) = (registry.clone(), dispatcher.clone());
+            let session = future::poll_fn(move || {
+                    let stream = match handshake.update_handshake()? {
+                        Async::Ready(Some(stream)) => stream,
+                        Async::Ready(None) => return Err(IoError::new(
+                            ErrorKind::UnexpectedEof,
+                            "Closed during the handshake")),
+                        Async::NotReady => return Ok(Async::NotReady),
+                    };
+                    Ok(Async::Ready((stream, handshake.session_id())))
+                })
+                .and_then(move |(stream, id)| {
+                    let conn = ClientConnection::with_credentials(stream,
+                                                                  credentials);
+                    Authentication::new(conn, id, registry)
+                })
+                .and_then(move |(node, io)|
+                          ClientSession::establish(node, io, dispatcher));
+            handle.spawn(session.map_err(|_| ()));
+            Ok(())
+        }))
     }
 
     /// Helper function to run in beginning of run function.
     fn spawn_threads(&mut self) {
 
     }
+}
 
-    /// Consumes and executes the Server
+/// Servers whose clients may come from any transport.
+impl LimeServer<BoxEnvStream> {
+    /// Consumes and executes the Server, serving tcp clients at its address
+    /// and local ones on its unix socket, until a listener fails.
     pub fn run(self) -> Result<(), IoError> {
-        unimplemented!()
+        let mut core = reactor::Core::new()?;
+        let handle = core.handle();
+        let mut listeners = Vec::new();
+        if let Some(addr) = self.addr {
+            let listener = net::TcpListener::bind(&addr, &handle)?;
+            listeners.push(self.serve::<_, Boxed<TcpHandshake>>(listener,
+                                                                 &handle));
+        }
+        if let Some(unix) = self.serve_unix(&handle)? {
+            listeners.push(unix);
+        }
+        core.run(future::join_all(listeners)).map(|_| ())
+    }
+
+    #[cfg(unix)]
+    fn serve_unix(&self, handle: &reactor::Handle)
+            -> Result<Option<Box<Future<Item=(), Error=IoError>>>, IoError> {
+        Ok(self.bind_unix(handle)?.map(|listener| self.serve::<_, Boxed<
+            TcpHandshake<UnixStream>>>(listener, handle)))
     }
 
+    #[cfg(not(unix))]
+    fn serve_unix(&self, _: &reactor::Handle)
+            -> Result<Option<Box<Future<Item=(), Error=IoError>>>, IoError> {
+        Ok(None)
+    }
 }
@@ -1,34 +1,36 @@
-use std::net::SocketAddr;
 use std::convert::From;
-use std::io::Error as IoError;
-use std::collections::{HashMap, VecDeque};
-use std::sync::{Arc, Mutex};
+use std::io::{self, Error as IoError};
+use std::collections::VecDeque;
 
-use futures::{sink, stream, future, sync, Future, BoxFuture, Stream, Sink, Async,
+use futures::{stream, future, Future, BoxFuture, Stream, Sink, Async,
+    AsyncSink,
     Poll,
     StartSend
 };
-use tokio_core::io;
 use tokio_core::io::{Io};
-use tokio_core::net::{TcpStream};
-use tokio_core::reactor::Remote;
 
 use tokio_service::{Service, NewService};
+use base64;
+use serde_json::Value;
 
-use envelope::{Node, LimeCodec, EnvelopeStream, Envelope,
+use envelope::{Node, EnvelopeCodec, EnvelopeStream, Envelope, ErrReason,
+    MsgID,
     Session,
+    identity,
 };
+use envelope::reason::ReasonCode;
 use envelope::session::{
-    EncryptionOptions,
-    CompressionOptions,
+    SessionState,
     SchemeOptions,
 };
-use user::{User};
+use user::UserStore;
+use transport::{PeerCredentials, Transport};
 
-use super::{NodeMap, EnvStream};
-use super::handshake::Handshake;
+use super::{ArcMut, EnvStream};
+use super::dispatcher::Dispatcher;
 
-type EnvFuture = Box<Future<Item=Envelope, Error=IoError> + Send>;
+/// Resolves to the envelope sent back to the client, if any.
+type EnvFuture = Box<Future<Item=Option<Envelope>, Error=IoError> + Send>;
 
 /// A client connection is created per incoming connection.
 ///
@@ -36,7 +38,10 @@ type EnvFuture = Box<Future<Item=Envelope, Error=IoError> + Send>;
 /// Field 'user' pertain to potentially logged in user.
 /// The client connection will be split once authenticated via 'Session'
 /// envelopes.
-pub struct ClientConnection<S> { inner: S }
+pub struct ClientConnection<S> {
+    inner: S,
+    credentials: Option<PeerCredentials>,
+}
 
 /// Implementation
 /// TODO: Create an error type for connection stuff.
@@ -44,7 +49,19 @@ pub struct ClientConnection<S> { inner: S }
 /// -   Either have two error types, one for critical errors w/ system crash & bang
 /// -   Or one error type and pass it up or handle it / panic when deemed appropriate.
 impl<S: EnvStream> ClientConnection<S> {
-    pub fn new(io: S) -> Self { ClientConnection { inner: io } }
+    pub fn new(io: S) -> Self {
+        ClientConnection { inner: io, credentials: None }
+    }
+
+    /// Connection of a client run by the system user behind `credentials`,
+    /// taken from the transport before its handshake.
+    pub fn with_credentials(io: S, credentials: Option<PeerCredentials>)
+            -> Self {
+        ClientConnection { inner: io, credentials: credentials }
+    }
+
+    /// Who runs the client, when the transport tells it.
+    pub fn credentials(&self) -> Option<PeerCredentials> { self.credentials }
 
     pub fn into_inner(self) -> S { self.inner }
 }
@@ -71,38 +88,22 @@ impl<S: EnvStream> Sink for ClientConnection<S> {
     }
 }
 
-/// Future which will evaluate to a properly configured connection.
-///
-/// Handshake, also known as the 'Negotiating' phase of the overall session.
-pub struct TcpHandshake {
-    conn: Option<io::Framed<TcpStream, LimeCodec>>,
-    user_id: Option<Node>,
-    encryption: EncryptionOptions,
-    compression: CompressionOptions,
-}
-
-impl Handshake for TcpHandshake {
-    type Stream = io::Framed<TcpStream, LimeCodec>;
-
-    fn take_stream(&mut self, tcp: TcpStream) {
-        self.conn = Some(tcp.framed(LimeCodec));
-    }
-
-    fn update_handshake(&mut self) -> Poll<Option<Self::Stream>, IoError> {
-        Ok(Async::Ready(self.conn.take()))
-    }
-}
-
 /// This will be the future representing the authentication process.
 ///
-/// TODO: Include a password attempt future which will be a 'helper future' of sorts
+/// The schemes the server can verify are offered to the client: `plain`,
+/// checked against the password of the user, and `transport` for local
+/// clients run by the `local_uid` of the user. Any other scheme, or a client
+/// failing them, fails the session with `SessionAuthenticationFailed`.
 pub struct Authentication<S> {
     conn: Option<ClientConnection<S>>,
-    peers: NodeMap<S>, // TODO: Make this a ref to something more pertinent.
+    users: ArcMut<UserStore>,
+    session_id: Option<MsgID>,
     user_id: Option<Node>,
-    password: String,
+    /// Session sent to the client once the connection takes it.
+    reply: Option<Envelope>,
     authenticated: bool,
-    scheme: SchemeOptions,
+    /// The authentication fails with it once the client was told why.
+    failure: Option<IoError>,
 }
 
 impl<S> Service for Authentication<S> {
@@ -116,48 +117,155 @@ impl<S> Service for Authentication<S> {
     }
 }
 
-impl<S> Authentication<S> {
-    /// TODO: Implement an authentication update thingy.
+/// The password of a `plain` session, sent in base64.
+fn password(session: &Session) -> Option<String> {
+    let password = session.authentication.as_ref()
+        .and_then(Value::as_object)
+        .and_then(|authentication| authentication.get("password"))
+        .and_then(Value::as_str);
+    match password.map(base64::decode) {
+        Some(Ok(bytes)) => String::from_utf8(bytes).ok(),
+        _ => None,
+    }
+}
+
+impl<S: EnvStream> Authentication<S> {
+    /// Authenticates the client of `conn` against the registered `users`.
+    /// Without the id of the session negotiated by the handshake, the
+    /// client must start with a `new` session.
+    pub fn new(conn: ClientConnection<S>, session_id: Option<MsgID>,
+               users: ArcMut<UserStore>) -> Self {
+        let mut auth = Authentication {
+            conn: Some(conn),
+            users: users,
+            session_id: session_id,
+            user_id: None,
+            reply: None,
+            authenticated: false,
+            failure: None,
+        };
+        if let Some(id) = session_id {
+            auth.reply = Some(auth.offer(id));
+        }
+        auth
+    }
+
+    /// The `authenticating` session listing the schemes of the client.
+    fn offer(&self, id: MsgID) -> Envelope {
+        let mut schemes = vec![SchemeOptions::Plain];
+        if self.conn.as_ref().and_then(|c| c.credentials()).is_some() {
+            schemes.push(SchemeOptions::Transport);
+        }
+        let mut offer = Session::new(id, SessionState::Authenticating);
+        offer.scheme_options = Some(schemes.iter()
+            .map(|scheme| Value::String(scheme.as_str().to_owned()))
+            .collect());
+        offer.into()
+    }
+
+    /// Tells the client why its session failed, see `poll`.
+    fn fail(&mut self, id: MsgID, code: ReasonCode, description: &str) {
+        let reason = ErrReason::new(code, description);
+        self.reply = Some(Session::new(id, SessionState::Failed(reason))
+                          .into());
+        let kind = match code {
+            ReasonCode::SessionAuthenticationFailed =>
+                io::ErrorKind::PermissionDenied,
+            _ => io::ErrorKind::InvalidData,
+        };
+        self.failure = Some(IoError::new(kind, description));
+    }
+
+    fn verify(&self, scheme: SchemeOptions, from: &Node, session: &Session)
+            -> bool {
+        let users = self.users.lock().unwrap();
+        let user = match users.get(identity(from)) {
+            Some(user) => user,
+            None => return false,
+        };
+        let password = password(session);
+        let credentials = self.conn.as_ref().and_then(|c| c.credentials());
+        user.authenticate(scheme, password.as_ref().map(|p| &**p),
+                          credentials.as_ref())
+    }
+
+    /// Authenticates with the session sent by the client.
     pub fn update_auth(&mut self, envelope: Session) {
-        self.authenticated = true;
+        let id = match self.session_id {
+            Some(id) => id,
+            None if envelope.state == SessionState::New => {
+                self.session_id = Some(envelope.id);
+                self.reply = Some(self.offer(envelope.id));
+                return;
+            }
+            None => return self.fail(envelope.id,
+                                     ReasonCode::SessionInvalidActionForState,
+                                     "The session must start as new"),
+        };
+        if envelope.state != SessionState::Authenticating {
+            return self.fail(id, ReasonCode::SessionInvalidActionForState,
+                             "The session must be authenticating");
+        }
+
+        let scheme = envelope.scheme.as_ref().and_then(Value::as_str)
+            .and_then(|scheme| scheme.parse().ok());
+        self.authenticated = match (scheme, envelope.from.as_ref()) {
+            (Some(scheme), Some(from)) => self.verify(scheme, from, &envelope),
+            _ => false,
+        };
+        if !self.authenticated {
+            return self.fail(id, ReasonCode::SessionAuthenticationFailed,
+                             "The client could not be authenticated");
+        }
+        let mut established = Session::new(id, SessionState::Established);
+        established.to = envelope.from.clone();
+        self.reply = Some(established.into());
+        self.user_id = envelope.from;
     }
 }
 
 impl<S: EnvStream> Future for Authentication<S> {
-    type Item = (ClientSink<S>, ClientSession<S>);
+    /// The node authenticated and its connection, ready to be established.
+    type Item = (Node, S);
     type Error = IoError;
 
     /// This is where some sort of database query would occur.
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        match self.conn.as_mut().unwrap().poll() {
-            Ok(Async::Ready(Some(env))) => {
-                if let Envelope::Session(s) = env {
-                    self.update_auth(s);
-                } else {
-                    panic!("Received non-session envelope \
-                           during session authentication.");
-                };
-            },
-            Ok(Async::Ready(None)) => panic!("Implement EOF during authentication"),
-            Ok(Async::NotReady) => return Ok(Async::NotReady),
-            Err(_) => panic!("Error envelope from stream \
-                                    during Authentication"),
-        };
-
-        if self.authenticated {
-            let conn = self.conn.take().unwrap().into_inner();
-            let (sink, stream) = conn.split();
-            Ok(Async::Ready((
-                ClientSink::new(sink),
-                ClientSession {
-                    inner: stream,
-                    user_id: self.user_id.take().unwrap(),
-                    user: User,
-                    peers: self.peers.clone(),
+        loop {
+            {
+                let conn = self.conn.as_mut().unwrap();
+                if let Some(reply) = self.reply.take() {
+                    if let AsyncSink::NotReady(reply) =
+                            conn.start_send(reply)? {
+                        self.reply = Some(reply);
+                        return Ok(Async::NotReady);
+                    }
                 }
-            )))
-        } else {
-            Ok(Async::NotReady)
+                if conn.poll_complete()?.is_not_ready() {
+                    return Ok(Async::NotReady);
+                }
+            }
+            if let Some(err) = self.failure.take() {
+                return Err(err);
+            }
+            if self.authenticated {
+                let conn = self.conn.take().unwrap().into_inner();
+                return Ok(Async::Ready((self.user_id.take().unwrap(), conn)));
+            }
+
+            match self.conn.as_mut().unwrap().poll()? {
+                Async::Ready(Some(Envelope::Session(session))) =>
+                    self.update_auth(session),
+                Async::Ready(Some(envelope)) => {
+                    let id = self.session_id.or(envelope.id()).unwrap_or(0);
+                    self.fail(id, ReasonCode::SessionInvalidActionForState,
+                              "Only sessions are sent until it is established")
+                }
+                Async::Ready(None) => return Err(IoError::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Closed during the authentication")),
+                Async::NotReady => return Ok(Async::NotReady),
+            }
         }
     }
 }
@@ -169,109 +277,150 @@ impl<S: EnvStream> Future for Authentication<S> {
 pub struct ClientSession<S> {
     inner: stream::SplitStream<S>,
     user_id: Node,
-    user: User,
-    peers: NodeMap<S>,
+    dispatcher: Dispatcher<S>,
+    /// Whether the messages kept while the identity was offline were
+    /// delivered, which happens on the first poll.
+    offline_delivered: bool,
 }
 
 /// Service implementation for the 'ClientSession' struct.
 ///
 /// ClientSession implements the Service trait to avoid having a blocking event
 /// occur on the stream of incoming messages when not necessary.
-impl<S> Service for ClientSession<S> {
+impl<S: EnvStream> Service for ClientSession<S> {
     type Request = Envelope;
-    type Response = Envelope;
+    type Response = Option<Envelope>;
     type Error = IoError;
     type Future = EnvFuture;
 
     fn call(&self, req: Envelope) -> Self::Future {
-        unimplemented!()
+        match req {
+            Envelope::Command(cmd) => {
+                let res = self.dispatcher.dispatch(&self.user_id, cmd);
+                future::ok(Some(res.into())).boxed()
+            }
+            Envelope::Session(ref session)
+                    if session.state == SessionState::Finishing => {
+                self.finish();
+                future::ok(None).boxed()
+            }
+            Envelope::Message(msg) => {
+                let notification = self.dispatcher
+                    .route_message(&self.user_id, msg);
+                future::ok(notification.map(|n| n.into())).boxed()
+            }
+            Envelope::Notification(notification) => {
+                self.dispatcher.route_notification(&self.user_id,
+                                                   notification);
+                future::ok(None).boxed()
+            }
+            // Sessions are only negotiated once, anything but finishing it
+            // is ignored after that, just like unknown envelopes.
+            Envelope::Session(_) | Envelope::Unknown(_) =>
+                future::ok(None).boxed(),
+        }
     }
 }
 
-impl<S> ClientSession<S> {
-    pub fn new(io: S) -> Self {
-        panic!()
+impl<S: EnvStream> ClientSession<S> {
+    /// Releases what the session holds on the server once it is finishing.
+    pub fn finish(&self) {
+        self.dispatcher.finish(&self.user_id);
     }
-}
 
-/// Designed to make it easier to send over a connection / channel.
-/// Not sure what else.
-pub struct ClientSink<S> {
-    inner: Mutex<Option<stream::SplitSink<S>>>,
-    queue: VecDeque<Envelope>,
-    handle: Remote,
-}
+    /// Delivers, in order, the messages kept while the identity was offline,
+    /// see `Dispatcher::deliver_offline`.
+    pub fn deliver_offline(&self) -> Result<(), IoError> {
+        self.dispatcher.deliver_offline(&self.user_id)
+    }
+
+    /// Adds `node` to the peers of the dispatcher with an already
+    /// established session over `io`, see `LimeServer::establish`.
+    pub fn establish(node: Node, io: S, dispatcher: Dispatcher<S>) -> Self {
+        let (sink, stream) = io.split();
+        dispatcher.add_peer(node.clone(), ClientSink::new(sink));
+        ClientSession::new(node, stream, dispatcher)
+    }
 
-/// Runs a future where it's goal is to return the `Stream`.
-struct SinkCourier<S> {
-    cur: Option<stream::SplitSink<S>>,
-    inner: Arc<Mutex<Option<stream::SplitSink<S>>>>,
+    /// Session of `node` reading from `io`, the node must already be a peer
+    /// of the dispatcher to receive anything, see `LimeServer::establish`.
+    pub fn new(node: Node, io: stream::SplitStream<S>,
+               dispatcher: Dispatcher<S>) -> Self {
+        ClientSession {
+            inner: io,
+            user_id: node,
+            dispatcher: dispatcher,
+            offline_delivered: false,
+        }
+    }
 }
 
-impl<S: EnvStream> Future for SinkCourier<S> {
+/// Handles the envelopes of the session, answering them on its sink, until
+/// the connection is closed.
+impl<S: EnvStream> Future for ClientSession<S> {
     type Item = ();
     type Error = IoError;
 
-    /// This is where some sort of database query would occur.
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        match self.cur.as_mut().unwrap().poll() {
-            Ok(Async::Ready(Some(env))) => {
-            },
-            Ok(Async::Ready(None)) => panic!("Implement EOF during authentication"),
-            Ok(Async::NotReady) => return Ok(Async::NotReady),
-            Err(_) => panic!("Error envelope from stream \
-                                    during Authentication"),
-        };
+        if !self.offline_delivered {
+            self.deliver_offline()?;
+            self.offline_delivered = true;
+        }
+        self.dispatcher.flush(&self.user_id)?;
+        loop {
+            match self.inner.poll()? {
+                Async::Ready(Some(env)) => {
+                    if let Some(res) = self.call(env).wait()? {
+                        self.dispatcher.reply(&self.user_id, res);
+                    }
+                }
+                Async::Ready(None) => {
+                    self.finish();
+                    return Ok(Async::Ready(()));
+                }
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
     }
 }
 
-impl<S: EnvStream> ClientSink<S> {
-    type Sender = sink::Send<stream::SplitSink<S>>;
+/// Sending half of an established session, envelopes the connection can't
+/// take yet are queued until it can.
+pub struct ClientSink<S> {
+    inner: stream::SplitSink<S>,
+    queue: VecDeque<Envelope>,
+}
 
+impl<S: EnvStream> ClientSink<S> {
     pub fn new(io: stream::SplitSink<S>) -> Self {
         ClientSink {
-            inner: Arc::new(Mutex::new(Some(io))),
+            inner: io,
             queue: VecDeque::new(),
         }
     }
 
-    /// Attempts to send the next message stored in a queue, returning 
-    pub fn try_send_next(&mut self) -> bool {
-        if self.queue.is_empty() { return false; }
-        match self.inner.take() {
-            Some(sink) => {
-                let send = sink.send(self.queue.pop_front().unwrap());
-                let send = send.and_then(|sink| {
-
-                });
-                self.handle.spawn();
-            },
-            None => false,
+    /// Hands the queued envelopes to the connection and flushes it. Whatever
+    /// the connection couldn't take is sent on the next poll of the session.
+    pub fn poll_flush(&mut self) -> Poll<(), IoError> {
+        while let Some(msg) = self.queue.pop_front() {
+            if let AsyncSink::NotReady(msg) = self.inner.start_send(msg)? {
+                self.queue.push_front(msg);
+                self.inner.poll_complete()?;
+                return Ok(Async::NotReady);
+            }
         }
+        self.inner.poll_complete()
     }
 
-    /// Attempts to send an envelope over the internal `Sink` struct. Fails if
-    /// the `Sink` is currently occupied.
-    fn send_next(&mut self, msg: Envelope)
-        -> Option<sink::Send<stream::SplitSink<S>>>
-    {
-        match self.inner.take() {
-            Some(sink) => {
-                let msg = if let Some(env) = self.queue.pop_front() {
-                    self.queue.push_back(msg);
-                    env
-                } else { msg }
-                sink.send(msg)
-            },  // sends either the given or next available message.
-            None => {
-                queue.push_back(msg);
-                None
-            },
-        } 
-    }
-
-    /// Guarantees the delivery of an envelope via an internal `VecDeque`.
+    /// Guarantees the delivery of an envelope via an internal `VecDeque`, as
+    /// long as the connection is alive.
     pub fn send_envelope(&mut self, msg: Envelope) {
+        self.queue.push_back(msg);
+        if self.poll_flush().is_err() {
+            // The connection is gone, the session is finished once its
+            // stream ends.
+            self.queue.clear();
+        }
     }
 }
 
@@ -280,17 +429,19 @@ impl<S: EnvStream> ClientSink<S> {
 /// 'Io'
 impl<S: EnvStream> From<S> for ClientConnection<S> {
     fn from(io: S) -> Self {
-        ClientConnection { inner: io }
+        ClientConnection::new(io)
     }
 }
 
 /// 'Io'
-impl From<(TcpStream, SocketAddr)>
-        for ClientConnection<EnvelopeStream<TcpStream>> {
-    fn from(connection: (TcpStream, SocketAddr)) -> Self {
+impl<T: Transport> From<(T, T::Addr)> for ClientConnection<EnvelopeStream<T>> {
+    fn from(connection: (T, T::Addr)) -> Self {
         let (stream, _) = connection;
-        let stream = stream.framed(LimeCodec);
-        ClientConnection { inner: stream }
+        let credentials = stream.peer_credentials();
+        ClientConnection {
+            inner: stream.framed(EnvelopeCodec::negotiate()),
+            credentials: credentials,
+        }
     }
 }
 
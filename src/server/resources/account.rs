@@ -0,0 +1,73 @@
+use serde_json;
+
+use envelope::{ErrReason, identity};
+use envelope::command::CommandMethod;
+use envelope::command::CommandMethod::*;
+use envelope::reason::ReasonCode;
+use envelope::resources::{Account, ACCOUNT_MIME};
+use user::UserStore;
+
+use server::ArcMut;
+use server::command::{CommandHandler, CommandResult, CommandRouter, Request,
+    Response};
+use super::{resource, not_found};
+
+/// Handles `get`, `set` and `delete` on `/account` for the caller's identity.
+pub struct AccountHandler {
+    users: ArcMut<UserStore>,
+}
+
+impl AccountHandler {
+    pub fn new(users: ArcMut<UserStore>) -> Self {
+        AccountHandler { users: users }
+    }
+
+    /// Registers the handler on the router for every supported method.
+    pub fn register(self, router: &mut CommandRouter) {
+        router.register_all(&[Get, Set, Delete], "/account", self);
+    }
+
+    fn get(&self, id: &str) -> CommandResult {
+        let users = self.users.lock().unwrap();
+        let user = users.get(id).ok_or_else(not_found)?;
+        Ok(Response::resource(ACCOUNT_MIME,
+                              serde_json::to_value(&user.account)))
+    }
+
+    /// Updates the account of a registered user, the password is only
+    /// changed if `oldPassword` matches the current one, if there is one.
+    fn set(&self, id: &str, mut account: Account) -> CommandResult {
+        let mut users = self.users.lock().unwrap();
+        let user = users.get_mut(id).ok_or_else(not_found)?;
+
+        if let Some(password) = account.password.take() {
+            let old_password = account.old_password.take();
+            if user.password.is_some() &&
+                    !user.verify_password(old_password.as_ref().map(|s| &**s)) {
+                return Err(ErrReason::new(ReasonCode::CommandNotAllowed,
+                                          "The old password is invalid"));
+            }
+            user.set_password(&password);
+        }
+        user.account.merge(account);
+        Ok(Response::empty())
+    }
+
+    fn delete(&self, id: &str) -> CommandResult {
+        let mut users = self.users.lock().unwrap();
+        users.remove(id).ok_or_else(not_found)?;
+        Ok(Response::empty())
+    }
+}
+
+impl CommandHandler for AccountHandler {
+    fn handle(&self, req: &Request) -> CommandResult {
+        let id = identity(req.caller);
+        match req.command.method {
+            CommandMethod::Get => self.get(id),
+            CommandMethod::Set => self.set(id, resource(req)?),
+            CommandMethod::Delete => self.delete(id),
+            _ => unreachable!(),
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+
+use serde_json;
+
+use envelope::Node;
+use envelope::command::CommandMethod;
+use envelope::command::CommandMethod::*;
+use envelope::resources::{Capability, CAPABILITY_MIME};
+
+use server::ArcMut;
+use server::command::{CommandHandler, CommandResult, CommandRouter, Request,
+    Response};
+use super::resource;
+
+/// Capabilities declared by each connected node. Nodes which never declared
+/// any are assumed to accept everything.
+#[derive(Debug, Default)]
+pub struct CapabilityStore {
+    nodes: HashMap<Node, Capability>,
+}
+
+impl CapabilityStore {
+    pub fn new() -> Self {
+        CapabilityStore { nodes: HashMap::new() }
+    }
+
+    pub fn get(&self, node: &str) -> Option<&Capability> {
+        self.nodes.get(node)
+    }
+
+    pub fn set(&mut self, node: &str, capability: Capability) {
+        self.nodes.insert(node.to_owned(), capability);
+    }
+
+    pub fn remove(&mut self, node: &str) -> Option<Capability> {
+        self.nodes.remove(node)
+    }
+
+    /// Whether a message of the given type may be dispatched to the node.
+    pub fn accepts_content(&self, node: &str, mime_type: &str) -> bool {
+        self.nodes.get(node).map_or(true, |c| c.supports_content(mime_type))
+    }
+}
+
+/// Handles `get` and `set` on `/capability`.
+///
+/// `get` returns what the server supports, `set` stores what the caller
+/// supports and answers with the types both sides have in common.
+pub struct CapabilityHandler {
+    server: Capability,
+    nodes: ArcMut<CapabilityStore>,
+}
+
+impl CapabilityHandler {
+    pub fn new(server: Capability, nodes: ArcMut<CapabilityStore>) -> Self {
+        CapabilityHandler {
+            server: server,
+            nodes: nodes,
+        }
+    }
+
+    pub fn register(self, router: &mut CommandRouter) {
+        router.register_all(&[Get, Set], "/capability", self);
+    }
+}
+
+impl CommandHandler for CapabilityHandler {
+    fn handle(&self, req: &Request) -> CommandResult {
+        let capability = match req.command.method {
+            CommandMethod::Get => self.server.clone(),
+            CommandMethod::Set => {
+                let declared: Capability = resource(req)?;
+                let negotiated = self.server.intersect(&declared);
+                self.nodes.lock().unwrap().set(req.caller, declared);
+                negotiated
+            }
+            _ => unreachable!(),
+        };
+        Ok(Response::resource(CAPABILITY_MIME,
+                              serde_json::to_value(&capability)))
+    }
+}
@@ -0,0 +1,124 @@
+use serde_json;
+
+use envelope::{ErrReason, identity};
+use envelope::command::CommandMethod;
+use envelope::command::CommandMethod::*;
+use envelope::reason::ReasonCode;
+use envelope::resources::{Collection, Contact, CONTACT_MIME, COLLECTION_MIME};
+use user::UserStore;
+
+use server::ArcMut;
+use server::command::{CommandHandler, CommandResult, CommandRouter, Request,
+    Response};
+use server::resources::subscriptions::{OBSERVABLE, SubscriptionStore};
+use super::{resource, not_found};
+
+/// Handles the caller's roster on `/contacts` and `/contacts/{identity}`.
+///
+/// The subscriptions of a contact to the owner's resources are revoked
+/// when the contact is removed or the resources are no longer shared.
+pub struct ContactsHandler {
+    users: ArcMut<UserStore>,
+    subscriptions: ArcMut<SubscriptionStore>,
+}
+
+impl ContactsHandler {
+    pub fn new(users: ArcMut<UserStore>,
+               subscriptions: ArcMut<SubscriptionStore>) -> Self {
+        ContactsHandler {
+            users: users,
+            subscriptions: subscriptions,
+        }
+    }
+
+    pub fn register(self, router: &mut CommandRouter) {
+        let handler = ContactsHandler::new(self.users.clone(),
+                                           self.subscriptions.clone());
+        router.register_all(&[Get, Set], "/contacts", self);
+        router.register_all(&[Get, Delete], "/contacts/{identity}", handler);
+    }
+
+    fn list(&self, owner: &str) -> CommandResult {
+        let users = self.users.lock().unwrap();
+        let items = users.get(owner)
+            .map(|user| {
+                user.contacts.values().map(serde_json::to_value).collect()
+            })
+            .unwrap_or_else(Vec::new);
+        let collection = Collection::new(CONTACT_MIME, items);
+        Ok(Response::resource(COLLECTION_MIME,
+                              serde_json::to_value(&collection)))
+    }
+
+    fn get(&self, owner: &str, id: &str) -> CommandResult {
+        let users = self.users.lock().unwrap();
+        let contact = users.get(owner)
+            .and_then(|user| user.contacts.get(id))
+            .ok_or_else(not_found)?;
+        Ok(Response::resource(CONTACT_MIME, serde_json::to_value(contact)))
+    }
+
+    /// Adds or updates a contact. The contact stays pending until the other
+    /// user adds the owner to their own roster.
+    fn set(&self, owner: &str, mut contact: Contact) -> CommandResult {
+        if contact.identity.is_empty() || contact.identity == owner {
+            return Err(ErrReason::new(ReasonCode::CommandInvalidArgument,
+                                      "Invalid contact identity"));
+        }
+        let mut users = self.users.lock().unwrap();
+        let mutual = match users.get_mut(&contact.identity) {
+            Some(other) => match other.contacts.get_mut(owner) {
+                Some(reverse) => { reverse.is_pending = Some(false); true },
+                None => false,
+            },
+            None => false,
+        };
+        contact.is_pending = Some(!mutual);
+        let unshared: Vec<&str> = OBSERVABLE.iter().cloned()
+            .filter(|path| match *path {
+                "/presence" => !contact.shares_presence(),
+                "/account" => !contact.shares_account_info(),
+                _ => false,
+            })
+            .collect();
+        let id = contact.identity.clone();
+        users.get_mut(owner).ok_or_else(not_found)?
+            .contacts.insert(id.clone(), contact);
+
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        for path in unshared {
+            subscriptions.revoke(owner, &id, path);
+        }
+        Ok(Response::empty())
+    }
+
+    fn delete(&self, owner: &str, id: &str) -> CommandResult {
+        let mut users = self.users.lock().unwrap();
+        users.get_mut(owner)
+            .and_then(|user| user.contacts.remove(id))
+            .ok_or_else(not_found)?;
+        if let Some(reverse) = users.get_mut(id)
+                .and_then(|other| other.contacts.get_mut(owner)) {
+            reverse.is_pending = Some(true);
+        }
+
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        for path in OBSERVABLE {
+            subscriptions.revoke(owner, id, path);
+        }
+        Ok(Response::empty())
+    }
+}
+
+impl CommandHandler for ContactsHandler {
+    fn handle(&self, req: &Request) -> CommandResult {
+        let owner = identity(req.caller);
+        match (req.command.method, req.params.get("identity")) {
+            (CommandMethod::Get, None) => self.list(owner),
+            (CommandMethod::Set, None) => self.set(owner, resource(req)?),
+            (CommandMethod::Get, Some(id)) => self.get(owner, id),
+            (CommandMethod::Delete, Some(id)) => self.delete(owner, id),
+            _ => unreachable!(),
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde_json;
+
+use envelope::{EnvelopeType, Node, identity};
+use envelope::command::CommandMethod;
+use envelope::command::CommandMethod::*;
+use envelope::resources::{Collection, Delegation, COLLECTION_MIME,
+    DELEGATION_MIME};
+
+use server::ArcMut;
+use server::command::{CommandHandler, CommandResult, CommandRouter, Request,
+    Response};
+use super::{resource, not_found};
+
+/// Resources which are never managed on behalf of their owner, whatever
+/// the delegation, since they would let the target take over the identity
+/// or read its conversations.
+pub static PRIVATE: &'static [&'static str] =
+    &["/account", "/contacts", "/delegations", "/groups", "/threads"];
+
+/// Whether commands on `path` may be sent on behalf of another identity.
+pub fn is_delegable(path: &str) -> bool {
+    !PRIVATE.iter().any(|private| {
+        path.starts_with(private) &&
+            (path.len() == private.len() ||
+             path[private.len()..].starts_with('/'))
+    })
+}
+
+/// The delegations given by each identity, keyed by their target.
+#[derive(Default)]
+pub struct DelegationStore {
+    delegations: HashMap<Node, BTreeMap<Node, Delegation>>,
+}
+
+impl DelegationStore {
+    pub fn new() -> Self {
+        DelegationStore { delegations: HashMap::new() }
+    }
+
+    /// Adds the delegation, replacing the one given to the same target.
+    pub fn set(&mut self, owner: &str, delegation: Delegation) {
+        self.delegations.entry(identity(owner).to_owned())
+            .or_insert_with(BTreeMap::new)
+            .insert(delegation.target.clone(), delegation);
+    }
+
+    pub fn get(&self, owner: &str, target: &str) -> Option<&Delegation> {
+        self.delegations.get(identity(owner))
+            .and_then(|delegations| delegations.get(target))
+    }
+
+    pub fn delegations(&self, owner: &str) -> Vec<&Delegation> {
+        self.delegations.get(identity(owner))
+            .map(|delegations| delegations.values().collect())
+            .unwrap_or_else(Vec::new)
+    }
+
+    pub fn remove(&mut self, owner: &str, target: &str) -> Option<Delegation> {
+        self.delegations.get_mut(identity(owner))
+            .and_then(|delegations| delegations.remove(target))
+    }
+
+    /// Whether `sender` may send envelopes of this type as `owner`, which an
+    /// identity may always do for itself.
+    pub fn allows(&self, owner: &str, sender: &str, kind: EnvelopeType)
+            -> bool {
+        if identity(owner) == identity(sender) { return true; }
+        [sender, identity(sender)].iter()
+            .filter_map(|target| self.get(owner, target))
+            .any(|delegation| delegation.allows(kind))
+    }
+}
+
+/// Handles `get` and `set` on `/delegations` and `get` and `delete` on
+/// `/delegations/{target}`, for the delegations given by the caller.
+pub struct DelegationsHandler {
+    delegations: ArcMut<DelegationStore>,
+}
+
+impl DelegationsHandler {
+    pub fn new(delegations: ArcMut<DelegationStore>) -> Self {
+        DelegationsHandler { delegations: delegations }
+    }
+
+    pub fn register(self, router: &mut CommandRouter) {
+        let delegations = self.delegations.clone();
+        router.register_all(&[Get, Set], "/delegations", self);
+        router.register_all(&[Get, Delete], "/delegations/{target}",
+                            DelegationsHandler::new(delegations));
+    }
+}
+
+impl CommandHandler for DelegationsHandler {
+    fn handle(&self, req: &Request) -> CommandResult {
+        let owner = identity(req.caller);
+        let mut delegations = self.delegations.lock().unwrap();
+
+        match (req.params.get("target"), req.command.method) {
+            (None, CommandMethod::Get) => {
+                let items = delegations.delegations(owner).into_iter()
+                    .map(serde_json::to_value).collect();
+                let collection = Collection::new(DELEGATION_MIME, items);
+                Ok(Response::resource(COLLECTION_MIME,
+                                      serde_json::to_value(&collection)))
+            }
+            (None, _) => {
+                delegations.set(owner, resource(req)?);
+                Ok(Response::empty())
+            }
+            (Some(target), CommandMethod::Get) => {
+                let delegation = delegations.get(owner, target)
+                    .ok_or_else(not_found)?;
+                Ok(Response::resource(DELEGATION_MIME,
+                                      serde_json::to_value(delegation)))
+            }
+            (Some(target), _) => {
+                delegations.remove(owner, target).ok_or_else(not_found)?;
+                Ok(Response::empty())
+            }
+        }
+    }
+}
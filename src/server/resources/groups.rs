@@ -0,0 +1,225 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde_json::{self, Value};
+
+use envelope::{ErrReason, Node, identity, is_group};
+use envelope::command::CommandMethod;
+use envelope::command::CommandMethod::*;
+use envelope::reason::ReasonCode;
+use envelope::resources::{Collection, Group, GroupMember, GroupRole,
+    COLLECTION_MIME, GROUP_MIME, GROUP_MEMBER_MIME};
+
+use server::ArcMut;
+use server::command::{CommandHandler, CommandResult, CommandRouter, Request,
+    Response};
+use super::{resource, not_found};
+
+struct GroupEntry {
+    group: Group,
+    members: BTreeMap<Node, GroupMember>,
+}
+
+/// Every group, keyed by the group's identity.
+#[derive(Default)]
+pub struct GroupStore {
+    groups: HashMap<Node, GroupEntry>,
+}
+
+impl GroupStore {
+    pub fn new() -> Self {
+        GroupStore { groups: HashMap::new() }
+    }
+
+    pub fn get(&self, group: &str) -> Option<&Group> {
+        self.groups.get(identity(group)).map(|entry| &entry.group)
+    }
+
+    /// Identities of the group members, `None` if the group doesn't exist.
+    pub fn members(&self, group: &str) -> Option<Vec<Node>> {
+        self.groups.get(identity(group))
+            .map(|entry| entry.members.keys().cloned().collect())
+    }
+
+    pub fn role(&self, group: &str, member: &str) -> Option<GroupRole> {
+        self.groups.get(identity(group))
+            .and_then(|entry| entry.members.get(identity(member)))
+            .map(GroupMember::role)
+    }
+
+    pub fn is_member(&self, group: &str, member: &str) -> bool {
+        self.role(group, member).is_some()
+    }
+
+    /// Groups the identity is a member of.
+    pub fn groups_of(&self, member: &str) -> Vec<&Group> {
+        let mut groups: Vec<&Group> = self.groups.values()
+            .filter(|entry| entry.members.contains_key(identity(member)))
+            .map(|entry| &entry.group)
+            .collect();
+        groups.sort_by(|a, b| a.identity.cmp(&b.identity));
+        groups
+    }
+
+    /// Creates the group, the owner becomes its first member. Groups are
+    /// kept under their identity, without any instance.
+    pub fn create(&mut self, mut group: Group, owner: &str)
+            -> Result<(), ErrReason> {
+        group.identity = identity(&group.identity).to_owned();
+        if !is_group(&group.identity) {
+            return Err(ErrReason::new(ReasonCode::CommandInvalidArgument,
+                                      "Groups must be under a group domain"));
+        }
+        if self.groups.contains_key(&group.identity) {
+            return Err(ErrReason::new(ReasonCode::CommandNotAllowed,
+                                      "The group already exists"));
+        }
+
+        let owner = identity(owner).to_owned();
+        group.owner = Some(owner.clone());
+        let mut members = BTreeMap::new();
+        members.insert(owner.clone(), GroupMember {
+            address: owner,
+            role: Some(GroupRole::Owner),
+        });
+        self.groups.insert(group.identity.clone(), GroupEntry {
+            group: group,
+            members: members,
+        });
+        Ok(())
+    }
+
+    pub fn delete(&mut self, group: &str) -> Option<Group> {
+        self.groups.remove(identity(group)).map(|entry| entry.group)
+    }
+
+    pub fn add_member(&mut self, group: &str, member: GroupMember) {
+        if let Some(entry) = self.groups.get_mut(identity(group)) {
+            entry.members.insert(identity(&member.address).to_owned(), member);
+        }
+    }
+
+    pub fn remove_member(&mut self, group: &str, member: &str)
+            -> Option<GroupMember> {
+        self.groups.get_mut(identity(group))
+            .and_then(|entry| entry.members.remove(identity(member)))
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Path {
+    Groups,
+    Group,
+    Members,
+    Member,
+}
+
+/// Handles `/groups`, `/groups/{id}`, `/groups/{id}/members` and
+/// `/groups/{id}/members/{member}`.
+///
+/// Only members may read a group, only owners and moderators may change its
+/// members and only the owner may delete it.
+pub struct GroupsHandler {
+    groups: ArcMut<GroupStore>,
+    path: Path,
+}
+
+impl GroupsHandler {
+    pub fn new(groups: ArcMut<GroupStore>) -> Self {
+        GroupsHandler {
+            groups: groups,
+            path: Path::Groups,
+        }
+    }
+
+    pub fn register(self, router: &mut CommandRouter) {
+        let at = |path| GroupsHandler {
+            groups: self.groups.clone(),
+            path: path,
+        };
+        router.register_all(&[Get, Set], "/groups", at(Path::Groups));
+        router.register_all(&[Get, Delete], "/groups/{id}", at(Path::Group));
+        router.register_all(&[Get, Set], "/groups/{id}/members",
+                            at(Path::Members));
+        router.register_all(&[Get, Delete], "/groups/{id}/members/{member}",
+                            at(Path::Member));
+    }
+}
+
+fn not_allowed() -> ErrReason {
+    ErrReason::new(ReasonCode::CommandNotAllowed,
+                   "The caller is not allowed to do this on the group")
+}
+
+fn collection(item_type: &str, items: Vec<Value>) -> CommandResult {
+    let collection = Collection::new(item_type, items);
+    Ok(Response::resource(COLLECTION_MIME, serde_json::to_value(&collection)))
+}
+
+impl CommandHandler for GroupsHandler {
+    fn handle(&self, req: &Request) -> CommandResult {
+        let caller = identity(req.caller);
+        let mut groups = self.groups.lock().unwrap();
+
+        let id = match self.path {
+            Path::Groups => return match req.command.method {
+                CommandMethod::Get => collection(GROUP_MIME,
+                    groups.groups_of(caller).into_iter()
+                          .map(serde_json::to_value).collect()),
+                _ => {
+                    groups.create(resource(req)?, caller)?;
+                    Ok(Response::empty())
+                }
+            },
+            _ => &req.params["id"],
+        };
+
+        // Everything below a group needs the caller to be one of its members.
+        groups.get(id).ok_or_else(not_found)?;
+        let role = groups.role(id, caller).ok_or_else(not_allowed)?;
+
+        match (self.path, req.command.method) {
+            (Path::Group, CommandMethod::Get) => {
+                let group = serde_json::to_value(groups.get(id).unwrap());
+                Ok(Response::resource(GROUP_MIME, group))
+            }
+            (Path::Group, CommandMethod::Delete) => {
+                if role != GroupRole::Owner { return Err(not_allowed()); }
+                groups.delete(id);
+                Ok(Response::empty())
+            }
+            (Path::Members, CommandMethod::Get) => {
+                let members = groups.groups[identity(id)].members.values()
+                    .map(serde_json::to_value).collect();
+                collection(GROUP_MEMBER_MIME, members)
+            }
+            (Path::Members, CommandMethod::Set) => {
+                let member: GroupMember = resource(req)?;
+                if !role.can_manage() || member.role() == GroupRole::Owner {
+                    return Err(not_allowed());
+                }
+                groups.add_member(id, member);
+                Ok(Response::empty())
+            }
+            (Path::Member, CommandMethod::Get) => {
+                let member = &req.params["member"];
+                let member = groups.groups[identity(id)].members
+                    .get(identity(member)).ok_or_else(not_found)?;
+                Ok(Response::resource(GROUP_MEMBER_MIME,
+                                      serde_json::to_value(member)))
+            }
+            (Path::Member, CommandMethod::Delete) => {
+                // Members may leave on their own, the owner never leaves.
+                let member = &req.params["member"];
+                if !role.can_manage() && identity(member) != caller {
+                    return Err(not_allowed());
+                }
+                if groups.role(id, member) == Some(GroupRole::Owner) {
+                    return Err(not_allowed());
+                }
+                groups.remove_member(id, member).ok_or_else(not_found)?;
+                Ok(Response::empty())
+            }
+            _ => unreachable!(),
+        }
+    }
+}
@@ -0,0 +1,37 @@
+//! Command handlers for the resources the server manages itself.
+
+pub mod account;
+pub mod presence;
+pub mod contacts;
+pub mod capability;
+pub mod groups;
+pub mod threads;
+pub mod subscriptions;
+pub mod delegations;
+
+use serde::Deserialize;
+use serde_json;
+
+use envelope::ErrReason;
+use envelope::reason::ReasonCode;
+
+use super::command::Request;
+
+/// Reads the command's resource as `T`, failing the command if it is missing
+/// or malformed.
+fn resource<T: Deserialize>(req: &Request) -> Result<T, ErrReason> {
+    let value = match req.command.resource {
+        Some(ref value) => value.clone(),
+        None => return Err(ErrReason::new(ReasonCode::CommandInvalidArgument,
+                                          "The command has no resource")),
+    };
+    serde_json::from_value(value).map_err(|_| {
+        ErrReason::new(ReasonCode::CommandInvalidArgument,
+                       "The resource is malformed")
+    })
+}
+
+fn not_found() -> ErrReason {
+    ErrReason::new(ReasonCode::CommandResourceNotFound,
+                   "The resource was not found")
+}
@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+
+use serde_json;
+
+use envelope::{Node, identity};
+use envelope::command::CommandMethod;
+use envelope::command::CommandMethod::*;
+use envelope::resources::{Presence, PRESENCE_MIME};
+
+use server::ArcMut;
+use server::command::{CommandHandler, CommandResult, CommandRouter, Request,
+    Response};
+use super::{resource, not_found};
+
+/// Presence of every connected node, keyed by the full node (with instance).
+#[derive(Debug, Default)]
+pub struct PresenceStore {
+    nodes: HashMap<Node, Presence>,
+}
+
+impl PresenceStore {
+    pub fn new() -> Self {
+        PresenceStore { nodes: HashMap::new() }
+    }
+
+    pub fn get(&self, node: &str) -> Option<&Presence> {
+        self.nodes.get(node)
+    }
+
+    pub fn set(&mut self, node: &str, presence: Presence) {
+        self.nodes.insert(node.to_owned(), presence);
+    }
+
+    /// Called once a session finishes.
+    pub fn remove(&mut self, node: &str) -> Option<Presence> {
+        self.nodes.remove(node)
+    }
+
+    /// Instances of the identity which currently have a presence.
+    pub fn instances<'a>(&'a self, id: &'a str)
+            -> Box<Iterator<Item=(&'a Node, &'a Presence)> + 'a> {
+        Box::new(self.nodes.iter().filter(move |&(node, _)| {
+            identity(node) == id
+        }))
+    }
+
+    /// The presence of an identity as seen by others, which is the presence
+    /// of its highest priority routable instance plus the list of instances.
+    /// Unroutable instances only count when none is routable.
+    pub fn identity(&self, id: &str) -> Option<Presence> {
+        let mut instances = Vec::new();
+        let mut best: Option<&Presence> = None;
+        for (node, presence) in self.instances(id) {
+            instances.push(node.clone());
+            let better = match best {
+                Some(best) => match (presence.is_routable(),
+                                     best.is_routable()) {
+                    (true, false) => true,
+                    (false, true) => false,
+                    _ => presence.priority.unwrap_or(0) >
+                        best.priority.unwrap_or(0),
+                },
+                None => true,
+            };
+            if better { best = Some(presence); }
+        }
+
+        best.map(|best| {
+            let mut presence = best.clone();
+            instances.sort();
+            presence.instances = Some(instances);
+            presence
+        })
+    }
+}
+
+/// Handles `get` and `set` on `/presence` for the caller's node.
+pub struct PresenceHandler {
+    presence: ArcMut<PresenceStore>,
+}
+
+impl PresenceHandler {
+    pub fn new(presence: ArcMut<PresenceStore>) -> Self {
+        PresenceHandler { presence: presence }
+    }
+
+    pub fn register(self, router: &mut CommandRouter) {
+        router.register_all(&[Get, Set], "/presence", self);
+    }
+}
+
+impl CommandHandler for PresenceHandler {
+    fn handle(&self, req: &Request) -> CommandResult {
+        match req.command.method {
+            CommandMethod::Get => {
+                let store = self.presence.lock().unwrap();
+                let presence = store.identity(identity(req.caller))
+                    .ok_or_else(not_found)?;
+                Ok(Response::resource(PRESENCE_MIME,
+                                      serde_json::to_value(&presence)))
+            }
+            CommandMethod::Set => {
+                let mut presence: Presence = resource(req)?;
+                presence.instances = None;
+                self.presence.lock().unwrap().set(req.caller, presence);
+                Ok(Response::empty())
+            }
+            _ => unreachable!(),
+        }
+    }
+}
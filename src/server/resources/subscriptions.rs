@@ -0,0 +1,180 @@
+use std::collections::{BTreeSet, HashMap};
+
+use envelope::{Command, ErrReason, JsonMap, MediaType, Node, Resources,
+    identity};
+use envelope::command::CommandMethod;
+use envelope::command::CommandMethod::*;
+use envelope::reason::ReasonCode;
+use user::UserStore;
+
+use server::ArcMut;
+use server::command::{CommandHandler, CommandResult, CommandRouter, Request,
+    Response};
+use super::not_found;
+
+/// Resources whose changes are pushed to their subscribers.
+pub static OBSERVABLE: &'static [&'static str] = &["/presence", "/account"];
+
+/// The nodes subscribed to each resource, keyed by the identity owning the
+/// resource and its path (ex. `("jesse@breakingbad.com", "/presence")`).
+#[derive(Default)]
+pub struct SubscriptionStore {
+    subscriptions: HashMap<(Node, String), BTreeSet<Node>>,
+}
+
+impl SubscriptionStore {
+    pub fn new() -> Self {
+        SubscriptionStore { subscriptions: HashMap::new() }
+    }
+
+    pub fn subscribe(&mut self, subscriber: &str, owner: &str, path: &str) {
+        self.subscriptions
+            .entry((identity(owner).to_owned(), path.to_owned()))
+            .or_insert_with(BTreeSet::new)
+            .insert(subscriber.to_owned());
+    }
+
+    /// Returns whether the node was subscribed to the resource.
+    pub fn unsubscribe(&mut self, subscriber: &str, owner: &str, path: &str)
+            -> bool {
+        let key = (identity(owner).to_owned(), path.to_owned());
+        let (removed, empty) = match self.subscriptions.get_mut(&key) {
+            Some(nodes) => (nodes.remove(subscriber), nodes.is_empty()),
+            None => return false,
+        };
+        if empty { self.subscriptions.remove(&key); }
+        removed
+    }
+
+    /// Drops every subscription of the node, once its session is finished.
+    pub fn remove_subscriber(&mut self, subscriber: &str) {
+        for nodes in self.subscriptions.values_mut() {
+            nodes.remove(subscriber);
+        }
+        self.subscriptions.retain(|_, nodes| !nodes.is_empty());
+    }
+
+    /// Drops the subscriptions of `contact`'s nodes to `owner`'s resource,
+    /// once the owner no longer shares it with them.
+    pub fn revoke(&mut self, owner: &str, contact: &str, path: &str) {
+        let key = (identity(owner).to_owned(), path.to_owned());
+        let empty = match self.subscriptions.get_mut(&key) {
+            Some(nodes) => {
+                *nodes = nodes.iter()
+                    .filter(|node| identity(node) != identity(contact))
+                    .cloned()
+                    .collect();
+                nodes.is_empty()
+            }
+            None => return,
+        };
+        if empty { self.subscriptions.remove(&key); }
+    }
+
+    pub fn subscribers(&self, owner: &str, path: &str) -> Vec<Node> {
+        self.subscriptions
+            .get(&(identity(owner).to_owned(), path.to_owned()))
+            .map(|nodes| nodes.iter().cloned().collect())
+            .unwrap_or_else(Vec::new)
+    }
+}
+
+/// The `observe` command pushing the new state of `owner`'s resource to a
+/// subscriber.
+pub fn observe(subscriber: &str, owner: &str, path: &str,
+               mime_type: Option<MediaType>, resource: Option<Resources>)
+        -> Command {
+    Command {
+        to: Some(subscriber.to_owned()),
+        from: Some(identity(owner).to_owned()),
+        pp: None,
+        id: None,
+        metadata: None,
+
+        method: Observe,
+        status: None,
+
+        uri: Some(format!("lime://{}{}", identity(owner), path)),
+        mime_type: mime_type,
+        resource: resource,
+
+        extras: JsonMap::new(),
+    }
+}
+
+/// Handles `subscribe` and `unsubscribe` on the observable resources.
+///
+/// The resource belongs to the identity the command is addressed to, or the
+/// caller's own when `to` is missing. Only contacts on the owner's roster
+/// may subscribe to someone else's resources, and only to those the owner
+/// shares with them.
+pub struct SubscriptionsHandler {
+    subscriptions: ArcMut<SubscriptionStore>,
+    users: ArcMut<UserStore>,
+}
+
+impl SubscriptionsHandler {
+    pub fn new(subscriptions: ArcMut<SubscriptionStore>,
+               users: ArcMut<UserStore>) -> Self {
+        SubscriptionsHandler {
+            subscriptions: subscriptions,
+            users: users,
+        }
+    }
+
+    pub fn register(self, router: &mut CommandRouter) {
+        for path in OBSERVABLE {
+            router.register_all(&[Subscribe, Unsubscribe], path,
+                SubscriptionsHandler::new(self.subscriptions.clone(),
+                                          self.users.clone()));
+        }
+    }
+
+    fn can_subscribe(&self, subscriber: &str, owner: &str, path: &str)
+            -> Result<(), ErrReason> {
+        if identity(subscriber) == identity(owner) { return Ok(()); }
+
+        let users = self.users.lock().unwrap();
+        let user = users.get(identity(owner)).ok_or_else(not_found)?;
+        let contact = user.contacts.get(identity(subscriber)).ok_or_else(|| {
+            ErrReason::new(ReasonCode::CommandNotAllowed,
+                           "Only contacts may subscribe to the resource")
+        })?;
+        let shared = match path {
+            "/presence" => contact.shares_presence(),
+            "/account" => contact.shares_account_info(),
+            _ => false,
+        };
+        if shared {
+            Ok(())
+        } else {
+            Err(ErrReason::new(ReasonCode::CommandNotAllowed,
+                               "The resource isn't shared with the contact"))
+        }
+    }
+}
+
+impl CommandHandler for SubscriptionsHandler {
+    fn handle(&self, req: &Request) -> CommandResult {
+        let owner = req.command.to.as_ref().unwrap_or(req.caller);
+        let path = &req.uri.path;
+        // The session subscribing on behalf of the caller is the one
+        // observing the changes.
+        let subscriber = req.command.pp.as_ref().unwrap_or(req.caller);
+
+        match req.command.method {
+            CommandMethod::Subscribe => {
+                self.can_subscribe(req.caller, owner, path)?;
+                self.subscriptions.lock().unwrap()
+                    .subscribe(subscriber, owner, path);
+            }
+            _ => {
+                if !self.subscriptions.lock().unwrap()
+                        .unsubscribe(subscriber, owner, path) {
+                    return Err(not_found());
+                }
+            }
+        }
+        Ok(Response::empty())
+    }
+}
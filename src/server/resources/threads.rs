@@ -0,0 +1,146 @@
+use std::cmp;
+use std::collections::{BTreeMap, HashMap};
+
+use serde_json;
+
+use envelope::{ErrReason, Message, Metadata, Node, identity};
+use envelope::command::CommandMethod::*;
+use envelope::reason::ReasonCode;
+use envelope::resources::{Collection, Direction, Thread, ThreadMessage,
+    COLLECTION_MIME, THREAD_MIME, THREAD_MESSAGE_MIME};
+
+use server::ArcMut;
+use server::command::{CommandHandler, CommandResult, CommandRouter, Request,
+    Response};
+use super::not_found;
+
+/// Page size used when `$take` isn't given, and the largest one allowed.
+pub static DEFAULT_TAKE: usize = 20;
+pub static MAX_TAKE: usize = 100;
+
+/// Conversations of the identities which opted in through
+/// `Account::store_message_content`, oldest message first.
+#[derive(Default)]
+pub struct MessageArchive {
+    threads: HashMap<Node, BTreeMap<Node, Vec<ThreadMessage>>>,
+}
+
+impl MessageArchive {
+    pub fn new() -> Self {
+        MessageArchive { threads: HashMap::new() }
+    }
+
+    /// Records the message in the thread of `owner` with `peer`.
+    pub fn archive(&mut self, owner: &str, peer: &str, direction: Direction,
+                   msg: &Message) {
+        let messages = self.threads.entry(identity(owner).to_owned())
+            .or_insert_with(BTreeMap::new)
+            .entry(identity(peer).to_owned())
+            .or_insert_with(Vec::new);
+        let sequence = messages.len() as u64 + 1;
+        messages.push(ThreadMessage {
+            id: msg.id,
+            sequence: sequence,
+            direction: direction,
+            mime_type: msg.mime_type.clone(),
+            content: msg.content.clone(),
+            date: msg.metadata.as_ref().and_then(Metadata::timestamp),
+        });
+    }
+
+    pub fn threads(&self, owner: &str) -> Vec<Thread> {
+        self.threads.get(identity(owner))
+            .map(|threads| threads.iter()
+                 .filter_map(|(peer, messages)| messages.last().map(|last| {
+                     Thread {
+                         identity: peer.clone(),
+                         total: messages.len(),
+                         last_message: last.clone(),
+                     }
+                 }))
+                 .collect())
+            .unwrap_or_else(Vec::new)
+    }
+
+    /// A page of the thread, newest message first. Only the messages stored
+    /// before the one with the `before` sequence are considered if it is
+    /// given.
+    pub fn messages(&self, owner: &str, peer: &str, before: Option<u64>,
+                    skip: usize, take: usize) -> Option<Vec<ThreadMessage>> {
+        let messages = match self.threads.get(identity(owner))
+                .and_then(|threads| threads.get(identity(peer))) {
+            Some(messages) => messages,
+            None => return None,
+        };
+        // Sequences are positions in the thread, from 1.
+        let end = match before {
+            Some(sequence) => cmp::min(sequence.saturating_sub(1) as usize,
+                                       messages.len()),
+            None => messages.len(),
+        };
+        Some(messages[..end].iter().rev().skip(skip).take(take)
+             .cloned().collect())
+    }
+}
+
+/// Handles `get /threads` and `get /threads/{identity}`, the latter accepts
+/// the `$skip`, `$take` and `$before` (a message sequence) query parameters.
+pub struct ThreadsHandler {
+    archive: ArcMut<MessageArchive>,
+}
+
+impl ThreadsHandler {
+    pub fn new(archive: ArcMut<MessageArchive>) -> Self {
+        ThreadsHandler { archive: archive }
+    }
+
+    pub fn register(self, router: &mut CommandRouter) {
+        let archive = self.archive.clone();
+        router.register(Get, "/threads", self);
+        router.register(Get, "/threads/{identity}",
+                        ThreadsHandler::new(archive));
+    }
+}
+
+fn query<T: ::std::str::FromStr>(req: &Request, key: &str)
+        -> Result<Option<T>, ErrReason> {
+    match req.uri.query.get(key) {
+        Some(value) => value.parse().map(Some).map_err(|_| {
+            ErrReason::new(ReasonCode::CommandInvalidArgument,
+                           "Invalid paging parameter")
+        }),
+        None => Ok(None),
+    }
+}
+
+impl CommandHandler for ThreadsHandler {
+    fn handle(&self, req: &Request) -> CommandResult {
+        let owner = identity(req.caller);
+        let archive = self.archive.lock().unwrap();
+
+        let collection = match req.params.get("identity") {
+            None => {
+                let threads = archive.threads(owner).iter()
+                    .map(serde_json::to_value).collect();
+                Collection::new(THREAD_MIME, threads)
+            }
+            Some(peer) => {
+                let skip = query(req, "$skip")?.unwrap_or(0);
+                let take = query(req, "$take")?.unwrap_or(DEFAULT_TAKE);
+                if take > MAX_TAKE {
+                    return Err(ErrReason::new(
+                        ReasonCode::CommandInvalidArgument,
+                        "$take is over the maximum page size"));
+                }
+                let messages = archive
+                    .messages(owner, peer, query(req, "$before")?, skip, take)
+                    .ok_or_else(not_found)?;
+                let messages = messages.iter()
+                    .map(serde_json::to_value).collect();
+                Collection::new(THREAD_MESSAGE_MIME, messages)
+            }
+        };
+        Ok(Response::resource(COLLECTION_MIME,
+                              serde_json::to_value(&collection)))
+    }
+}
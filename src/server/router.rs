@@ -0,0 +1,151 @@
+use envelope::{EnvelopeType, Node, identity};
+use envelope::resources::RoutingRule;
+use user::UserStore;
+
+use super::ArcMut;
+use super::resources::presence::PresenceStore;
+use super::resources::capability::CapabilityStore;
+use super::resources::groups::GroupStore;
+use super::resources::delegations::DelegationStore;
+
+/// Messages kept for an offline identity whose account sets no inbox size.
+pub static DEFAULT_INBOX_SIZE: u32 = 100;
+
+/// Decides which connected nodes an envelope addressed to `to` is delivered
+/// to, using the routing rule of each instance's presence.
+#[derive(Clone)]
+pub struct Router {
+    presence: ArcMut<PresenceStore>,
+    registry: ArcMut<UserStore>,
+    capabilities: ArcMut<CapabilityStore>,
+    groups: ArcMut<GroupStore>,
+    delegations: ArcMut<DelegationStore>,
+}
+
+impl Router {
+    pub fn new(presence: ArcMut<PresenceStore>, registry: ArcMut<UserStore>,
+               capabilities: ArcMut<CapabilityStore>,
+               groups: ArcMut<GroupStore>,
+               delegations: ArcMut<DelegationStore>) -> Self {
+        Router {
+            presence: presence,
+            registry: registry,
+            capabilities: capabilities,
+            groups: groups,
+            delegations: delegations,
+        }
+    }
+
+    /// Forgets the presence and capabilities of a node whose session
+    /// finished, so that nothing is routed to it anymore.
+    pub fn forget(&self, node: &str) {
+        self.presence.lock().unwrap().remove(node);
+        self.capabilities.lock().unwrap().remove(node);
+    }
+
+    /// Whether the presence of `node` lets the router pick it for envelopes
+    /// addressed to its identity.
+    pub fn is_routable(&self, node: &str) -> bool {
+        self.presence.lock().unwrap().get(node)
+            .map_or(false, |presence| presence.is_routable())
+    }
+
+    /// Maximum number of messages kept for the identity while it is offline,
+    /// `DEFAULT_INBOX_SIZE` unless its account sets one. `None` if the
+    /// identity isn't registered, nothing is kept for it.
+    pub fn inbox_size(&self, to: &str) -> Option<u32> {
+        let registry = self.registry.lock().unwrap();
+        registry.get(identity(to)).map(|user| {
+            user.account.inbox_size.unwrap_or(DEFAULT_INBOX_SIZE)
+        })
+    }
+
+    /// Whether the identity opted in to keeping its message history.
+    pub fn stores_content(&self, id: &str) -> bool {
+        let registry = self.registry.lock().unwrap();
+        registry.get(identity(id))
+            .and_then(|user| user.account.store_message_content)
+            .unwrap_or(false)
+    }
+
+    /// Member identities of a group, `None` if there is no such group.
+    pub fn members(&self, group: &str) -> Option<Vec<Node>> {
+        self.groups.lock().unwrap().members(group)
+    }
+
+    /// Whether the node declared it can handle messages of this type.
+    pub fn accepts_content(&self, node: &str, mime_type: &str) -> bool {
+        self.capabilities.lock().unwrap().accepts_content(node, mime_type)
+    }
+
+    /// Whether the identity of `to` accepts envelopes from `from`, users
+    /// refusing unknown senders only accept the contacts on their roster.
+    pub fn is_authorized(&self, from: &str, to: &str) -> bool {
+        let registry = self.registry.lock().unwrap();
+        match registry.get(identity(to)) {
+            Some(user) => user.accepts_sender(identity(from)),
+            None => true,
+        }
+    }
+
+    /// Whether the session of `sender` may send envelopes of this type with
+    /// `from` as their sender.
+    pub fn can_send_as(&self, sender: &str, from: &str, kind: EnvelopeType)
+            -> bool {
+        self.delegations.lock().unwrap().allows(from, sender, kind)
+    }
+
+    /// Picks the destination instance out of the `online` nodes.
+    ///
+    /// - An online instance always receives envelopes addressed to it.
+    /// - Otherwise the routable instances of the identity whose rule accepts
+    ///   the envelope are candidates, and the highest priority one wins.
+    pub fn destinations<'a, I>(&self, to: &str, online: I) -> Vec<Node>
+        where I: IntoIterator<Item=&'a Node>
+    {
+        let mut best: Option<(i32, Node)> = None;
+        for (priority, node) in self.candidates(to, online) {
+            best = match best {
+                Some((p, _)) if p >= priority => best,
+                _ => Some((priority, node)),
+            };
+        }
+        best.map(|(_, node)| vec![node]).unwrap_or_else(Vec::new)
+    }
+
+    /// Every instance accepting the envelope, regardless of priority.
+    pub fn fan_out<'a, I>(&self, to: &str, online: I) -> Vec<Node>
+        where I: IntoIterator<Item=&'a Node>
+    {
+        self.candidates(to, online).into_iter().map(|(_, node)| node).collect()
+    }
+
+    fn candidates<'a, I>(&self, to: &str, online: I) -> Vec<(i32, Node)>
+        where I: IntoIterator<Item=&'a Node>
+    {
+        let to_identity = identity(to);
+        let addressed_to_instance = to_identity.len() != to.len();
+        let presence = self.presence.lock().unwrap();
+
+        let mut candidates = Vec::new();
+        for node in online.into_iter().filter(|n| identity(n) == to_identity) {
+            if node == to {
+                candidates.push((i32::max_value(), node.clone()));
+                continue;
+            }
+            let presence = match presence.get(node) {
+                Some(presence) if presence.is_routable() => presence,
+                _ => continue,
+            };
+            let accepts = match presence.routing_rule() {
+                RoutingRule::Instance => false,
+                RoutingRule::Identity => !addressed_to_instance,
+                RoutingRule::Promiscuous => true,
+            };
+            if accepts {
+                candidates.push((presence.priority.unwrap_or(0), node.clone()));
+            }
+        }
+        candidates
+    }
+}
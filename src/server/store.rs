@@ -0,0 +1,127 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+
+use serde_json;
+
+use envelope::{Envelope, Message, DELIMITER, identity};
+
+/// Keeps messages for identities which are offline until they come back.
+///
+/// Messages are kept per identity (without instance) and handed back in the
+/// order they were stored.
+pub trait MessageStore: Send {
+    fn store(&mut self, to: &str, msg: Message) -> io::Result<()>;
+
+    /// Removes and returns every message stored for the identity.
+    fn take(&mut self, to: &str) -> io::Result<Vec<Message>>;
+
+    /// Number of messages waiting for the identity.
+    fn len(&self, to: &str) -> usize;
+}
+
+/// Keeps the messages in memory, they are lost when the server stops.
+#[derive(Default)]
+pub struct MemoryStore {
+    inboxes: HashMap<String, VecDeque<Message>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore { inboxes: HashMap::new() }
+    }
+}
+
+impl MessageStore for MemoryStore {
+    fn store(&mut self, to: &str, msg: Message) -> io::Result<()> {
+        self.inboxes.entry(identity(to).to_owned())
+            .or_insert_with(VecDeque::new)
+            .push_back(msg);
+        Ok(())
+    }
+
+    fn take(&mut self, to: &str) -> io::Result<Vec<Message>> {
+        Ok(self.inboxes.remove(identity(to))
+               .map(|inbox| inbox.into_iter().collect())
+               .unwrap_or_else(Vec::new))
+    }
+
+    fn len(&self, to: &str) -> usize {
+        self.inboxes.get(identity(to)).map_or(0, VecDeque::len)
+    }
+}
+
+/// Keeps the messages on disk, one file per identity holding one json
+/// envelope per line, the same framing used on the wire.
+pub struct FileStore {
+    dir: PathBuf,
+}
+
+impl FileStore {
+    /// Uses `dir` for the inboxes, creating it if needed.
+    pub fn new<P: Into<PathBuf>>(dir: P) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(FileStore { dir: dir })
+    }
+
+    /// Identities are percent-encoded so that each one gets its own file,
+    /// even on case-insensitive file systems: only lowercase letters,
+    /// digits, `@`, `.` and `-` are kept as they are.
+    fn path(&self, to: &str) -> PathBuf {
+        let mut name = String::new();
+        for byte in identity(to).bytes() {
+            match byte {
+                b'a'...b'z' | b'0'...b'9' | b'@' | b'.' | b'-' =>
+                    name.push(byte as char),
+                _ => name.push_str(&format!("%{:02X}", byte)),
+            }
+        }
+        self.dir.join(format!("{}.inbox", name))
+    }
+}
+
+fn invalid_data<E>(_: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "Corrupted inbox")
+}
+
+impl MessageStore for FileStore {
+    fn store(&mut self, to: &str, msg: Message) -> io::Result<()> {
+        let mut line = serde_json::to_vec(&msg).map_err(invalid_data)?;
+        line.push(DELIMITER);
+        let mut file = OpenOptions::new()
+            .create(true).append(true).open(self.path(to))?;
+        file.write_all(&line)
+    }
+
+    fn take(&mut self, to: &str) -> io::Result<Vec<Message>> {
+        let path = self.path(to);
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound =>
+                return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+
+        let mut messages = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            if line.is_empty() { continue; }
+            match serde_json::from_str(&line).map_err(invalid_data)? {
+                Envelope::Message(msg) => messages.push(msg),
+                _ => return Err(invalid_data(())),
+            }
+        }
+        fs::remove_file(&path)?;
+        Ok(messages)
+    }
+
+    fn len(&self, to: &str) -> usize {
+        File::open(self.path(to))
+            .map(|file| BufReader::new(file).lines()
+                 .filter(|line| line.as_ref().map_or(false, |l| !l.is_empty()))
+                 .count())
+            .unwrap_or(0)
+    }
+}
@@ -0,0 +1,567 @@
+//! LIME over WebSocket, for browser clients.
+//!
+//! Every envelope travels as the json of a single text message. Once the
+//! HTTP upgrade is done the connection is a regular `EnvStream`, so it goes
+//! through the same `Authentication` and `ClientSession` as tcp clients.
+//! Pings are answered and closes echoed by the `WsStream` itself.
+
+use std::collections::VecDeque;
+use std::io::{self, Read};
+use std::mem;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64;
+use futures::{Async, AsyncSink, Future, Poll, Sink, StartSend, Stream};
+use serde_json;
+use sha1::Sha1;
+use tokio_core::io::{Codec, EasyBuf, Framed, Io};
+use tokio_core::net::TcpStream;
+
+use envelope::Envelope;
+use transport::Transport;
+use super::handshake::{Handshake, would_block, write_all};
+
+/// Sub protocol announced by LIME clients and servers.
+pub static SUB_PROTOCOL: &'static str = "lime";
+
+static ACCEPT_GUID: &'static str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// The only version of the protocol spoken, the one of RFC 6455.
+static VERSION: &'static str = "13";
+/// Upper bound for the size of a message, fragments included.
+static MAX_MESSAGE_SIZE: usize = 1 << 24;
+static MAX_HEAD_SIZE: usize = 8192;
+
+/// Control frames can't be fragmented nor carry more than this.
+static MAX_CONTROL_SIZE: usize = 125;
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xa;
+
+/// What a `WsCodec` reads and writes, envelopes travel in text messages and
+/// the other frames only keep the connection alive or end it.
+#[derive(Debug)]
+pub enum WsFrame {
+    Envelope(Envelope),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    /// The payload is the status code and reason, if any.
+    Close(Vec<u8>),
+}
+
+/// Side of the connection, clients mask the frames they send and servers
+/// refuse frames which aren't masked.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Role {
+    Server,
+    Client,
+}
+
+pub struct WsCodec {
+    role: Role,
+    /// Payload of the text message being received in several frames.
+    fragments: Vec<u8>,
+    /// Whether a message was started by a frame without FIN.
+    fragmented: bool,
+    mask_state: u32,
+}
+
+impl WsCodec {
+    pub fn new(role: Role) -> Self {
+        let seed = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|time| time.subsec_nanos())
+            .unwrap_or(0);
+        WsCodec {
+            role: role,
+            fragments: Vec::new(),
+            fragmented: false,
+            mask_state: seed | 1,
+        }
+    }
+
+    fn next_mask(&mut self) -> [u8; 4] {
+        // xorshift, the mask only has to vary between frames.
+        let mut x = self.mask_state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.mask_state = x;
+        [(x >> 24) as u8, (x >> 16) as u8, (x >> 8) as u8, x as u8]
+    }
+}
+
+fn invalid(description: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, description.to_owned())
+}
+
+struct FrameHeader {
+    fin: bool,
+    /// Bits of the extensions, none is ever negotiated.
+    reserved: u8,
+    opcode: u8,
+    mask: Option<[u8; 4]>,
+    /// Length of the header, the payload starts right after it.
+    size: usize,
+    len: usize,
+}
+
+/// Reads the header of the frame at the start of `buf`, `None` until all of
+/// it arrived.
+fn parse_header(buf: &[u8]) -> io::Result<Option<FrameHeader>> {
+    if buf.len() < 2 { return Ok(None); }
+
+    let masked = buf[1] & 0x80 != 0;
+    let (len, mut size) = match buf[1] & 0x7f {
+        126 => {
+            if buf.len() < 4 { return Ok(None); }
+            ((buf[2] as u64) << 8 | buf[3] as u64, 4)
+        }
+        127 => {
+            if buf.len() < 10 { return Ok(None); }
+            (buf[2..10].iter().fold(0, |len, &b| len << 8 | b as u64), 10)
+        }
+        len => (len as u64, 2),
+    };
+    if len > MAX_MESSAGE_SIZE as u64 {
+        return Err(invalid("WebSocket frame is too large"));
+    }
+
+    let mask = if masked {
+        if buf.len() < size + 4 { return Ok(None); }
+        let key = [buf[size], buf[size + 1], buf[size + 2], buf[size + 3]];
+        size += 4;
+        Some(key)
+    } else { None };
+
+    Ok(Some(FrameHeader {
+        fin: buf[0] & 0x80 != 0,
+        reserved: buf[0] & 0x70,
+        opcode: buf[0] & 0x0f,
+        mask: mask,
+        size: size,
+        len: len as usize,
+    }))
+}
+
+/// Refuses the frames a peer must never send, the connection is then failed.
+fn check_header(header: &FrameHeader) -> io::Result<()> {
+    if header.reserved != 0 {
+        return Err(invalid("No WebSocket extension was negotiated"));
+    }
+    match header.opcode {
+        OP_CONTINUATION | OP_TEXT | OP_BINARY => Ok(()),
+        OP_CLOSE | OP_PING | OP_PONG => {
+            if !header.fin {
+                Err(invalid("WebSocket control frames can't be fragmented"))
+            } else if header.len > MAX_CONTROL_SIZE {
+                Err(invalid("WebSocket control frame is too large"))
+            } else {
+                Ok(())
+            }
+        }
+        _ => Err(invalid("Reserved WebSocket opcode")),
+    }
+}
+
+impl Codec for WsCodec {
+    type In = WsFrame;
+    type Out = WsFrame;
+
+    /// Control frames may arrive between the fragments of a message, they
+    /// are decoded right away.
+    fn decode(&mut self, buf: &mut EasyBuf)
+            -> Result<Option<Self::In>, io::Error> {
+        loop {
+            let header = match parse_header(buf.as_slice())? {
+                Some(header) => header,
+                None => return Ok(None),
+            };
+            check_header(&header)?;
+            if buf.len() < header.size + header.len { return Ok(None); }
+
+            let frame = buf.drain_to(header.size + header.len);
+            let mut payload = frame.as_slice()[header.size..].to_vec();
+            match header.mask {
+                Some(key) => for (i, b) in payload.iter_mut().enumerate() {
+                    *b ^= key[i % 4];
+                },
+                None if self.role == Role::Server =>
+                    return Err(invalid("Client frames must be masked")),
+                None => (),
+            }
+
+            match header.opcode {
+                OP_TEXT | OP_CONTINUATION => {
+                    if (header.opcode == OP_TEXT) == self.fragmented {
+                        return Err(invalid(if self.fragmented {
+                            "WebSocket message started before the last ended"
+                        } else {
+                            "WebSocket continuation of no message"
+                        }));
+                    }
+                    if self.fragments.len() + payload.len() > MAX_MESSAGE_SIZE {
+                        return Err(invalid("WebSocket message is too large"));
+                    }
+                    self.fragments.extend(payload);
+                    self.fragmented = !header.fin;
+                    if !header.fin { continue; }
+
+                    let message = mem::replace(&mut self.fragments, Vec::new());
+                    return serde_json::from_slice(&message)
+                        .map(|envelope| Some(WsFrame::Envelope(envelope)))
+                        .map_err(|_| invalid("Failed to decode object"));
+                }
+                OP_PING => return Ok(Some(WsFrame::Ping(payload))),
+                OP_PONG => return Ok(Some(WsFrame::Pong(payload))),
+                OP_CLOSE => return Ok(Some(WsFrame::Close(payload))),
+                _ => return Err(invalid("Envelopes must be sent as text")),
+            }
+        }
+    }
+
+    fn encode(&mut self, msg: Self::Out, buf: &mut Vec<u8>) -> io::Result<()> {
+        let (opcode, payload) = match msg {
+            WsFrame::Envelope(envelope) => {
+                let payload = serde_json::to_vec(&envelope).map_err(|_| {
+                    io::Error::new(io::ErrorKind::Other,
+                                   "Failed to encode object")
+                })?;
+                (OP_TEXT, payload)
+            }
+            WsFrame::Ping(payload) => (OP_PING, payload),
+            WsFrame::Pong(payload) => (OP_PONG, payload),
+            WsFrame::Close(payload) => (OP_CLOSE, payload),
+        };
+
+        let mask_bit = if self.role == Role::Client { 0x80 } else { 0 };
+        buf.push(0x80 | opcode);
+        match payload.len() {
+            len if len < 126 => buf.push(mask_bit | len as u8),
+            len if len <= 0xffff => {
+                buf.push(mask_bit | 126);
+                buf.push((len >> 8) as u8);
+                buf.push(len as u8);
+            }
+            len => {
+                buf.push(mask_bit | 127);
+                for shift in (0..8).rev() {
+                    buf.push((len as u64 >> (shift * 8)) as u8);
+                }
+            }
+        }
+
+        if self.role == Role::Client {
+            let key = self.next_mask();
+            buf.extend_from_slice(&key);
+            buf.extend(payload.iter().enumerate().map(|(i, b)| b ^ key[i % 4]));
+        } else {
+            buf.extend(payload);
+        }
+        Ok(())
+    }
+}
+
+/// A connection once upgraded, carrying envelopes. Pings of the peer are
+/// answered and its close is echoed, after which the stream ends.
+pub struct WsStream<T> {
+    inner: Framed<T, WsCodec>,
+    /// Control frames answering the peer, sent before any envelope.
+    replies: VecDeque<WsFrame>,
+    closed: bool,
+}
+
+impl<T: Io> WsStream<T> {
+    pub fn new(inner: Framed<T, WsCodec>) -> Self {
+        WsStream {
+            inner: inner,
+            replies: VecDeque::new(),
+            closed: false,
+        }
+    }
+
+    /// Hands the pending replies to the connection and flushes it.
+    fn poll_replies(&mut self) -> Poll<(), io::Error> {
+        while let Some(reply) = self.replies.pop_front() {
+            if let AsyncSink::NotReady(reply) = self.inner.start_send(reply)? {
+                self.replies.push_front(reply);
+                self.inner.poll_complete()?;
+                return Ok(Async::NotReady);
+            }
+        }
+        self.inner.poll_complete()
+    }
+}
+
+impl<T: Io> Stream for WsStream<T> {
+    type Item = Envelope;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Envelope>, io::Error> {
+        loop {
+            if self.closed { return Ok(Async::Ready(None)); }
+            let frame = match self.inner.poll()? {
+                Async::Ready(Some(frame)) => frame,
+                Async::Ready(None) => return Ok(Async::Ready(None)),
+                Async::NotReady => return Ok(Async::NotReady),
+            };
+            match frame {
+                WsFrame::Envelope(envelope) =>
+                    return Ok(Async::Ready(Some(envelope))),
+                WsFrame::Ping(payload) =>
+                    self.replies.push_back(WsFrame::Pong(payload)),
+                WsFrame::Pong(_) => (),
+                WsFrame::Close(payload) => {
+                    self.replies.push_back(WsFrame::Close(payload));
+                    self.closed = true;
+                }
+            }
+            self.poll_replies()?;
+        }
+    }
+}
+
+impl<T: Io> Sink for WsStream<T> {
+    type SinkItem = Envelope;
+    type SinkError = io::Error;
+
+    fn start_send(&mut self, envelope: Envelope)
+            -> StartSend<Envelope, io::Error> {
+        if self.poll_replies()?.is_not_ready() {
+            return Ok(AsyncSink::NotReady(envelope));
+        }
+        match self.inner.start_send(WsFrame::Envelope(envelope))? {
+            AsyncSink::Ready => Ok(AsyncSink::Ready),
+            AsyncSink::NotReady(WsFrame::Envelope(envelope)) =>
+                Ok(AsyncSink::NotReady(envelope)),
+            AsyncSink::NotReady(_) => unreachable!(),
+        }
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), io::Error> {
+        self.poll_replies()
+    }
+}
+
+/// The `Sec-WebSocket-Accept` value answering a `Sec-WebSocket-Key`.
+pub fn accept_key(key: &str) -> String {
+    let mut sha = Sha1::new();
+    sha.update(key.trim().as_bytes());
+    sha.update(ACCEPT_GUID.as_bytes());
+    base64::encode(&sha.digest().bytes())
+}
+
+fn header<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+    head.lines().skip(1)
+        .filter_map(|line| line.find(':').map(|i| (&line[..i], &line[i + 1..])))
+        .find(|&(key, _)| key.trim().to_lowercase() == name)
+        .map(|(_, value)| value.trim())
+}
+
+/// Reads the HTTP head one byte at a time, so that no frame sent right after
+/// it is consumed before the codec takes over.
+fn read_head<R: Read>(io: &mut R, head: &mut Vec<u8>) -> Poll<(), io::Error> {
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() > MAX_HEAD_SIZE {
+            return Err(invalid("The HTTP head is too large"));
+        }
+        let mut byte = [0];
+        match would_block(io.read(&mut byte))? {
+            Async::Ready(0) => return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof, "Closed during the handshake")),
+            Async::Ready(_) => head.push(byte[0]),
+            Async::NotReady => return Ok(Async::NotReady),
+        }
+    }
+    Ok(Async::Ready(()))
+}
+
+/// What the server answers to the upgrade request.
+enum Answer {
+    Accept(Vec<u8>),
+    /// The response is written, then the handshake fails with the reason.
+    Refuse(Vec<u8>, &'static str),
+}
+
+/// The server's answer to the upgrade request.
+fn upgrade_response(head: &[u8]) -> io::Result<Answer> {
+    let head = String::from_utf8_lossy(head);
+    let upgrade = header(&head, "upgrade").map(str::to_lowercase);
+    if !head.starts_with("GET ") || upgrade != Some("websocket".to_owned()) {
+        return Err(invalid("Not a WebSocket upgrade request"));
+    }
+    // Clients speaking another version of the protocol are told which one
+    // the server does, RFC 6455 section 4.4.
+    if header(&head, "sec-websocket-version") != Some(VERSION) {
+        let response = format!("HTTP/1.1 426 Upgrade Required\r\n\
+                                Sec-WebSocket-Version: {}\r\n\
+                                Content-Length: 0\r\n\r\n", VERSION);
+        return Ok(Answer::Refuse(response.into_bytes(),
+                                 "Unsupported WebSocket version"));
+    }
+    let key = header(&head, "sec-websocket-key")
+        .ok_or_else(|| invalid("Missing Sec-WebSocket-Key"))?;
+
+    let mut response = format!("HTTP/1.1 101 Switching Protocols\r\n\
+                                Upgrade: websocket\r\n\
+                                Connection: Upgrade\r\n\
+                                Sec-WebSocket-Accept: {}\r\n", accept_key(key));
+    let lime = header(&head, "sec-websocket-protocol")
+        .map_or(false, |protocols| {
+            protocols.split(',').any(|p| p.trim() == SUB_PROTOCOL)
+        });
+    if lime {
+        response.push_str(&format!("Sec-WebSocket-Protocol: {}\r\n",
+                                   SUB_PROTOCOL));
+    }
+    response.push_str("\r\n");
+    Ok(Answer::Accept(response.into_bytes()))
+}
+
+enum State {
+    Reading(Vec<u8>),
+    Writing(Vec<u8>, usize),
+}
+
+/// Server side of the upgrade, answers the HTTP request of the client and
+/// hands back the connection framed with a `WsCodec`.
+pub struct WsHandshake<T = TcpStream> {
+    conn: Option<T>,
+    state: State,
+    /// Why the upgrade was refused, once the response is written.
+    refusal: Option<&'static str>,
+}
+
+impl<T: Transport> WsHandshake<T> {
+    pub fn new() -> Self {
+        WsHandshake {
+            conn: None,
+            state: State::Reading(Vec::new()),
+            refusal: None,
+        }
+    }
+}
+
+impl<T: Transport> Default for WsHandshake<T> {
+    fn default() -> Self {
+        WsHandshake::new()
+    }
+}
+
+impl<T: Transport> Handshake for WsHandshake<T> {
+    type Transport = T;
+    type Stream = WsStream<T>;
+
+    fn take_stream(&mut self, io: T) {
+        self.conn = Some(io);
+        self.state = State::Reading(Vec::new());
+        self.refusal = None;
+    }
+
+    fn update_handshake(&mut self) -> Poll<Option<Self::Stream>, io::Error> {
+        loop {
+            let conn = match self.conn.as_mut() {
+                Some(conn) => conn,
+                None => return Ok(Async::Ready(None)),
+            };
+            let next = match self.state {
+                State::Reading(ref mut head) => {
+                    if read_head(conn, head)?.is_not_ready() {
+                        return Ok(Async::NotReady);
+                    }
+                    match upgrade_response(head)? {
+                        Answer::Accept(response) => State::Writing(response, 0),
+                        Answer::Refuse(response, reason) => {
+                            self.refusal = Some(reason);
+                            State::Writing(response, 0)
+                        }
+                    }
+                }
+                State::Writing(ref response, ref mut pos) => {
+                    if write_all(conn, response, pos)?.is_not_ready() {
+                        return Ok(Async::NotReady);
+                    }
+                    if let Some(reason) = self.refusal {
+                        return Err(invalid(reason));
+                    }
+                    break;
+                }
+            };
+            self.state = next;
+        }
+
+        let conn = self.conn.take().unwrap();
+        let framed = conn.framed(WsCodec::new(Role::Server));
+        Ok(Async::Ready(Some(WsStream::new(framed))))
+    }
+}
+
+/// Client side of the upgrade, resolves to the framed connection once the
+/// server accepted it.
+pub struct WsConnect<T = TcpStream> {
+    conn: Option<T>,
+    key: String,
+    state: State,
+}
+
+impl<T: Io> WsConnect<T> {
+    /// Upgrades `io`, `host` and `path` are the ones of the server's url.
+    pub fn new(io: T, host: &str, path: &str) -> Self {
+        let mut codec = WsCodec::new(Role::Client);
+        let nonce: Vec<u8> = (0..4).flat_map(|_| codec.next_mask().to_vec())
+            .collect();
+        let key = base64::encode(&nonce);
+        let request = format!("GET {} HTTP/1.1\r\n\
+                               Host: {}\r\n\
+                               Upgrade: websocket\r\n\
+                               Connection: Upgrade\r\n\
+                               Sec-WebSocket-Key: {}\r\n\
+                               Sec-WebSocket-Version: {}\r\n\
+                               Sec-WebSocket-Protocol: {}\r\n\r\n",
+                              path, host, key, VERSION, SUB_PROTOCOL);
+        WsConnect {
+            conn: Some(io),
+            key: key,
+            state: State::Writing(request.into_bytes(), 0),
+        }
+    }
+}
+
+impl<T: Io> Future for WsConnect<T> {
+    type Item = WsStream<T>;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let conn = self.conn.as_mut().expect("Polled WsConnect twice");
+            let next = match self.state {
+                State::Writing(ref request, ref mut pos) => {
+                    if write_all(conn, request, pos)?.is_not_ready() {
+                        return Ok(Async::NotReady);
+                    }
+                    State::Reading(Vec::new())
+                }
+                State::Reading(ref mut head) => {
+                    if read_head(conn, head)?.is_not_ready() {
+                        return Ok(Async::NotReady);
+                    }
+                    let head = String::from_utf8_lossy(head);
+                    let accepted = head.starts_with("HTTP/1.1 101") &&
+                        header(&head, "sec-websocket-accept") ==
+                            Some(&*accept_key(&self.key));
+                    if !accepted {
+                        return Err(invalid("The server refused the upgrade"));
+                    }
+                    break;
+                }
+            };
+            self.state = next;
+        }
+
+        let conn = self.conn.take().unwrap();
+        let framed = conn.framed(WsCodec::new(Role::Client));
+        Ok(Async::Ready(WsStream::new(framed)))
+    }
+}
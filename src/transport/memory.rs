@@ -0,0 +1,199 @@
+//! In-process transport, pairs of connected streams which never touch the
+//! network.
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+
+use futures::{future, BoxFuture, Future, Stream};
+use futures::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::task::{self, Task};
+use tokio_core::io::Io;
+use tokio_core::reactor::Handle;
+
+use super::{Incoming, Listener, Transport};
+
+static NEXT_ID: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Identifies an end of a pair, or a `MemoryListener` which can be
+/// connected to.
+#[derive(Clone)]
+pub struct MemoryAddr {
+    id: usize,
+    listener: Option<UnboundedSender<MemoryStream>>,
+}
+
+impl MemoryAddr {
+    fn new(listener: Option<UnboundedSender<MemoryStream>>) -> Self {
+        MemoryAddr {
+            id: NEXT_ID.fetch_add(1, Ordering::SeqCst),
+            listener: listener,
+        }
+    }
+}
+
+impl fmt::Debug for MemoryAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MemoryAddr({})", self.id)
+    }
+}
+
+impl PartialEq for MemoryAddr {
+    fn eq(&self, other: &MemoryAddr) -> bool {
+        self.id == other.id
+    }
+}
+
+/// Bytes written by one end and not yet read by the other.
+#[derive(Default)]
+struct Pipe {
+    buf: VecDeque<u8>,
+    /// The writing end was dropped, or the reading one for writers.
+    closed: bool,
+    reader: Option<Task>,
+}
+
+impl Pipe {
+    fn close(&mut self) {
+        self.closed = true;
+        if let Some(task) = self.reader.take() {
+            task.unpark();
+        }
+    }
+}
+
+/// One end of an in-memory connection, see `pair`.
+pub struct MemoryStream {
+    read: Arc<Mutex<Pipe>>,
+    write: Arc<Mutex<Pipe>>,
+    peer: MemoryAddr,
+}
+
+/// Two connected streams, what one writes the other reads.
+pub fn pair() -> (MemoryStream, MemoryStream) {
+    pair_with(MemoryAddr::new(None))
+}
+
+/// The first stream of the pair reads `peer` as its peer address.
+fn pair_with(peer: MemoryAddr) -> (MemoryStream, MemoryStream) {
+    let ours = Arc::new(Mutex::new(Pipe::default()));
+    let theirs = Arc::new(Mutex::new(Pipe::default()));
+    let a = MemoryStream {
+        read: ours.clone(),
+        write: theirs.clone(),
+        peer: peer,
+    };
+    let b = MemoryStream {
+        read: theirs,
+        write: ours,
+        peer: MemoryAddr::new(None),
+    };
+    (a, b)
+}
+
+impl Read for MemoryStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut pipe = self.read.lock().unwrap();
+        if pipe.buf.is_empty() {
+            if pipe.closed { return Ok(0); }
+            pipe.reader = Some(task::park());
+            return Err(io::Error::new(io::ErrorKind::WouldBlock,
+                                      "Nothing to read yet"));
+        }
+
+        let len = cmp::min(buf.len(), pipe.buf.len());
+        for (byte, slot) in pipe.buf.drain(..len).zip(buf.iter_mut()) {
+            *slot = byte;
+        }
+        Ok(len)
+    }
+}
+
+impl Write for MemoryStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut pipe = self.write.lock().unwrap();
+        if pipe.closed {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe,
+                                      "The other end was dropped"));
+        }
+        pipe.buf.extend(buf);
+        if let Some(task) = pipe.reader.take() {
+            task.unpark();
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Io for MemoryStream {}
+
+impl Drop for MemoryStream {
+    fn drop(&mut self) {
+        self.write.lock().unwrap().close();
+        self.read.lock().unwrap().close();
+    }
+}
+
+impl Transport for MemoryStream {
+    type Addr = MemoryAddr;
+
+    /// Hands the other end of a new pair to the listener at `addr`.
+    fn connect(addr: &MemoryAddr, _: &Handle) -> BoxFuture<Self, io::Error> {
+        let refused = || io::Error::new(io::ErrorKind::ConnectionRefused,
+                                        "No listener at this address");
+        let listener = match addr.listener {
+            Some(ref listener) => listener,
+            None => return future::err(refused()).boxed(),
+        };
+
+        let (client, server) = pair_with(addr.clone());
+        match listener.send(server) {
+            Ok(()) => future::ok(client).boxed(),
+            Err(_) => future::err(refused()).boxed(),
+        }
+    }
+
+    fn peer_addr(&self) -> io::Result<MemoryAddr> {
+        Ok(self.peer.clone())
+    }
+}
+
+/// Accepts the streams connected to its address.
+pub struct MemoryListener {
+    addr: MemoryAddr,
+    incoming: UnboundedReceiver<MemoryStream>,
+}
+
+impl MemoryListener {
+    pub fn bind() -> Self {
+        let (sender, receiver) = mpsc::unbounded();
+        MemoryListener {
+            addr: MemoryAddr::new(Some(sender)),
+            incoming: receiver,
+        }
+    }
+}
+
+impl Listener for MemoryListener {
+    type Transport = MemoryStream;
+
+    fn local_addr(&self) -> io::Result<MemoryAddr> {
+        Ok(self.addr.clone())
+    }
+
+    fn incoming(self) -> Incoming<MemoryStream> {
+        Box::new(self.incoming
+                 .map(|stream| {
+                     let addr = stream.peer.clone();
+                     (stream, addr)
+                 })
+                 .map_err(|_| io::Error::new(io::ErrorKind::Other,
+                                             "The listener was closed")))
+    }
+}
@@ -0,0 +1,116 @@
+//! The byte streams envelopes travel on.
+//!
+//! A `Transport` is a connected stream which knows its peer and which
+//! encryption and compression it is able to switch to during the session
+//! negotiation. A `Listener` accepts transports and `connect` opens them, so
+//! servers and clients can be assembled from any transport: tcp, unix
+//! sockets or the in-memory pairs used by the tests.
+
+#[cfg(unix)]
+pub mod unix;
+pub mod memory;
+
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+
+use futures::{future, BoxFuture, Future, Stream};
+use tokio_core::io::Io;
+use tokio_core::net::{TcpListener, TcpStream};
+use tokio_core::reactor::Handle;
+
+use envelope::{EncryptionOptions, CompressionOptions};
+
+pub use self::memory::{MemoryAddr, MemoryListener, MemoryStream};
+#[cfg(unix)]
+pub use self::unix::UnixAddr;
+
+/// Connections accepted by a listener, with the address of their peer.
+pub type Incoming<T> =
+    Box<Stream<Item=(T, <T as Transport>::Addr), Error=io::Error> + Send>;
+
+/// Process and system user of the peer of a local connection.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PeerCredentials {
+    pub uid: u32,
+    pub gid: u32,
+    /// Not every platform tells the process.
+    pub pid: Option<i32>,
+}
+
+pub trait Transport: Io + Send + Sized + 'static {
+    type Addr: Clone + fmt::Debug + Send + 'static;
+
+    /// Opens a connection to a listener at `addr`.
+    fn connect(addr: &Self::Addr, handle: &Handle)
+        -> BoxFuture<Self, io::Error>;
+
+    fn peer_addr(&self) -> io::Result<Self::Addr>;
+
+    /// Who runs the peer, only known for local transports. Sessions may then
+    /// be authenticated by the `transport` scheme.
+    fn peer_credentials(&self) -> Option<PeerCredentials> {
+        None
+    }
+
+    /// Encryptions offered to the peer during the negotiation, in order of
+    /// preference.
+    fn encryption_options(&self) -> Vec<EncryptionOptions> {
+        vec![EncryptionOptions::Nil]
+    }
+
+    /// Compressions offered to the peer during the negotiation, in order of
+    /// preference.
+    fn compression_options(&self) -> Vec<CompressionOptions> {
+        vec![CompressionOptions::Nil]
+    }
+
+    /// Switches to the options both ends agreed on, once the session is
+    /// negotiated. Transports which only offer `none` can keep this.
+    fn upgrade(self, encryption: EncryptionOptions,
+               compression: CompressionOptions) -> BoxFuture<Self, io::Error> {
+        if encryption == EncryptionOptions::Nil &&
+                compression == CompressionOptions::Nil {
+            future::ok(self).boxed()
+        } else {
+            future::err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The transport does not support the negotiated options"))
+                .boxed()
+        }
+    }
+}
+
+/// Accepts the connections of a transport.
+pub trait Listener: Sized {
+    type Transport: Transport;
+
+    fn local_addr(&self) -> io::Result<<Self::Transport as Transport>::Addr>;
+
+    fn incoming(self) -> Incoming<Self::Transport>;
+}
+
+impl Transport for TcpStream {
+    type Addr = SocketAddr;
+
+    fn connect(addr: &SocketAddr, handle: &Handle)
+            -> BoxFuture<Self, io::Error> {
+        TcpStream::connect(addr, handle).boxed()
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        TcpStream::peer_addr(self)
+    }
+}
+
+impl Listener for TcpListener {
+    type Transport = TcpStream;
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        TcpListener::local_addr(self)
+    }
+
+    fn incoming(self) -> Incoming<TcpStream> {
+        Box::new(TcpListener::incoming(self))
+    }
+}
@@ -0,0 +1,101 @@
+//! Unix domain sockets, for clients running on the same host as the server.
+
+use std::io;
+use std::mem;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net;
+use std::path::{Path, PathBuf};
+
+use futures::{future, BoxFuture, Future, Stream};
+use libc;
+use tokio_core::reactor::Handle;
+use tokio_uds::{UnixListener, UnixStream};
+
+use super::{Incoming, Listener, PeerCredentials, Transport};
+
+/// Path of a unix socket, `None` for the unnamed sockets clients usually
+/// connect with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnixAddr(pub Option<PathBuf>);
+
+impl UnixAddr {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        UnixAddr(Some(path.into()))
+    }
+
+    pub fn as_path(&self) -> Option<&Path> {
+        self.0.as_ref().map(|path| path.as_path())
+    }
+}
+
+impl From<net::SocketAddr> for UnixAddr {
+    fn from(addr: net::SocketAddr) -> Self {
+        UnixAddr(addr.as_pathname().map(Path::to_path_buf))
+    }
+}
+
+impl Transport for UnixStream {
+    type Addr = UnixAddr;
+
+    fn connect(addr: &UnixAddr, handle: &Handle)
+            -> BoxFuture<Self, io::Error> {
+        let path = match addr.as_path() {
+            Some(path) => path,
+            None => return future::err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Can't connect to an unnamed socket")).boxed(),
+        };
+        future::result(UnixStream::connect(path, handle)).boxed()
+    }
+
+    fn peer_addr(&self) -> io::Result<UnixAddr> {
+        UnixStream::peer_addr(self).map(UnixAddr::from)
+    }
+
+    fn peer_credentials(&self) -> Option<PeerCredentials> {
+        peer_credentials(self.as_raw_fd()).ok()
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn peer_credentials(fd: RawFd) -> io::Result<PeerCredentials> {
+    let mut cred = libc::ucred { pid: 0, uid: 0, gid: 0 };
+    let mut len = mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(fd, libc::SOL_SOCKET, libc::SO_PEERCRED,
+                         &mut cred as *mut libc::ucred as *mut libc::c_void,
+                         &mut len)
+    };
+    if ret != 0 { return Err(io::Error::last_os_error()); }
+    Ok(PeerCredentials {
+        uid: cred.uid,
+        gid: cred.gid,
+        pid: Some(cred.pid),
+    })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn peer_credentials(fd: RawFd) -> io::Result<PeerCredentials> {
+    let (mut uid, mut gid) = (0, 0);
+    if unsafe { libc::getpeereid(fd, &mut uid, &mut gid) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(PeerCredentials {
+        uid: uid,
+        gid: gid,
+        pid: None,
+    })
+}
+
+impl Listener for UnixListener {
+    type Transport = UnixStream;
+
+    fn local_addr(&self) -> io::Result<UnixAddr> {
+        UnixListener::local_addr(self).map(UnixAddr::from)
+    }
+
+    fn incoming(self) -> Incoming<UnixStream> {
+        Box::new(UnixListener::incoming(self)
+                 .map(|(stream, addr)| (stream, UnixAddr::from(addr))))
+    }
+}
@@ -1,6 +1,110 @@
+use std::collections::{BTreeMap, HashMap};
+
+mod password;
+
+pub use self::password::PasswordHash;
 
 //use net::Node;
-use envelope::UserID;
+use envelope::{SchemeOptions, UserID};
+use envelope::resources::{Account, Contact};
+use transport::PeerCredentials;
+
+/// A registered user, keyed by identity (ex. `ww@breakingbad.com`).
+#[derive(Debug, Clone, Default)]
+pub struct User {
+    pub id: UserID,
+    pub password: Option<PasswordHash>,
+    pub account: Account,
+    pub contacts: BTreeMap<UserID, Contact>,
+    /// System user whose local processes may authenticate as this identity
+    /// with the `transport` scheme, without a password.
+    pub local_uid: Option<u32>,
+}
+
+impl User {
+    /// The password, if any, is only kept hashed.
+    pub fn new(id: &str, password: Option<String>) -> Self {
+        User {
+            id: id.to_owned(),
+            password: password.map(|password| PasswordHash::new(&password)),
+            ..User::default()
+        }
+    }
+
+    pub fn set_password(&mut self, password: &str) {
+        self.password = Some(PasswordHash::new(password));
+    }
+
+    /// Users without a password never authenticate with one, only through
+    /// their transport, see `verify_credentials`.
+    pub fn verify_password(&self, password: Option<&str>) -> bool {
+        match (self.password.as_ref(), password) {
+            (Some(stored), Some(password)) => stored.verify(password),
+            _ => false,
+        }
+    }
+
+    /// Whether the peer of a local connection may authenticate as this
+    /// user with the `transport` scheme.
+    pub fn verify_credentials(&self, credentials: &PeerCredentials) -> bool {
+        self.local_uid == Some(credentials.uid)
+    }
+
+    /// Whether a client proves it is this user with one of the session
+    /// schemes, from the password it sent or the credentials of its
+    /// connection. Guests are never registered users.
+    pub fn authenticate(&self, scheme: SchemeOptions, password: Option<&str>,
+                        credentials: Option<&PeerCredentials>) -> bool {
+        match scheme {
+            SchemeOptions::Plain => self.verify_password(password),
+            // Local clients are authenticated by the system user running
+            // them.
+            SchemeOptions::Transport => credentials
+                .map_or(false, |credentials| {
+                    self.verify_credentials(credentials)
+                }),
+            SchemeOptions::Guest => false,
+        }
+    }
+
+    pub fn has_contact(&self, id: &str) -> bool {
+        self.contacts.contains_key(id)
+    }
+
+    /// Whether messages from `sender` may be delivered to this user, which is
+    /// only refused when unknown senders are disallowed by the account.
+    pub fn accepts_sender(&self, sender: &str) -> bool {
+        self.account.allow_unknown_sender.unwrap_or(true) ||
+            sender == self.id || self.has_contact(sender)
+    }
+}
+
+/// Every known user, the server wraps this in an `Arc<Mutex<_>>` so that all
+/// sessions share it. Users are only ever added by `insert`, commands can't
+/// register new ones.
+#[derive(Debug, Default)]
+pub struct UserStore {
+    users: HashMap<UserID, User>,
+}
+
+impl UserStore {
+    pub fn new() -> Self {
+        UserStore { users: HashMap::new() }
+    }
+
+    pub fn get(&self, id: &str) -> Option<&User> {
+        self.users.get(id)
+    }
+
+    pub fn get_mut(&mut self, id: &str) -> Option<&mut User> {
+        self.users.get_mut(id)
+    }
+
+    pub fn insert(&mut self, user: User) -> Option<User> {
+        self.users.insert(user.id.clone(), user)
+    }
 
-/// TODO: Implement a User struct.
-pub struct User;
+    pub fn remove(&mut self, id: &str) -> Option<User> {
+        self.users.remove(id)
+    }
+}
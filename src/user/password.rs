@@ -0,0 +1,90 @@
+//! Passwords are only kept hashed, with PBKDF2-HMAC-SHA256 and a random salt
+//! per password so that guessing them from a leaked store is slow.
+//!
+//! The algorithm and the iteration count are kept with each hash, so the
+//! count can be raised for new passwords without losing the old ones.
+
+use std::fmt;
+
+use base64;
+use rand;
+use ring::pbkdf2;
+
+static ALGORITHM: &'static str = "pbkdf2-sha256";
+
+/// Iterations for new passwords.
+pub const ITERATIONS: u32 = 100_000;
+
+#[derive(Clone, PartialEq)]
+pub struct PasswordHash {
+    iterations: u32,
+    salt: [u8; 16],
+    hash: [u8; 32],
+}
+
+impl PasswordHash {
+    pub fn new(password: &str) -> Self {
+        let salt = rand::random::<[u8; 16]>();
+        let mut hash = [0; 32];
+        pbkdf2::derive(&pbkdf2::HMAC_SHA256, ITERATIONS as usize, &salt,
+                       password.as_bytes(), &mut hash);
+        PasswordHash {
+            iterations: ITERATIONS,
+            salt: salt,
+            hash: hash,
+        }
+    }
+
+    /// Compares in constant time, whatever the password.
+    pub fn verify(&self, password: &str) -> bool {
+        pbkdf2::verify(&pbkdf2::HMAC_SHA256, self.iterations as usize,
+                       &self.salt, password.as_bytes(), &self.hash).is_ok()
+    }
+
+    pub fn iterations(&self) -> u32 {
+        self.iterations
+    }
+
+    /// The hash as it is stored, with its algorithm and iteration count,
+    /// ex. `pbkdf2-sha256$100000$<salt>$<hash>` in base64.
+    pub fn encode(&self) -> String {
+        format!("{}${}${}${}", ALGORITHM, self.iterations,
+                base64::encode(&self.salt), base64::encode(&self.hash))
+    }
+
+    /// Reads a hash written by `encode`, `None` if it is malformed or was
+    /// made with another algorithm.
+    pub fn decode(encoded: &str) -> Option<Self> {
+        let parts: Vec<&str> = encoded.split('$').collect();
+        if parts.len() != 4 || parts[0] != ALGORITHM {
+            return None;
+        }
+        let iterations = match parts[1].parse() {
+            Ok(0) | Err(_) => return None,
+            Ok(iterations) => iterations,
+        };
+        let (salt, hash) = match (base64::decode(parts[2]),
+                                  base64::decode(parts[3])) {
+            (Ok(ref salt), Ok(ref hash))
+                if salt.len() == 16 && hash.len() == 32 =>
+                (salt.clone(), hash.clone()),
+            _ => return None,
+        };
+
+        let mut stored = PasswordHash {
+            iterations: iterations,
+            salt: [0; 16],
+            hash: [0; 32],
+        };
+        stored.salt.copy_from_slice(&salt);
+        stored.hash.copy_from_slice(&hash);
+        Some(stored)
+    }
+}
+
+/// Hashes are never printed.
+impl fmt::Debug for PasswordHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PasswordHash(..)")
+    }
+}
@@ -0,0 +1,68 @@
+extern crate rust_lime;
+extern crate serde_json;
+
+use rust_lime::envelope::Command;
+use rust_lime::envelope::command::{CommandMethod, CommandStatus};
+use rust_lime::envelope::reason::ReasonCode;
+use rust_lime::server::command::{CommandRouter, Request, Response};
+use serde_json::{Map, Value};
+
+fn command(method: CommandMethod, uri: &str) -> Command {
+    Command {
+        to: None,
+        from: Some("skyler@breakingbad.com/bedroom".to_string()),
+        pp: None,
+        id: Some(7),
+        metadata: None,
+        method: method,
+        status: None,
+        uri: Some(uri.to_string()),
+        mime_type: None,
+        resource: None,
+        extras: Map::new(),
+    }
+}
+
+fn router() -> CommandRouter {
+    let mut router = CommandRouter::new();
+    router.register(CommandMethod::Get, "/contacts/{identity}",
+                    |req: &Request| {
+        let identity = req.params["identity"].clone();
+        Ok(Response::resource("text/plain", Value::String(identity)))
+    });
+    router
+}
+
+#[test]
+fn command_route_params() {
+    let caller = "skyler@breakingbad.com/bedroom".to_string();
+    let res = router().dispatch(&caller,
+        &command(CommandMethod::Get, "lime://breakingbad.com/contacts/ww"));
+
+    assert_eq!(res.id, Some(7));
+    assert_eq!(res.to, Some(caller));
+    assert_eq!(res.status, Some(CommandStatus::Success));
+    assert_eq!(res.resource, Some(Value::String("ww".to_string())));
+}
+
+#[test]
+fn command_route_failures() {
+    let caller = "skyler@breakingbad.com/bedroom".to_string();
+    let router = router();
+
+    let res = router.dispatch(&caller,
+        &command(CommandMethod::Get, "/account"));
+    match res.status {
+        Some(CommandStatus::Failure(reason)) =>
+            assert_eq!(reason.code, ReasonCode::CommandResourceNotSupported),
+        _ => panic!("Unknown resource did not fail"),
+    }
+
+    let res = router.dispatch(&caller,
+        &command(CommandMethod::Delete, "/contacts/ww"));
+    match res.status {
+        Some(CommandStatus::Failure(reason)) =>
+            assert_eq!(reason.code, ReasonCode::CommandMethodNotSupported),
+        _ => panic!("Unsupported method did not fail"),
+    }
+}
@@ -1,7 +1,7 @@
 extern crate rust_lime;
 extern crate serde_json;
 
-use rust_lime::envelope::SealedEnvelope;
+use rust_lime::envelope::Envelope;
 use serde_json::from_str;
 use serde_json::Value::*;
 
@@ -13,8 +13,8 @@ fn message_basic() {
             "type": "text/plain",
             "content": "Walter, are you in danger?"    
         }"#;
-    let message : SealedEnvelope = from_str(message_json).unwrap();
-    let message = if let SealedEnvelope::Message(msg) = message {
+    let message : Envelope = from_str(message_json).unwrap();
+    let message = if let Envelope::Message(msg) = message {
         msg
     } else {
         panic!("Non-message envelope parsed from json with content")
@@ -32,14 +32,14 @@ fn notification_basic() {
     use rust_lime::envelope::NotificationEvent::*;
 
     let notification_json = r#"{
-            "id": "54321",
+            "id": 54321,
             "from": "skyler@breakingbad.com/bedroom",
             "to": "heisenberg@breakingbad.com/bedroom",
             "event": "received"
         }"#;
-    let notification : SealedEnvelope = from_str(notification_json).unwrap();
+    let notification : Envelope = from_str(notification_json).unwrap();
     let notification =
-            if let SealedEnvelope::Notification(notify) = notification {
+            if let Envelope::Notification(notify) = notification {
         notify
     } else {
         panic!("Non-notification envelope parsed from json with event")
@@ -58,7 +58,7 @@ fn notification_failure() {
     use rust_lime::envelope::ErrReason;
 
     let notification_json = r#"{
-            "id": "12345",
+            "id": 12345,
             "to": "skyler@breakingbad.com/bedroom",
             "event": "failed",
             "reason": {
@@ -66,9 +66,9 @@ fn notification_failure() {
                 "description": "The message destination was not found"
             }
         }"#;
-    let notification : SealedEnvelope = from_str(notification_json).unwrap();
+    let notification : Envelope = from_str(notification_json).unwrap();
     let notification =
-            if let SealedEnvelope::Notification(notify) = notification {
+            if let Envelope::Notification(notify) = notification {
         notify
     } else {
         panic!("Non-notification envelope parsed from json with event")
@@ -84,3 +84,156 @@ fn notification_failure() {
         description: Some("The message destination was not found"
             .to_string()) }));
 }
+
+#[test]
+fn message_kind_and_serialization() {
+    use rust_lime::envelope::Message;
+    use rust_lime::envelope::message::MessageType;
+
+    let message_json = r#"{
+            "to": "ww@breakingbad.com",
+            "type": "text/plain",
+            "content": "Say my name."
+        }"#;
+    let mut message : Message = match from_str(message_json).unwrap() {
+        Envelope::Message(msg) => msg,
+        _ => panic!("Non-message envelope parsed from json with content"),
+    };
+    assert_eq!(message.kind(), MessageType::Chat);
+
+    let json = serde_json::to_string(&message).unwrap();
+    assert_eq!(json, concat!(r#"{"to":"ww@breakingbad.com","#,
+                             r#""type":"text/plain","#,
+                             r#""content":"Say my name."}"#));
+
+    message.to = Some("family@groups.breakingbad.com".to_string());
+    assert_eq!(message.kind(), MessageType::Groupchat);
+    message.to = Some("breakingbad.com".to_string());
+    assert_eq!(message.kind(), MessageType::Normal);
+
+    let kind = from_str(r#"{ "#message.kind": "error" }"#).unwrap();
+    message.metadata = Some(kind);
+    assert_eq!(message.kind(), MessageType::Error);
+
+    assert_eq!("groupchat".parse(), Ok(MessageType::Groupchat));
+    assert!("broadcast".parse::<MessageType>().is_err());
+}
+
+#[test]
+fn envelope_sender() {
+    let message_json = r#"{
+            "to": "jesse@breakingbad.com",
+            "from": "ww@breakingbad.com",
+            "type": "text/plain",
+            "content": "Jesse, we need to cook."
+        }"#;
+    let message : Envelope = from_str(message_json).unwrap();
+    assert_eq!(message.sender(), Some(&"ww@breakingbad.com".to_string()));
+    assert_eq!(message.pp(), None);
+
+    let command_json = r#"{
+            "from": "ww@breakingbad.com",
+            "pp": "saul@breakingbad.com/bot",
+            "method": "get",
+            "uri": "/account"
+        }"#;
+    let command : Envelope = from_str(command_json).unwrap();
+    assert_eq!(command.from(), Some(&"ww@breakingbad.com".to_string()));
+    assert_eq!(command.sender(),
+               Some(&"saul@breakingbad.com/bot".to_string()));
+}
+
+#[test]
+fn metadata_keys() {
+    use rust_lime::envelope::{Message, Metadata};
+    use rust_lime::envelope::message::MessageType;
+    use rust_lime::envelope::metadata::MetadataError;
+
+    let message_json = r#"{
+            "to": "jesse@breakingbad.com",
+            "type": "text/plain",
+            "content": "Hello ${name}",
+            "metadata": {
+                "#message.replaceVariables": "true",
+                "#envelope.originalId": "42",
+                "#trace.id": "abc",
+                "client.version": "1.0"
+            }
+        }"#;
+    let mut message : Message = match from_str(message_json).unwrap() {
+        Envelope::Message(msg) => msg,
+        _ => panic!("Non-message envelope parsed from json with content"),
+    };
+    {
+        let metadata = message.metadata.as_ref().unwrap();
+        assert!(metadata.replace_variables());
+        assert_eq!(metadata.original_id(), Some(42));
+        assert_eq!(metadata.trace_id(), Some("abc"));
+        assert_eq!(metadata.get("client.version"), Some("1.0"));
+        assert_eq!(metadata.kind(), None);
+        assert!(metadata.validate().is_ok());
+    }
+
+    // Unknown keys are sent back as they were received.
+    message.metadata.as_mut().unwrap().set_kind(MessageType::Chat);
+    let json = serde_json::to_value(&message);
+    let metadata = json.find("metadata").unwrap();
+    assert_eq!(metadata.find("client.version").and_then(|v| v.as_str()),
+               Some("1.0"));
+    assert_eq!(metadata.find("#message.kind").and_then(|v| v.as_str()),
+               Some("chat"));
+
+    let metadata: Metadata = from_str(r#"{ "#trace.id": 7 }"#).unwrap();
+    assert_eq!(metadata.trace_id(), None);
+    assert_eq!(metadata.validate(),
+               Err(MetadataError { key: "#trace.id".to_string() }));
+
+    // Every member of the group was reached.
+    let mut metadata = Metadata::new();
+    metadata.set_group_failed(&[]);
+    assert!(metadata.group_failed().is_empty());
+    metadata.set_group_failed(&["jesse@breakingbad.com".to_string()]);
+    assert_eq!(metadata.group_failed(), vec!["jesse@breakingbad.com"]);
+}
+
+#[test]
+fn timestamps() {
+    use std::time::{Duration, UNIX_EPOCH};
+    use rust_lime::envelope::{Metadata, TimeStamp};
+
+    let time = TimeStamp::parse("2016-11-20T18:42:07.123Z").unwrap();
+    assert_eq!(time.as_millis(), 1479667327123);
+    assert_eq!(time.to_string(), "2016-11-20T18:42:07.123Z");
+    assert_eq!(time.to_system_time(),
+               UNIX_EPOCH + Duration::from_millis(1479667327123));
+
+    // Offsets and any precision are accepted, times are written in UTC.
+    let time: TimeStamp = "2016-02-29T23:30:00+01:00".parse().unwrap();
+    assert_eq!(time.to_string(), "2016-02-29T22:30:00.000Z");
+    let time: TimeStamp = "2016-02-29T22:30:00.5-00:00".parse().unwrap();
+    assert_eq!(time.as_millis() % 1000, 500);
+    let time: TimeStamp = "2016-02-29T22:30:00.123456Z".parse().unwrap();
+    assert_eq!(time.as_millis() % 1000, 123);
+
+    for invalid in &["2016-02-30T00:00:00Z", "2016-11-20 18:42:07Z",
+                     "2016-11-20T18:42:07", "2016-11-20T24:00:00Z",
+                     "2016-11-20T18:42:07.Z", "1969-12-31T23:59:59Z"] {
+        assert!(TimeStamp::parse(invalid).is_err(), "{}", invalid);
+    }
+    assert_eq!(TimeStamp::parse("1969-12-31T23:59:59Z").unwrap_err()
+               .to_string(),
+               "The timestamp '1969-12-31T23:59:59Z' is before 1970");
+
+    let metadata: Metadata = from_str(
+        r#"{ "#envelope.timestamp": "2016-11-20T18:42:07.123Z" }"#).unwrap();
+    assert_eq!(metadata.timestamp().map(|time| time.as_millis()),
+               Some(1479667327123));
+    let metadata: Metadata = from_str(
+        r#"{ "#envelope.timestamp": "yesterday" }"#).unwrap();
+    assert_eq!(metadata.timestamp(), None);
+
+    // Envelopes without metadata get some when they are stamped.
+    let mut metadata = None;
+    Metadata::stamp(&mut metadata, time);
+    assert_eq!(metadata.as_ref().and_then(Metadata::timestamp), Some(time));
+}
@@ -0,0 +1,163 @@
+extern crate rust_lime;
+extern crate serde_json;
+
+use rust_lime::envelope::{Document, DocumentError, DocumentRegistry, Message};
+use rust_lime::envelope::document::PlainText;
+use rust_lime::envelope::resources::Presence;
+use serde_json::{from_str, Map, Value};
+
+/// Application specific document, registered at runtime.
+#[derive(Debug, PartialEq)]
+struct Ping(u64);
+
+impl Document for Ping {
+    fn media_type() -> &'static str { "application/x-ping+json" }
+
+    fn from_content(content: &Value) -> Result<Self, DocumentError> {
+        content.as_u64().map(Ping).ok_or_else(|| {
+            DocumentError::Invalid("ping must be a number".to_string())
+        })
+    }
+
+    fn to_content(&self) -> Value {
+        Value::U64(self.0)
+    }
+}
+
+#[test]
+fn document_content_as() {
+    let msg = Message::with_document(None,
+        &PlainText("Say my name.".to_string()));
+    assert_eq!(msg.mime_type, "text/plain");
+    assert_eq!(msg.content_as::<PlainText>().unwrap().0, "Say my name.");
+    match msg.content_as::<Presence>() {
+        Err(DocumentError::MediaType { .. }) => (),
+        _ => panic!("text/plain content read as a presence"),
+    }
+}
+
+#[test]
+fn document_registry() {
+    let mut registry = DocumentRegistry::new();
+    let mut msg = Message::with_document(None, &Ping(42));
+
+    match msg.document(&registry).unwrap().downcast::<Ping>() {
+        None => (),
+        Some(_) => panic!("Unregistered document was decoded"),
+    }
+
+    registry.register::<Ping>();
+    assert_eq!(msg.document(&registry).unwrap().downcast::<Ping>(),
+               Some(Ping(42)));
+
+    msg.mime_type = "application/vnd.lime.presence+json".parse().unwrap();
+    msg.content = from_str(r#"{ "status": "busy" }"#).unwrap();
+    let presence = msg.document(&registry).unwrap().downcast::<Presence>();
+    assert!(presence.unwrap().status.is_some());
+}
+
+#[test]
+fn media_type_parse() {
+    use rust_lime::envelope::MediaType;
+
+    let mime = MediaType::parse("Application/vnd.lime.account+json; \
+                                 charset=\"utf-8\"").unwrap();
+    assert_eq!(mime.top, "application");
+    assert_eq!(mime.subtype, "vnd.lime.account");
+    assert_eq!(mime.suffix, Some("json".to_string()));
+    assert_eq!(mime.parameter("charset"), Some("utf-8"));
+    assert_eq!(mime.essence(), "application/vnd.lime.account+json");
+    assert!(mime.is_json());
+
+    assert!(!MediaType::parse("text/plain").unwrap().is_json());
+    assert!(MediaType::parse("application/json").unwrap().is_json());
+    assert!(MediaType::parse("text").is_err());
+    assert!(MediaType::parse("text/plain; charset").is_err());
+}
+
+#[test]
+fn media_type_validates_content() {
+    use rust_lime::envelope::Envelope;
+
+    let plain_object = r#"{
+            "to": "ww@breakingbad.com",
+            "type": "text/plain",
+            "content": { "text": "Walter?" }
+        }"#;
+    assert!(from_str::<Envelope>(plain_object).is_err());
+
+    let json_object = r#"{
+            "to": "ww@breakingbad.com",
+            "type": "application/json",
+            "content": { "text": "Walter?" }
+        }"#;
+    assert!(from_str::<Envelope>(json_object).is_ok());
+}
+
+/// Parses the json, checks it reads back as `T` and serializes unchanged.
+fn round_trip<T>(mime_type: &str, json: &str) -> T
+    where T: Document + std::fmt::Debug + PartialEq
+{
+    let content : Value = from_str(json).unwrap();
+    let msg = Message {
+        to: None, from: None, pp: None, id: None, metadata: None,
+        mime_type: mime_type.parse().unwrap(),
+        content: content.clone(),
+        extras: Map::new(),
+    };
+    let doc = msg.content_as::<T>().unwrap();
+    assert_eq!(doc.to_content(), content);
+    assert_eq!(Message::with_document(None, &doc).mime_type, mime_type);
+    doc
+}
+
+#[test]
+fn document_round_trips() {
+    use rust_lime::envelope::message::*;
+
+    let link : MediaLink = round_trip("application/vnd.lime.media-link+json",
+        r#"{ "type": "image/jpeg", "uri": "http://x.com/a.jpg",
+             "size": 1024, "previewUri": "http://x.com/a_small.jpg",
+             "title": "Blue" }"#);
+    assert_eq!(link.mime_type.essence(), "image/jpeg");
+
+    let web : WebLink = round_trip("application/vnd.lime.web-link+json",
+        r#"{ "uri": "http://breakingbad.com", "title": "Breaking Bad" }"#);
+    assert_eq!(web.text, None);
+
+    let state : ChatState = round_trip("application/vnd.lime.chatstate+json",
+        r#"{ "state": "composing" }"#);
+    assert_eq!(state.state, State::Composing);
+
+    let location : Location = round_trip(
+        "application/vnd.lime.location+json",
+        r#"{ "latitude": 35.0844, "longitude": -106.6504,
+             "text": "Albuquerque" }"#);
+    assert_eq!(location.altitude, None);
+
+    let select : Select = round_trip("application/vnd.lime.select+json",
+        r#"{ "text": "Who are you?", "options": [
+                { "order": 1, "text": "The one who knocks" },
+                { "order": 2, "text": "Heisenberg",
+                  "type": "application/json", "value": { "id": 2 } }
+             ] }"#);
+    assert_eq!(select.options.len(), 2);
+}
+
+#[test]
+fn document_collections() {
+    use rust_lime::envelope::message::*;
+
+    let container = DocumentContainer::new(
+        &PlainText("Say my name.".to_string()));
+    let collection = Collection::of(&[container.clone()]);
+    let json = serde_json::to_string(&collection).unwrap();
+    let collection : Collection = from_str(&json).unwrap();
+
+    assert_eq!(collection.total, 1);
+    assert_eq!(collection.item_type, "application/vnd.lime.container+json");
+    let items = collection.items_as::<DocumentContainer>().unwrap();
+    assert_eq!(items[0], container);
+    assert_eq!(items[0].document_as::<PlainText>().unwrap().0, "Say my name.");
+    assert!(collection.items_as::<Location>().is_err());
+}
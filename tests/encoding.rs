@@ -0,0 +1,230 @@
+extern crate rust_lime;
+extern crate serde_json;
+extern crate tokio_core;
+
+use tokio_core::io::{Codec, EasyBuf};
+
+use rust_lime::envelope::{CborCodec, Encoding, Envelope, EnvelopeCodec,
+    LimeCodec};
+use serde_json::Value;
+
+static ENVELOPES: &'static [&'static str] = &[
+    r#"{
+        "id": 1,
+        "from": "skyler@breakingbad.com/bedroom",
+        "to": "ww@breakingbad.com",
+        "type": "text/plain",
+        "content": "Walter, are you in danger?"
+    }"#,
+    r#"{
+        "id": 2,
+        "to": "jesse@breakingbad.com",
+        "type": "application/json",
+        "content": { "batch": 42, "purity": 99.1, "blue": true },
+        "metadata": { "#message.replaceVariables": "true" }
+    }"#,
+    r#"{
+        "id": 3,
+        "from": "jesse@breakingbad.com/home",
+        "to": "ww@breakingbad.com/lab",
+        "event": "failed",
+        "reason": { "code": 42, "description": "Nope" }
+    }"#,
+    r#"{
+        "id": 4,
+        "method": "set",
+        "uri": "/account",
+        "type": "application/vnd.lime.account+json",
+        "resource": { "fullName": "Walter White", "inboxSize": 100 }
+    }"#,
+    r#"{
+        "id": 5,
+        "from": "ww@breakingbad.com/lab",
+        "state": "negotiating",
+        "encryption": "none",
+        "compression": "gzip"
+    }"#,
+];
+
+fn encode<C: Codec>(codec: &mut C, envelope: C::Out) -> Vec<u8> {
+    let mut buf = Vec::new();
+    codec.encode(envelope, &mut buf).unwrap();
+    buf
+}
+
+fn to_json(envelope: Envelope) -> Value {
+    let buf = encode(&mut LimeCodec, envelope);
+    serde_json::from_slice(&buf).unwrap()
+}
+
+#[test]
+fn cbor_round_trips() {
+    for json in ENVELOPES {
+        let envelope: Envelope = serde_json::from_str(json).unwrap();
+        let expected = to_json(serde_json::from_str(json).unwrap());
+
+        let cbor = encode(&mut CborCodec, envelope);
+        assert_eq!(cbor[0], 0);
+        assert!(cbor.len() < encode(&mut LimeCodec,
+                                    serde_json::from_str(json).unwrap())
+                                    .len());
+
+        // Only complete frames are decoded.
+        let mut buf = EasyBuf::from(cbor[..cbor.len() - 1].to_vec());
+        assert!(CborCodec.decode(&mut buf).unwrap().is_none());
+        let mut buf = EasyBuf::from(cbor);
+        let decoded = CborCodec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(buf.len(), 0);
+        assert_eq!(to_json(decoded), expected);
+    }
+}
+
+#[test]
+fn encoding_negotiation() {
+    let envelope = || serde_json::from_str::<Envelope>(ENVELOPES[0]).unwrap();
+
+    // The server answers in the encoding picked by the client.
+    for &encoding in &[Encoding::Json, Encoding::Cbor] {
+        let mut client = EnvelopeCodec::new(encoding);
+        let mut server = EnvelopeCodec::negotiate();
+        assert_eq!(server.encoding(), None);
+
+        let mut buf = EasyBuf::from(encode(&mut client, envelope()));
+        assert!(server.decode(&mut buf).unwrap().is_some());
+        assert_eq!(server.encoding(), Some(encoding));
+
+        let mut buf = EasyBuf::from(encode(&mut server, envelope()));
+        let answer = client.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(to_json(answer), to_json(envelope()));
+    }
+
+    // Json is written until the client picked.
+    let buf = encode(&mut EnvelopeCodec::negotiate(), envelope());
+    assert_eq!(buf[0], b'{');
+}
+
+#[test]
+fn lazy_decode() {
+    use rust_lime::envelope::{EnvelopeType, RawCodec};
+
+    let kinds = [EnvelopeType::Message, EnvelopeType::Message,
+                 EnvelopeType::Notification, EnvelopeType::Command,
+                 EnvelopeType::Session];
+    for (json, &kind) in ENVELOPES.iter().zip(kinds.iter()) {
+        let mut frame = json.replace("\n", "").into_bytes();
+        frame.push(b'\n');
+        let mut buf = EasyBuf::from(frame.clone());
+        let raw = RawCodec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(raw.headers.kind, kind);
+
+        let envelope = raw.decode().unwrap();
+        assert_eq!(raw.headers.id, envelope.id());
+        assert_eq!(raw.headers.from.as_ref(), envelope.from());
+
+        // Forwarded envelopes are the bytes they were received as.
+        assert_eq!(encode(&mut RawCodec, raw), frame);
+    }
+
+    // Decoded envelopes are written back with the type they were sent with,
+    // until it is changed.
+    let frame = concat!(r#"{"to":"ww@breakingbad.com","#,
+                        r#""type":"Text/Plain; Charset=\"UTF-8\"","#,
+                        r#""content":"Say my name."}"#, "\n");
+    let mut buf = EasyBuf::from(frame.as_bytes().to_vec());
+    let raw = RawCodec.decode(&mut buf).unwrap().unwrap();
+    let mut msg = match raw.decode().unwrap() {
+        Envelope::Message(msg) => msg,
+        other => panic!("Expected a message, got {:?}", other),
+    };
+    assert_eq!(msg.mime_type, "text/plain; charset=UTF-8");
+    let written = to_json(Envelope::Message(msg.clone()));
+    assert_eq!(written.find("type").and_then(Value::as_str),
+               Some(r#"Text/Plain; Charset="UTF-8""#));
+    msg.mime_type.parameters.clear();
+    let written = to_json(Envelope::Message(msg));
+    assert_eq!(written.find("type").and_then(Value::as_str),
+               Some("text/plain"));
+
+    // Envelopes without content, event, method or state are rejected.
+    let frame = b"{ \"to\": \"ww@breakingbad.com\" }\n";
+    let mut buf = EasyBuf::from(frame.to_vec());
+    assert!(RawCodec.decode(&mut buf).is_err());
+}
+
+#[test]
+fn parse_policies() {
+    use rust_lime::envelope::{from_slice, ParsePolicy};
+
+    let forwarded = br#"{
+        "id": 5,
+        "to": "saul@bettercallsaul.com",
+        "type": "text/plain",
+        "content": "S'all good, man.",
+        "status": "success",
+        "x-client": { "name": "lawyer", "version": 2 }
+    }"#;
+
+    // Unknown fields and fields of other kinds are kept and written back.
+    let envelope = from_slice(forwarded, ParsePolicy::Lenient).unwrap();
+    match envelope {
+        Envelope::Message(ref msg) => assert_eq!(msg.extras.len(), 2),
+        ref other => panic!("Expected a message, got {:?}", other),
+    }
+    let expected: Value = serde_json::from_slice(forwarded).unwrap();
+    assert_eq!(to_json(envelope), expected);
+
+    // So are envelopes of a kind this implementation doesn't know.
+    let unknown = br#"{ "id": 6, "to": "saul@bettercallsaul.com",
+                        "x-kind": "fax" }"#;
+    let envelope = from_slice(unknown, ParsePolicy::Lenient).unwrap();
+    match envelope {
+        Envelope::Unknown(ref fields) => assert_eq!(fields.len(), 3),
+        ref other => panic!("Expected an unknown envelope, got {:?}", other),
+    }
+    assert_eq!(envelope.id(), Some(6));
+    let expected: Value = serde_json::from_slice(unknown).unwrap();
+    assert_eq!(to_json(envelope), expected);
+
+    let error = |json: &str| {
+        from_slice(json.as_bytes(), ParsePolicy::Strict).unwrap_err()
+            .to_string()
+    };
+    assert!(from_slice(ENVELOPES[0].as_bytes(), ParsePolicy::Strict).is_ok());
+    assert!(error(::std::str::from_utf8(forwarded).unwrap())
+            .contains("Field 'status' is not allowed in a message"));
+    assert!(error(r#"{ "event": "received", "id": 1, "color": "blue" }"#)
+            .contains("Unknown field 'color'"));
+    assert!(error(r#"{ "event": "received", "id": 1, "id": 2 }"#)
+            .contains("Duplicate field 'id'"));
+    assert!(error(r#"{ "event": "received", "id": "one" }"#)
+            .contains("Invalid field 'id'"));
+    assert!(error(r#"{ "event": "received" }"#)
+            .contains("Missing field 'id' in a notification"));
+    assert!(error(r#"{ "event": "received", "id": 1,
+                       "metadata": { "#trace.id": 7 } }"#)
+            .contains("#trace.id"));
+    assert!(error(r#"{ "event": "failed", "id": 1 }"#)
+            .contains("A failed notification must have a reason"));
+    assert!(from_slice(ENVELOPES[4].as_bytes(), ParsePolicy::Strict).is_ok());
+    assert!(from_slice(br#"{ "state": "authenticating", "id": 1,
+                             "scheme": "plain",
+                             "authentication": { "password": "U2F5" } }"#,
+                       ParsePolicy::Strict).is_ok());
+    assert!(error(r#"{ "state": "new", "id": 1, "type": "text/plain" }"#)
+            .contains("Field 'type' is not allowed in a session"));
+    assert!(error(r#"{ "state": "failed", "id": 1 }"#)
+            .contains("A failed session must have a reason"));
+    assert!(error(::std::str::from_utf8(unknown).unwrap())
+            .contains("The kind of the envelope is unknown"));
+
+    // Codecs are lenient unless told otherwise.
+    let mut frame = ::std::str::from_utf8(forwarded).unwrap()
+        .replace("\n", "").into_bytes();
+    frame.push(b'\n');
+    let mut buf = EasyBuf::from(frame.clone());
+    assert!(EnvelopeCodec::negotiate().decode(&mut buf).unwrap().is_some());
+    let mut strict = EnvelopeCodec::negotiate()
+        .with_policy(ParsePolicy::Strict);
+    let mut buf = EasyBuf::from(frame);
+    assert!(strict.decode(&mut buf).is_err());
+}
@@ -0,0 +1,347 @@
+extern crate rust_lime;
+extern crate serde_json;
+
+use rust_lime::envelope::{Envelope, NotificationEvent};
+use rust_lime::envelope::reason::ReasonCode;
+use rust_lime::envelope::command::{CommandMethod, CommandStatus};
+use rust_lime::envelope::session::SessionState;
+use rust_lime::server::harness::Harness;
+use rust_lime::user::User;
+use serde_json::{from_str, Value};
+
+static PASSWORD: &'static str = "Say my name";
+
+fn register(harness: &Harness, node: &str) {
+    let identity = node.split('/').next().unwrap();
+    harness.server().registry().lock().unwrap()
+        .insert(User::new(identity, Some(PASSWORD.to_string())));
+}
+
+/// A session for each of the `nodes`, whose identities are registered.
+fn harness(nodes: &[&str]) -> Harness {
+    let mut harness = Harness::new();
+    for node in nodes {
+        register(&harness, node);
+        harness.connect(node, PASSWORD);
+    }
+    harness
+}
+
+fn message(id: u64, to: &str, text: &str) -> Envelope {
+    from_str(&format!(r#"{{
+            "id": {},
+            "to": "{}",
+            "type": "text/plain",
+            "content": "{}"
+        }}"#, id, to, text)).unwrap()
+}
+
+fn available() -> Envelope {
+    from_str(r#"{
+            "id": 10,
+            "method": "set",
+            "uri": "/presence",
+            "type": "application/vnd.lime.presence+json",
+            "resource": { "status": "available", "routingRule": "identity" }
+        }"#).unwrap()
+}
+
+fn event(envelope: Option<Envelope>) -> NotificationEvent {
+    match envelope {
+        Some(Envelope::Notification(notification)) => notification.event,
+        other => panic!("Expected a notification, got {:?}", other),
+    }
+}
+
+#[test]
+fn harness_negotiation() {
+    let mut harness = Harness::new();
+    register(&harness, "ww@breakingbad.com");
+
+    // Nothing but `none` to negotiate in memory, the schemes come first.
+    let ww = harness.open();
+    harness.send(ww, from_str(r#"{ "id": 1, "state": "new" }"#).unwrap());
+    match harness.receive(ww) {
+        Some(Envelope::Session(session)) => {
+            assert_eq!(session.state, SessionState::Authenticating);
+            assert_eq!(session.scheme_options,
+                       Some(vec![Value::String("plain".to_string())]));
+        }
+        other => panic!("Expected a session, got {:?}", other),
+    }
+    harness.send(ww, from_str(r#"{
+            "id": 1,
+            "from": "ww@breakingbad.com/lab",
+            "state": "authenticating",
+            "scheme": "plain",
+            "authentication": { "password": "SGVpc2VuYmVyZw==" }
+        }"#).unwrap());
+    match harness.receive(ww) {
+        Some(Envelope::Session(session)) => match session.state {
+            SessionState::Failed(reason) => assert_eq!(
+                reason.code, ReasonCode::SessionAuthenticationFailed),
+            other => panic!("Expected a failure, got {:?}", other),
+        },
+        other => panic!("Expected a session, got {:?}", other),
+    }
+    // The server closed the connection.
+    assert!(harness.receive(ww).is_none());
+
+    let ww = harness.connect("ww@breakingbad.com/lab", PASSWORD);
+    assert_eq!(harness.node(ww), "ww@breakingbad.com/lab");
+}
+
+#[test]
+fn harness_exchange() {
+    let mut harness = harness(&["ww@breakingbad.com/lab",
+                              "jesse@breakingbad.com/home"]);
+    let (ww, jesse) = (0, 1);
+
+    harness.send(jesse, available());
+    match harness.receive(jesse) {
+        Some(Envelope::Command(cmd)) =>
+            assert_eq!(cmd.status, Some(CommandStatus::Success)),
+        other => panic!("Expected a command, got {:?}", other),
+    }
+
+    harness.send(ww, message(1, "jesse@breakingbad.com",
+                             "Jesse, we need to cook."));
+    assert_eq!(event(harness.receive(ww)), NotificationEvent::Dispatched);
+    match harness.receive(jesse) {
+        Some(Envelope::Message(msg)) => {
+            assert_eq!(msg.from, Some(harness.node(ww).clone()));
+            assert_eq!(msg.content.as_str(), Some("Jesse, we need to cook."));
+        }
+        other => panic!("Expected a message, got {:?}", other),
+    }
+    assert!(harness.receive_all(jesse).is_empty());
+
+    // Once jesse is gone the message waits for the next session.
+    harness.disconnect(jesse);
+    harness.send(ww, message(2, "jesse@breakingbad.com", "Yo, Mr. White?"));
+    assert_eq!(event(harness.receive(ww)), NotificationEvent::Accepted);
+
+    let jesse = harness.connect("jesse@breakingbad.com/car", PASSWORD);
+    let received = harness.receive_all(jesse);
+    assert_eq!(received.len(), 1);
+    assert_eq!(event(harness.receive(ww)), NotificationEvent::Dispatched);
+
+    // Nothing is kept for identities the server doesn't know.
+    harness.send(ww, message(3, "saul@breakingbad.com", "Better call."));
+    match event(harness.receive(ww)) {
+        NotificationEvent::Failed(reason) =>
+            assert_eq!(reason.code, ReasonCode::RoutingDestinationNotFound),
+        other => panic!("Expected a failure, got {:?}", other),
+    }
+}
+
+#[test]
+fn unroutable_sessions() {
+    let mut harness = harness(&["ww@breakingbad.com/lab",
+                              "jesse@breakingbad.com/home"]);
+    let (ww, jesse) = (0, 1);
+
+    // Online without a routable presence, the message is kept for jesse.
+    harness.send(ww, message(1, "jesse@breakingbad.com", "Jesse?"));
+    assert_eq!(event(harness.receive(ww)), NotificationEvent::Accepted);
+    assert!(harness.receive_all(jesse).is_empty());
+
+    // It is delivered as soon as jesse becomes routable.
+    harness.send(jesse, available());
+    let received = harness.receive_all(jesse);
+    assert_eq!(received.len(), 2);
+    assert!(received.iter().any(|envelope| match *envelope {
+        Envelope::Message(ref msg) => msg.content.as_str() == Some("Jesse?"),
+        _ => false,
+    }));
+    assert!(received.iter().any(|envelope| match *envelope {
+        Envelope::Command(ref cmd) =>
+            cmd.status == Some(CommandStatus::Success),
+        _ => false,
+    }));
+    assert_eq!(event(harness.receive(ww)), NotificationEvent::Dispatched);
+}
+
+#[test]
+fn harness_timestamps() {
+    use rust_lime::envelope::{Metadata, TimeStamp};
+
+    let timestamp = |metadata: &Option<Metadata>| {
+        metadata.as_ref().and_then(Metadata::timestamp)
+            .expect("The envelope is stamped")
+    };
+
+    let mut harness = harness(&["ww@breakingbad.com/lab",
+                              "jesse@breakingbad.com/home"]);
+    let (ww, jesse) = (0, 1);
+
+    // Commands are answered with when they were.
+    let before = TimeStamp::now();
+    harness.send(jesse, available());
+    match harness.receive(jesse) {
+        Some(Envelope::Command(res)) =>
+            assert!(timestamp(&res.metadata) >= before),
+        other => panic!("Expected a command, got {:?}", other),
+    }
+
+    // The time claimed by the sender is replaced by the server's.
+    let claimed = TimeStamp::parse("2008-01-20T21:00:00Z").unwrap();
+    let mut msg = match message(1, "jesse@breakingbad.com", "Yo.") {
+        Envelope::Message(msg) => msg,
+        _ => unreachable!(),
+    };
+    Metadata::stamp(&mut msg.metadata, claimed);
+    let before = TimeStamp::now();
+    harness.send(ww, Envelope::Message(msg));
+
+    let received = match harness.receive(jesse) {
+        Some(Envelope::Message(msg)) => timestamp(&msg.metadata),
+        other => panic!("Expected a message, got {:?}", other),
+    };
+    assert!(received >= before);
+    match harness.receive(ww) {
+        Some(Envelope::Notification(notification)) =>
+            assert!(timestamp(&notification.metadata) >= received),
+        other => panic!("Expected a notification, got {:?}", other),
+    }
+}
+
+#[test]
+fn unauthorized_notifications() {
+    let mut harness = harness(&["ww@breakingbad.com/lab",
+                              "jesse@breakingbad.com/home"]);
+    let (ww, jesse) = (0, 1);
+    harness.send(jesse, available());
+    harness.receive_all(jesse);
+
+    let received = || -> Envelope {
+        from_str(r#"{
+            "id": 1,
+            "to": "jesse@breakingbad.com",
+            "event": "received"
+        }"#).unwrap()
+    };
+    harness.send(ww, received());
+    assert_eq!(event(harness.receive(jesse)), NotificationEvent::Received);
+
+    harness.server().registry().lock().unwrap()
+        .get_mut("jesse@breakingbad.com").unwrap()
+        .account.allow_unknown_sender = Some(false);
+    harness.send(ww, received());
+    assert!(harness.receive_all(jesse).is_empty());
+}
+
+#[test]
+fn delegated_commands() {
+    let mut harness = harness(&["ww@breakingbad.com/lab",
+                              "saul@breakingbad.com/office"]);
+    let (ww, saul) = (0, 1);
+    harness.send(ww, from_str(r#"{
+            "id": 1,
+            "method": "set",
+            "uri": "/delegations",
+            "type": "application/vnd.lime.delegation+json",
+            "resource": { "target": "saul@breakingbad.com" }
+        }"#).unwrap());
+    harness.receive_all(ww);
+
+    {
+        let mut status = |uri: &str| {
+            let mut cmd = match available() {
+                Envelope::Command(cmd) => cmd,
+                _ => unreachable!(),
+            };
+            cmd.from = Some("ww@breakingbad.com".to_string());
+            cmd.uri = Some(uri.to_string());
+            harness.send(saul, Envelope::Command(cmd));
+            match harness.receive(saul) {
+                Some(Envelope::Command(res)) => res.status,
+                other => panic!("Expected a command, got {:?}", other),
+            }
+        };
+        assert_eq!(status("/presence"), Some(CommandStatus::Success));
+        // Even a delegation of every envelope type doesn't give the account,
+        // nor the groups and conversations of the identity.
+        for uri in &["/account", "/groups", "/threads"] {
+            match status(uri) {
+                Some(CommandStatus::Failure(reason)) =>
+                    assert_eq!(reason.code,
+                               ReasonCode::AuthorizationUnauthorizedSender),
+                other => panic!("Expected a failure, got {:?}", other),
+            }
+        }
+    }
+
+    // Changes are observed by the session which subscribed.
+    harness.send(saul, from_str(r#"{
+            "id": 2,
+            "from": "ww@breakingbad.com",
+            "method": "subscribe",
+            "uri": "/presence"
+        }"#).unwrap());
+    match harness.receive(saul) {
+        Some(Envelope::Command(res)) =>
+            assert_eq!(res.status, Some(CommandStatus::Success)),
+        other => panic!("Expected a command, got {:?}", other),
+    }
+    harness.send(ww, available());
+    harness.receive_all(ww);
+    match harness.receive(saul) {
+        Some(Envelope::Command(observe)) => {
+            assert_eq!(observe.method, CommandMethod::Observe);
+            assert_eq!(observe.to, Some(harness.node(saul).clone()));
+        }
+        other => panic!("Expected an observe command, got {:?}", other),
+    }
+}
+
+#[test]
+fn established_sessions_ignore_negotiation() {
+    let mut harness = harness(&["ww@breakingbad.com/lab"]);
+    harness.send(0, from_str(r#"{ "id": 3, "state": "authenticating" }"#)
+                 .unwrap());
+    assert!(harness.receive(0).is_none());
+
+    // The session still answers afterwards.
+    harness.send(0, available());
+    assert!(harness.receive(0).is_some());
+}
+
+#[test]
+fn invalid_metadata() {
+    let mut harness = harness(&["ww@breakingbad.com/lab",
+                                "jesse@breakingbad.com/home"]);
+    let (ww, jesse) = (0, 1);
+    harness.send(jesse, available());
+    harness.receive_all(jesse);
+
+    // Metadata values must be strings.
+    harness.send(ww, from_str(r#"{
+            "id": 1,
+            "to": "jesse@breakingbad.com",
+            "type": "text/plain",
+            "content": "Yo.",
+            "metadata": { "#trace.id": 7 }
+        }"#).unwrap());
+    match event(harness.receive(ww)) {
+        NotificationEvent::Failed(reason) =>
+            assert_eq!(reason.code, ReasonCode::ValidationError),
+        other => panic!("Expected a failure, got {:?}", other),
+    }
+    assert!(harness.receive_all(jesse).is_empty());
+
+    harness.send(ww, from_str(r#"{
+            "id": 2,
+            "method": "get",
+            "uri": "/account",
+            "metadata": { "#trace.id": 7 }
+        }"#).unwrap());
+    match harness.receive(ww) {
+        Some(Envelope::Command(res)) => match res.status {
+            Some(CommandStatus::Failure(reason)) =>
+                assert_eq!(reason.code, ReasonCode::ValidationError),
+            other => panic!("Expected a failure, got {:?}", other),
+        },
+        other => panic!("Expected a command, got {:?}", other),
+    }
+}
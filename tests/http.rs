@@ -0,0 +1,194 @@
+extern crate base64;
+extern crate rust_lime;
+extern crate serde_json;
+extern crate tokio_core;
+
+use tokio_core::io::{Codec, EasyBuf};
+use tokio_core::net::TcpStream;
+
+use rust_lime::envelope::EnvelopeStream;
+use rust_lime::server::LimeServer;
+use rust_lime::server::http::{HttpCodec, HttpRequest};
+use rust_lime::user::User;
+use serde_json::Value;
+
+type Server = LimeServer<EnvelopeStream<TcpStream>>;
+
+fn request(method: &str, path: &str, credentials: Option<&str>, body: &str)
+        -> HttpRequest {
+    let mut headers = Vec::new();
+    if let Some(credentials) = credentials {
+        let credentials = base64::encode(credentials.as_bytes());
+        headers.push(("Authorization".to_string(),
+                      format!("Basic {}", credentials)));
+    }
+    HttpRequest {
+        method: method.to_string(),
+        path: path.to_string(),
+        headers: headers,
+        body: body.as_bytes().to_vec(),
+        credentials: None,
+    }
+}
+
+fn json(body: &[u8]) -> Value {
+    serde_json::from_slice(body).unwrap()
+}
+
+#[test]
+fn codec_request() {
+    let raw = b"POST /messages HTTP/1.1\r\n\
+                Host: localhost\r\n\
+                Content-Length: 2\r\n\r\n{}GET /messages HTTP/1.1\r\n\r\n";
+    let mut codec = HttpCodec::new(None);
+    let mut buf = EasyBuf::from(raw[..raw.len() - 27].to_vec());
+    assert!(codec.decode(&mut buf).unwrap().is_none());
+
+    let mut buf = EasyBuf::from(raw.to_vec());
+    let post = codec.decode(&mut buf).unwrap().unwrap();
+    assert_eq!(post.method, "POST");
+    assert_eq!(post.header("content-length"), Some("2"));
+    assert_eq!(post.body, b"{}".to_vec());
+    let get = codec.decode(&mut buf).unwrap().unwrap();
+    assert_eq!(get.path, "/messages");
+    assert!(get.body.is_empty());
+
+    let mut buf = EasyBuf::from(b"POST /messages\r\n\r\n".to_vec());
+    assert!(codec.decode(&mut buf).is_err());
+}
+
+#[test]
+fn gateway() {
+    let server = Server::new(&"127.0.0.1:0".parse().unwrap());
+    {
+        let registry = server.registry();
+        let mut users = registry.lock().unwrap();
+        users.insert(User::new("ww@breakingbad.com",
+                               Some("heisenberg".to_string())));
+        users.insert(User::new("jesse@breakingbad.com",
+                               Some("science".to_string())));
+        users.insert(User::new("saul@breakingbad.com", None));
+    }
+    let gateway = server.http_gateway();
+    let ww = Some("ww@breakingbad.com:heisenberg");
+    let jesse = Some("jesse@breakingbad.com:science");
+
+    let res = gateway.handle(&request("GET", "/messages", None, ""));
+    assert_eq!(res.status, 401);
+    let wrong = Some("ww@breakingbad.com:blue");
+    let res = gateway.handle(&request("GET", "/messages", wrong, ""));
+    assert_eq!(res.status, 401);
+    // Users without a password can't authenticate, whatever they send.
+    for saul in &["saul@breakingbad.com:", "saul@breakingbad.com:call"] {
+        let res = gateway.handle(&request("GET", "/messages", Some(saul), ""));
+        assert_eq!(res.status, 401);
+    }
+
+    let set = r#"{
+            "id": 1,
+            "method": "set",
+            "uri": "/account",
+            "type": "application/vnd.lime.account+json",
+            "resource": { "fullName": "Walter White" }
+        }"#;
+    let res = gateway.handle(&request("POST", "/commands", ww, set));
+    assert_eq!(res.status, 200);
+    assert_eq!(json(&res.body).find("status").and_then(Value::as_str),
+               Some("success"));
+    let get = r#"{ "id": 2, "method": "get", "uri": "/account" }"#;
+    let res = gateway.handle(&request("POST", "/commands", ww, get));
+    let name = json(&res.body).lookup("resource.fullName")
+        .and_then(Value::as_str).map(str::to_owned);
+    assert_eq!(name, Some("Walter White".to_string()));
+
+    let message = r#"{
+            "id": 3,
+            "to": "jesse@breakingbad.com",
+            "type": "text/plain",
+            "content": "Jesse, we need to cook."
+        }"#;
+    let res = gateway.handle(&request("POST", "/messages", ww, message));
+    assert_eq!(json(&res.body).find("event").and_then(Value::as_str),
+               Some("accepted"));
+
+    let res = gateway.handle(&request("GET", "/messages", jesse, ""));
+    let messages = json(&res.body);
+    let messages = messages.as_array().unwrap();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].find("from").and_then(Value::as_str),
+               Some("ww@breakingbad.com/http"));
+    let res = gateway.handle(&request("GET", "/messages", jesse, ""));
+    assert_eq!(json(&res.body), Value::Array(vec![]));
+
+    let res = gateway.handle(&request("POST", "/messages", ww, "nope"));
+    assert_eq!(res.status, 400);
+    let res = gateway.handle(&request("DELETE", "/messages", ww, ""));
+    assert_eq!(res.status, 405);
+    let res = gateway.handle(&request("GET", "/presence", ww, ""));
+    assert_eq!(res.status, 404);
+}
+
+#[test]
+fn gateway_schemes() {
+    use rust_lime::transport::PeerCredentials;
+
+    let server = Server::new(&"127.0.0.1:0".parse().unwrap());
+    {
+        let registry = server.registry();
+        let mut users = registry.lock().unwrap();
+        users.insert(User::new("ww@breakingbad.com",
+                               Some("heisenberg".to_string())));
+        let mut saul = User::new("saul@breakingbad.com", None);
+        saul.local_uid = Some(1000);
+        users.insert(saul);
+    }
+    let gateway = server.http_gateway();
+    let account = r#"{ "id": 1, "method": "get", "uri": "/account" }"#;
+    let local = |uid| Some(PeerCredentials { uid: uid, gid: uid, pid: None });
+
+    // Callers may name their instance.
+    let message = r#"{
+            "id": 2,
+            "to": "saul@breakingbad.com",
+            "type": "text/plain",
+            "content": "Better call Saul."
+        }"#;
+    let ww = Some("ww@breakingbad.com/lab:heisenberg");
+    let res = gateway.handle(&request("POST", "/messages", ww, message));
+    assert_eq!(res.status, 200);
+
+    // Local callers authenticate by their system user, like sessions.
+    let mut req = request("POST", "/commands", None, account);
+    req.headers.push(("Authorization".to_string(),
+                      "Transport saul@breakingbad.com/office".to_string()));
+    let res = gateway.handle(&req);
+    assert_eq!(res.status, 401);
+    assert_eq!(res.headers.len(), 1);
+
+    req.credentials = local(1001);
+    let res = gateway.handle(&req);
+    assert_eq!(res.status, 401);
+    assert_eq!(res.headers.len(), 2);
+
+    req.credentials = local(1000);
+    let res = gateway.handle(&req);
+    assert_eq!(res.status, 200);
+    assert_eq!(json(&res.body).find("status").and_then(Value::as_str),
+               Some("success"));
+
+    req.method = "GET".to_string();
+    req.path = "/messages".to_string();
+    let res = gateway.handle(&req);
+    let messages = json(&res.body);
+    let messages = messages.as_array().unwrap();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].find("from").and_then(Value::as_str),
+               Some("ww@breakingbad.com/lab"));
+
+    // Passwords are only verified by the plain scheme.
+    let mut req = request("GET", "/messages", None, "");
+    req.headers.push(("Authorization".to_string(),
+                      "Transport ww@breakingbad.com".to_string()));
+    req.credentials = local(1000);
+    assert_eq!(gateway.handle(&req).status, 401);
+}
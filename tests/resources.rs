@@ -0,0 +1,509 @@
+extern crate rust_lime;
+extern crate serde_json;
+
+use std::sync::{Arc, Mutex};
+
+use rust_lime::envelope::Command;
+use rust_lime::envelope::command::{CommandMethod, CommandStatus};
+use rust_lime::envelope::resources::Account;
+use rust_lime::server::command::CommandRouter;
+use rust_lime::server::resources::capability::CapabilityStore;
+use rust_lime::server::resources::groups::GroupStore;
+use rust_lime::server::resources::delegations::DelegationStore;
+use rust_lime::server::resources::account::AccountHandler;
+use rust_lime::user::{PasswordHash, User, UserStore};
+use serde_json::{from_str, from_value, Map, Value};
+
+fn command(method: CommandMethod, uri: &str, resource: Option<Value>)
+        -> Command {
+    Command {
+        to: None,
+        from: None,
+        pp: None,
+        id: Some(1),
+        metadata: None,
+        method: method,
+        status: None,
+        uri: Some(uri.to_string()),
+        mime_type: None,
+        resource: resource,
+        extras: Map::new(),
+    }
+}
+
+/// A store with the users registered, without passwords.
+fn registered(ids: &[&str]) -> Arc<Mutex<UserStore>> {
+    let mut users = UserStore::new();
+    for id in ids {
+        users.insert(User::new(id, None));
+    }
+    Arc::new(Mutex::new(users))
+}
+
+#[test]
+fn account_basic() {
+    let account_json = r#"{
+            "fullName": "Walter White",
+            "email": "ww@breakingbad.com",
+            "inboxSize": 100,
+            "allowUnknownSender": false
+        }"#;
+    let account : Account = from_str(account_json).unwrap();
+    assert_eq!(account.full_name, Some("Walter White".to_string()));
+    assert_eq!(account.inbox_size, Some(100));
+    assert_eq!(account.allow_unknown_sender, Some(false));
+    assert_eq!(account.photo_uri, None);
+}
+
+#[test]
+fn account_set_password() {
+    let users = Arc::new(Mutex::new(UserStore::new()));
+    users.lock().unwrap().insert(
+        User::new("ww@breakingbad.com", Some("heisenberg".to_string())));
+
+    let mut router = CommandRouter::new();
+    AccountHandler::new(users.clone()).register(&mut router);
+    let caller = "ww@breakingbad.com/lab".to_string();
+
+    let wrong = from_str(r#"{ "password": "blue", "oldPassword": "nope" }"#);
+    let res = router.dispatch(&caller,
+        &command(CommandMethod::Set, "/account", Some(wrong.unwrap())));
+    assert!(res.status != Some(CommandStatus::Success));
+
+    let right = from_str(r#"{
+            "fullName": "Walter White",
+            "password": "blue",
+            "oldPassword": "heisenberg"
+        }"#);
+    let res = router.dispatch(&caller,
+        &command(CommandMethod::Set, "/account", Some(right.unwrap())));
+    assert_eq!(res.status, Some(CommandStatus::Success));
+    assert!(users.lock().unwrap().get("ww@breakingbad.com").unwrap()
+                 .verify_password(Some("blue")));
+
+    let res = router.dispatch(&caller,
+        &command(CommandMethod::Get, "/account", None));
+    let account : Account = from_value(res.resource.unwrap()).unwrap();
+    assert_eq!(account.full_name, Some("Walter White".to_string()));
+    assert_eq!(account.password, None);
+
+    // Accounts are never created by commands.
+    let res = router.dispatch(&"jesse@breakingbad.com/rv".to_string(),
+        &command(CommandMethod::Set, "/account",
+                 Some(from_str(r#"{ "fullName": "Jesse" }"#).unwrap())));
+    assert!(res.status != Some(CommandStatus::Success));
+    assert!(users.lock().unwrap().get("jesse@breakingbad.com").is_none());
+}
+
+#[test]
+fn passwords() {
+    let mut user = User::new("ww@breakingbad.com", None);
+    assert!(!user.verify_password(None));
+    assert!(!user.verify_password(Some("")));
+    assert!(!user.verify_password(Some("anything")));
+
+    user.set_password("heisenberg");
+    assert!(user.verify_password(Some("heisenberg")));
+    assert!(!user.verify_password(Some("Heisenberg")));
+    assert!(!user.verify_password(None));
+    // Only the salted hash is kept.
+    assert!(!format!("{:?}", user).contains("heisenberg"));
+    assert!(user.password != User::new("ww@breakingbad.com",
+                                       Some("heisenberg".to_string()))
+                                  .password);
+
+    // Hashes are stored with their algorithm and iteration count.
+    let stored = user.password.as_ref().unwrap().encode();
+    assert!(stored.starts_with("pbkdf2-sha256$100000$"));
+    let hash = PasswordHash::decode(&stored).unwrap();
+    assert_eq!(Some(&hash), user.password.as_ref());
+    assert!(hash.verify("heisenberg"));
+    assert!(PasswordHash::decode(&stored.replace("sha256", "sha1"))
+                         .is_none());
+    assert!(PasswordHash::decode("heisenberg").is_none());
+
+    // A user without a password sets one without an old password.
+    let users = registered(&["jesse@breakingbad.com"]);
+    let mut router = CommandRouter::new();
+    AccountHandler::new(users.clone()).register(&mut router);
+    let res = router.dispatch(&"jesse@breakingbad.com/rv".to_string(),
+        &command(CommandMethod::Set, "/account",
+                 Some(from_str(r#"{ "password": "science" }"#).unwrap())));
+    assert_eq!(res.status, Some(CommandStatus::Success));
+    assert!(users.lock().unwrap().get("jesse@breakingbad.com").unwrap()
+                 .verify_password(Some("science")));
+}
+
+#[test]
+fn presence_routing() {
+    use rust_lime::envelope::resources::{Presence, PresenceStatus,
+        RoutingRule};
+    use rust_lime::server::Router;
+    use rust_lime::server::resources::presence::PresenceStore;
+
+    let presence = Arc::new(Mutex::new(PresenceStore::new()));
+    let users = Arc::new(Mutex::new(UserStore::new()));
+    let capabilities = Arc::new(Mutex::new(CapabilityStore::new()));
+    let groups = Arc::new(Mutex::new(GroupStore::new()));
+    let delegations = Arc::new(Mutex::new(DelegationStore::new()));
+    let router = Router::new(presence.clone(), users, capabilities, groups,
+                             delegations);
+    let online = vec!["ww@breakingbad.com/lab".to_string(),
+                      "ww@breakingbad.com/home".to_string()];
+
+    // Nobody accepts envelopes for the identity without a routing rule.
+    assert!(router.destinations("ww@breakingbad.com", &online).is_empty());
+    assert_eq!(router.destinations("ww@breakingbad.com/lab", &online),
+               vec!["ww@breakingbad.com/lab".to_string()]);
+
+    let rule = |priority| Presence {
+        status: Some(PresenceStatus::Available),
+        routing_rule: Some(RoutingRule::Identity),
+        priority: Some(priority),
+        ..Presence::default()
+    };
+    presence.lock().unwrap().set("ww@breakingbad.com/lab", rule(1));
+    presence.lock().unwrap().set("ww@breakingbad.com/home", rule(5));
+
+    assert_eq!(router.destinations("ww@breakingbad.com", &online),
+               vec!["ww@breakingbad.com/home".to_string()]);
+    assert!(router.destinations("ww@breakingbad.com/car", &online)
+                  .is_empty());
+
+    let identity = presence.lock().unwrap().identity("ww@breakingbad.com");
+    assert_eq!(identity.unwrap().instances.unwrap().len(), 2);
+
+    // An unavailable instance never outranks a routable one.
+    presence.lock().unwrap().set("ww@breakingbad.com/car", Presence {
+        status: Some(PresenceStatus::Unavailable),
+        priority: Some(10),
+        ..Presence::default()
+    });
+    let identity = presence.lock().unwrap().identity("ww@breakingbad.com");
+    assert_eq!(identity.unwrap().priority, Some(5));
+
+    // Finished sessions are forgotten.
+    router.forget("ww@breakingbad.com/home");
+    assert_eq!(router.destinations("ww@breakingbad.com", &online),
+               vec!["ww@breakingbad.com/lab".to_string()]);
+}
+
+#[test]
+fn contacts_roster() {
+    use rust_lime::envelope::resources::{Collection, Contact};
+    use rust_lime::server::Router;
+    use rust_lime::server::resources::contacts::ContactsHandler;
+    use rust_lime::server::resources::presence::PresenceStore;
+    use rust_lime::server::resources::subscriptions::SubscriptionStore;
+
+    let users = registered(&["ww@breakingbad.com", "jesse@breakingbad.com"]);
+    let subscriptions = Arc::new(Mutex::new(SubscriptionStore::new()));
+    let mut router = CommandRouter::new();
+    ContactsHandler::new(users.clone(), subscriptions).register(&mut router);
+    let ww = "ww@breakingbad.com/lab".to_string();
+    let jesse = "jesse@breakingbad.com/rv".to_string();
+
+    let add = |caller: &String, identity: &str| {
+        let contact = from_str(&format!(r#"{{ "identity": "{}" }}"#, identity));
+        router.dispatch(caller, &command(CommandMethod::Set, "/contacts",
+                                         Some(contact.unwrap())))
+    };
+    assert_eq!(add(&ww, "jesse@breakingbad.com").status,
+               Some(CommandStatus::Success));
+
+    let res = router.dispatch(&ww,
+        &command(CommandMethod::Get, "/contacts/jesse@breakingbad.com", None));
+    let contact : Contact = from_value(res.resource.unwrap()).unwrap();
+    assert_eq!(contact.is_pending, Some(true));
+
+    add(&jesse, "ww@breakingbad.com");
+    let res = router.dispatch(&ww,
+        &command(CommandMethod::Get, "/contacts", None));
+    let roster : Collection = from_value(res.resource.unwrap()).unwrap();
+    assert_eq!(roster.total, 1);
+    let contact : Contact = from_value(roster.items[0].clone()).unwrap();
+    assert_eq!(contact.is_pending, Some(false));
+
+    let presence = Arc::new(Mutex::new(PresenceStore::new()));
+    let capabilities = Arc::new(Mutex::new(CapabilityStore::new()));
+    let groups = Arc::new(Mutex::new(GroupStore::new()));
+    let delegations = Arc::new(Mutex::new(DelegationStore::new()));
+    let routing = Router::new(presence, users.clone(), capabilities, groups,
+                              delegations);
+    users.lock().unwrap().get_mut("ww@breakingbad.com").unwrap().account
+         .allow_unknown_sender = Some(false);
+    assert!(routing.is_authorized(&jesse, &ww));
+    assert!(!routing.is_authorized("skyler@breakingbad.com", &ww));
+}
+
+#[test]
+fn capability_negotiation() {
+    use rust_lime::envelope::resources::Capability;
+    use rust_lime::server::resources::capability::CapabilityHandler;
+
+    let server = Capability {
+        content_types: vec!["text/plain".to_string(),
+                            "application/*".to_string()],
+        resource_types: Vec::new(),
+    };
+    let nodes = Arc::new(Mutex::new(CapabilityStore::new()));
+    let mut router = CommandRouter::new();
+    CapabilityHandler::new(server, nodes.clone()).register(&mut router);
+    let caller = "jesse@breakingbad.com/rv".to_string();
+
+    let declared = from_str(r#"{
+            "contentTypes": ["text/plain", "image/png"],
+            "resourceTypes": ["application/vnd.lime.account+json"]
+        }"#);
+    let res = router.dispatch(&caller,
+        &command(CommandMethod::Set, "/capability", Some(declared.unwrap())));
+    let negotiated : Capability = from_value(res.resource.unwrap()).unwrap();
+    assert_eq!(negotiated.content_types, vec!["text/plain".to_string()]);
+
+    let nodes = nodes.lock().unwrap();
+    assert!(nodes.accepts_content(&caller, "text/plain; charset=utf-8"));
+    assert!(!nodes.accepts_content(&caller, "application/json"));
+    assert!(nodes.accepts_content("ww@breakingbad.com/lab", "application/json"));
+}
+
+#[test]
+fn groups_membership() {
+    use rust_lime::envelope::resources::{Collection, GroupMember, GroupRole};
+    use rust_lime::server::resources::groups::GroupsHandler;
+
+    let groups = Arc::new(Mutex::new(GroupStore::new()));
+    let mut router = CommandRouter::new();
+    GroupsHandler::new(groups.clone()).register(&mut router);
+    let ww = "ww@breakingbad.com/lab".to_string();
+    let jesse = "jesse@breakingbad.com/rv".to_string();
+    let uri = "/groups/cook@groups.breakingbad.com/members";
+
+    let group = from_str(r#"{ "identity": "cook@groups.breakingbad.com" }"#);
+    let res = router.dispatch(&ww,
+        &command(CommandMethod::Set, "/groups", Some(group.unwrap())));
+    assert_eq!(res.status, Some(CommandStatus::Success));
+
+    // The same group under an instance is not another group.
+    let group = r#"{ "identity": "cook@groups.breakingbad.com/lab" }"#;
+    let res = router.dispatch(&jesse, &command(CommandMethod::Set, "/groups",
+                                               Some(from_str(group).unwrap())));
+    assert!(res.status != Some(CommandStatus::Success));
+
+    // Non members can neither read nor change the group.
+    let member = from_str(r#"{ "address": "jesse@breakingbad.com" }"#);
+    let member : Value = member.unwrap();
+    let res = router.dispatch(&jesse,
+        &command(CommandMethod::Set, uri, Some(member.clone())));
+    assert!(res.status != Some(CommandStatus::Success));
+
+    let res = router.dispatch(&ww,
+        &command(CommandMethod::Set, uri, Some(member)));
+    assert_eq!(res.status, Some(CommandStatus::Success));
+
+    let res = router.dispatch(&jesse, &command(CommandMethod::Get, uri, None));
+    let members : Collection = from_value(res.resource.unwrap()).unwrap();
+    let members = members.items_as::<GroupMember>().unwrap();
+    assert_eq!(members.len(), 2);
+    assert_eq!(members[1].role(), GroupRole::Owner);
+    assert_eq!(groups.lock().unwrap().members("cook@groups.breakingbad.com"),
+               Some(vec!["jesse@breakingbad.com".to_string(),
+                         "ww@breakingbad.com".to_string()]));
+
+    let res = router.dispatch(&jesse, &command(CommandMethod::Delete,
+        "/groups/cook@groups.breakingbad.com/members/ww@breakingbad.com",
+        None));
+    assert!(res.status != Some(CommandStatus::Success));
+}
+
+#[test]
+fn threads_paging() {
+    use rust_lime::envelope::Message;
+    use rust_lime::envelope::document::PlainText;
+    use rust_lime::envelope::resources::{Collection, Direction, Thread,
+        ThreadMessage};
+    use rust_lime::server::resources::threads::{MessageArchive,
+        ThreadsHandler};
+
+    // Clients may send several messages with the same id.
+    let archive = Arc::new(Mutex::new(MessageArchive::new()));
+    for id in 1..6 {
+        let mut msg = Message::with_document(
+            Some("jesse@breakingbad.com".to_string()),
+            &PlainText(format!("message {}", id)));
+        msg.id = Some(id % 3);
+        archive.lock().unwrap().archive("ww@breakingbad.com/lab",
+            "jesse@breakingbad.com", Direction::Sent, &msg);
+    }
+
+    let mut router = CommandRouter::new();
+    ThreadsHandler::new(archive).register(&mut router);
+    let ww = "ww@breakingbad.com/home".to_string();
+
+    let res = router.dispatch(&ww, &command(CommandMethod::Get, "/threads",
+                                            None));
+    let threads : Collection = from_value(res.resource.unwrap()).unwrap();
+    let threads = threads.items_as::<Thread>().unwrap();
+    assert_eq!(threads[0].total, 5);
+    assert_eq!(threads[0].last_message.id, Some(2));
+    assert_eq!(threads[0].last_message.sequence, 5);
+
+    let page = |uri: &str| {
+        let res = router.dispatch(&ww, &command(CommandMethod::Get, uri,
+                                                None));
+        let page : Collection = from_value(res.resource.unwrap()).unwrap();
+        page.items_as::<ThreadMessage>().unwrap().iter()
+            .map(|msg| msg.sequence).collect::<Vec<_>>()
+    };
+    assert_eq!(page("/threads/jesse@breakingbad.com?$take=2"), vec![5, 4]);
+    assert_eq!(page("/threads/jesse@breakingbad.com?$skip=1&$take=2"),
+               vec![4, 3]);
+    assert_eq!(page("/threads/jesse@breakingbad.com?$before=3"), vec![2, 1]);
+    assert_eq!(page("/threads/jesse@breakingbad.com?$before=42").len(), 5);
+    assert!(page("/threads/jesse@breakingbad.com?$before=1").is_empty());
+
+    let res = router.dispatch(&ww, &command(CommandMethod::Get,
+        "/threads/jesse@breakingbad.com?$take=nope", None));
+    assert!(res.status != Some(CommandStatus::Success));
+}
+
+#[test]
+fn subscriptions() {
+    use rust_lime::server::resources::contacts::ContactsHandler;
+    use rust_lime::server::resources::subscriptions::{observe,
+        SubscriptionStore, SubscriptionsHandler};
+
+    let users = registered(&["ww@breakingbad.com", "jesse@breakingbad.com"]);
+    let subscriptions = Arc::new(Mutex::new(SubscriptionStore::new()));
+    let mut router = CommandRouter::new();
+    ContactsHandler::new(users.clone(), subscriptions.clone())
+        .register(&mut router);
+    SubscriptionsHandler::new(subscriptions.clone(), users.clone())
+        .register(&mut router);
+    let ww = "ww@breakingbad.com/lab".to_string();
+    let jesse = "jesse@breakingbad.com/rv".to_string();
+
+    let subscribe = |caller: &String, method| {
+        let mut cmd = command(method, "/presence", None);
+        cmd.to = Some("ww@breakingbad.com".to_string());
+        router.dispatch(caller, &cmd).status
+    };
+    assert!(subscribe(&jesse, CommandMethod::Subscribe) !=
+            Some(CommandStatus::Success));
+
+    let contact = from_str(r#"{ "identity": "jesse@breakingbad.com" }"#);
+    router.dispatch(&ww, &command(CommandMethod::Set, "/contacts",
+                                  Some(contact.unwrap())));
+    assert_eq!(subscribe(&jesse, CommandMethod::Subscribe),
+               Some(CommandStatus::Success));
+    assert_eq!(subscriptions.lock().unwrap()
+                   .subscribers("ww@breakingbad.com/home", "/presence"),
+               vec![jesse.clone()]);
+
+    let res = router.dispatch(&jesse,
+        &command(CommandMethod::Subscribe, "/contacts", None));
+    assert!(res.status != Some(CommandStatus::Success));
+
+    let push = observe(&jesse, &ww, "/presence", None, None);
+    assert_eq!(push.method, CommandMethod::Observe);
+    assert_eq!(push.from, Some("ww@breakingbad.com".to_string()));
+    assert_eq!(push.uri, Some("lime://ww@breakingbad.com/presence".to_string()));
+
+    assert_eq!(subscribe(&jesse, CommandMethod::Unsubscribe),
+               Some(CommandStatus::Success));
+    assert!(subscribe(&jesse, CommandMethod::Unsubscribe) !=
+            Some(CommandStatus::Success));
+
+    subscribe(&jesse, CommandMethod::Subscribe);
+    subscriptions.lock().unwrap().remove_subscriber(&jesse);
+    assert!(subscriptions.lock().unwrap()
+                .subscribers("ww@breakingbad.com", "/presence").is_empty());
+
+    // Contacts the presence isn't shared with can't observe it.
+    let contact = from_str(r#"{
+            "identity": "jesse@breakingbad.com",
+            "sharePresence": false
+        }"#);
+    router.dispatch(&ww, &command(CommandMethod::Set, "/contacts",
+                                  Some(contact.unwrap())));
+    assert!(subscribe(&jesse, CommandMethod::Subscribe) !=
+            Some(CommandStatus::Success));
+    let mut cmd = command(CommandMethod::Subscribe, "/account", None);
+    cmd.to = Some("ww@breakingbad.com".to_string());
+    assert_eq!(router.dispatch(&jesse, &cmd).status,
+               Some(CommandStatus::Success));
+
+    // Subscriptions are revoked once the resource is no longer shared, or
+    // the contact is removed.
+    let contact = from_str(r#"{ "identity": "jesse@breakingbad.com" }"#);
+    router.dispatch(&ww, &command(CommandMethod::Set, "/contacts",
+                                  Some(contact.unwrap())));
+    subscribe(&jesse, CommandMethod::Subscribe);
+    let contact = from_str(r#"{
+            "identity": "jesse@breakingbad.com",
+            "sharePresence": false
+        }"#);
+    router.dispatch(&ww, &command(CommandMethod::Set, "/contacts",
+                                  Some(contact.unwrap())));
+    assert!(subscriptions.lock().unwrap()
+                .subscribers("ww@breakingbad.com", "/presence").is_empty());
+    assert_eq!(subscriptions.lock().unwrap()
+                   .subscribers("ww@breakingbad.com", "/account"),
+               vec![jesse.clone()]);
+
+    router.dispatch(&ww, &command(CommandMethod::Delete,
+                                  "/contacts/jesse@breakingbad.com", None));
+    assert!(subscriptions.lock().unwrap()
+                .subscribers("ww@breakingbad.com", "/account").is_empty());
+}
+
+#[test]
+fn delegations() {
+    use rust_lime::envelope::EnvelopeType;
+    use rust_lime::envelope::resources::Delegation;
+    use rust_lime::server::Router;
+    use rust_lime::server::resources::delegations::{DelegationsHandler,
+        is_delegable};
+    use rust_lime::server::resources::presence::PresenceStore;
+
+    let delegations = Arc::new(Mutex::new(DelegationStore::new()));
+    let mut router = CommandRouter::new();
+    DelegationsHandler::new(delegations.clone()).register(&mut router);
+    let ww = "ww@breakingbad.com/lab".to_string();
+    let bot = "saul@breakingbad.com/bot";
+
+    let delegation = from_str(r#"{
+            "target": "saul@breakingbad.com",
+            "envelopeTypes": ["message"]
+        }"#);
+    let res = router.dispatch(&ww, &command(CommandMethod::Set, "/delegations",
+                                            Some(delegation.unwrap())));
+    assert_eq!(res.status, Some(CommandStatus::Success));
+
+    let res = router.dispatch(&ww, &command(CommandMethod::Get,
+        "/delegations/saul@breakingbad.com", None));
+    let delegation : Delegation = from_value(res.resource.unwrap()).unwrap();
+    assert_eq!(delegation.envelope_types, vec![EnvelopeType::Message]);
+
+    let routing = Router::new(Arc::new(Mutex::new(PresenceStore::new())),
+                              Arc::new(Mutex::new(UserStore::new())),
+                              Arc::new(Mutex::new(CapabilityStore::new())),
+                              Arc::new(Mutex::new(GroupStore::new())),
+                              delegations);
+    assert!(routing.can_send_as(bot, &ww, EnvelopeType::Message));
+    assert!(!routing.can_send_as(bot, &ww, EnvelopeType::Command));
+    assert!(!routing.can_send_as("jesse@breakingbad.com/rv", &ww,
+                                 EnvelopeType::Message));
+    assert!(routing.can_send_as("ww@breakingbad.com/home", &ww,
+                                EnvelopeType::Command));
+
+    let res = router.dispatch(&ww, &command(CommandMethod::Delete,
+        "/delegations/saul@breakingbad.com", None));
+    assert_eq!(res.status, Some(CommandStatus::Success));
+    assert!(!routing.can_send_as(bot, &ww, EnvelopeType::Message));
+
+    assert!(is_delegable("/presence"));
+    assert!(is_delegable("/accounts"));
+    assert!(!is_delegable("/account"));
+    assert!(!is_delegable("/contacts/jesse@breakingbad.com"));
+    assert!(!is_delegable("/delegations"));
+}
@@ -0,0 +1,60 @@
+extern crate rand;
+extern crate rust_lime;
+extern crate serde_json;
+
+use std::env;
+use std::fs;
+
+use rust_lime::envelope::Message;
+use rust_lime::envelope::document::PlainText;
+use rust_lime::server::{MessageStore, MemoryStore, FileStore};
+
+fn message(text: &str) -> Message {
+    let mut msg = Message::with_document(
+        Some("ww@breakingbad.com".to_string()), &PlainText(text.to_string()));
+    msg.from = Some("skyler@breakingbad.com/bedroom".to_string());
+    msg
+}
+
+fn check_store<M: MessageStore>(store: &mut M) {
+    store.store("ww@breakingbad.com", message("one")).unwrap();
+    store.store("ww@breakingbad.com/lab", message("two")).unwrap();
+    store.store("jesse@breakingbad.com", message("other")).unwrap();
+    assert_eq!(store.len("ww@breakingbad.com"), 2);
+
+    let messages = store.take("ww@breakingbad.com/home").unwrap();
+    let texts: Vec<String> = messages.iter()
+        .map(|msg| msg.content_as::<PlainText>().unwrap().0)
+        .collect();
+    assert_eq!(texts, vec!["one".to_string(), "two".to_string()]);
+
+    assert_eq!(store.len("ww@breakingbad.com"), 0);
+    assert!(store.take("ww@breakingbad.com").unwrap().is_empty());
+    assert_eq!(store.len("jesse@breakingbad.com"), 1);
+
+    // Every identity has its own inbox, however close their names.
+    let ids = ["a.b@breakingbad.com", "a_b@breakingbad.com",
+               "a+b@breakingbad.com", "A_b@breakingbad.com",
+               "a%5Fb@breakingbad.com"];
+    for id in &ids {
+        store.store(id, message(id)).unwrap();
+    }
+    for id in &ids {
+        let messages = store.take(id).unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].content_as::<PlainText>().unwrap().0, *id);
+    }
+}
+
+#[test]
+fn memory_store() {
+    check_store(&mut MemoryStore::new());
+}
+
+#[test]
+fn file_store() {
+    let dir = env::temp_dir()
+        .join(format!("rust_lime_file_store_{:016x}", rand::random::<u64>()));
+    check_store(&mut FileStore::new(&dir).unwrap());
+    fs::remove_dir_all(&dir).unwrap();
+}
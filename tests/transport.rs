@@ -0,0 +1,310 @@
+extern crate futures;
+#[cfg(unix)]
+extern crate libc;
+extern crate rust_lime;
+extern crate serde_json;
+extern crate tokio_core;
+#[cfg(unix)]
+extern crate tokio_uds;
+
+use std::io;
+
+use futures::{future, Future, Sink, Stream};
+use tokio_core::io::Io;
+use tokio_core::reactor::Core;
+
+use rust_lime::envelope::{Encoding, Envelope, EnvelopeCodec, EnvelopeStream,
+    LimeCodec, Session};
+use rust_lime::envelope::reason::ReasonCode;
+use rust_lime::envelope::session::SessionState;
+use rust_lime::server::{EnvStream, LimeServer, TcpHandshake};
+use rust_lime::server::handshake::Handshake;
+use rust_lime::user::User;
+use rust_lime::transport::{self, Listener, MemoryListener, MemoryStream,
+    Transport};
+use serde_json::{from_str, Value};
+
+fn message(text: &str) -> Envelope {
+    from_str(&format!(r#"{{
+            "id": 1,
+            "to": "ww@breakingbad.com",
+            "type": "text/plain",
+            "content": "{}"
+        }}"#, text)).unwrap()
+}
+
+fn session(json: &str) -> Envelope {
+    from_str(json).unwrap()
+}
+
+/// Sends the session in `json` and waits for the one answered.
+fn exchange<S: EnvStream>(core: &mut Core, stream: S, json: &str)
+        -> (Session, S) {
+    let answer = stream.send(session(json))
+        .and_then(|stream| stream.into_future().map_err(|(err, _)| err));
+    match core.run(answer).unwrap() {
+        (Some(Envelope::Session(session)), stream) => (session, stream),
+        (other, _) => panic!("Expected a session, got {:?}", other),
+    }
+}
+
+fn failure(session: Session) -> ReasonCode {
+    match session.state {
+        SessionState::Failed(reason) => reason.code,
+        other => panic!("Expected a failed session, got {:?}", other),
+    }
+}
+
+fn schemes(names: &[&str]) -> Option<Vec<Value>> {
+    Some(names.iter().map(|name| Value::String(name.to_string())).collect())
+}
+
+fn content(envelope: Option<Envelope>) -> String {
+    match envelope {
+        Some(Envelope::Message(msg)) =>
+            msg.content.as_str().unwrap().to_owned(),
+        other => panic!("Expected a message, got {:?}", other),
+    }
+}
+
+#[test]
+fn memory_pair() {
+    let mut core = Core::new().unwrap();
+    let (a, b) = transport::memory::pair();
+    let (a, b) = (a.framed(LimeCodec), b.framed(LimeCodec));
+
+    let received = b.into_future().map_err(|(err, _)| err);
+    let sent = a.send(message("Jesse, we need to cook."));
+    let ((received, b), a) = core.run(received.join(sent)).unwrap();
+    assert_eq!(content(received), "Jesse, we need to cook.");
+
+    // The other end sees the end of the stream once one is dropped.
+    drop(a);
+    let (received, _) = core.run(b.into_future().map_err(|(err, _)| err))
+        .unwrap();
+    assert!(received.is_none());
+}
+
+#[test]
+fn memory_listener() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let listener = MemoryListener::bind();
+    let addr = listener.local_addr().unwrap();
+
+    let server = listener.incoming().into_future()
+        .map_err(|(err, _)| err)
+        .and_then(|(conn, _)| {
+            let (stream, _) = conn.unwrap();
+            let mut handshake = TcpHandshake::new();
+            handshake.take_stream(stream);
+            future::poll_fn(move || handshake.update_handshake())
+        })
+        .and_then(|stream| stream.unwrap().into_future()
+                  .map_err(|(err, _)| err));
+    // Nothing is offered when the transport only has `none`, the session
+    // goes on right away.
+    let client = MemoryStream::connect(&addr, &handle)
+        .and_then(|stream| {
+            assert_eq!(stream.peer_addr().unwrap(), addr);
+            stream.framed(LimeCodec).send(session(r#"{"id":1,"state":"new"}"#))
+        })
+        .and_then(|stream| stream.send(message("Yeah, science!")));
+
+    let ((received, _), _) = core.run(server.join(client)).unwrap();
+    assert_eq!(content(received), "Yeah, science!");
+
+    // Only listeners can be connected to.
+    let (stream, _) = transport::memory::pair();
+    let peer = stream.peer_addr().unwrap();
+    let err = core.run(MemoryStream::connect(&peer, &handle)).err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+}
+
+#[test]
+fn handshake_failure() {
+    let mut core = Core::new().unwrap();
+    let (server, client) = transport::memory::pair();
+    let mut handshake = TcpHandshake::new();
+    handshake.take_stream(server);
+
+    // The session must start as new, the client is told why it failed.
+    let client = client.framed(LimeCodec)
+        .send(session(r#"{"id":7,"state":"negotiating"}"#))
+        .and_then(|stream| stream.into_future().map_err(|(err, _)| err));
+    let (handshake, (received, _)) = core.run(
+        future::poll_fn(move || handshake.update_handshake())
+            .then(|res| Ok::<_, io::Error>(res))
+            .join(client)).unwrap();
+    assert!(handshake.is_err());
+    match received {
+        Some(Envelope::Session(session)) => {
+            assert_eq!(session.id, 7);
+            assert_eq!(failure(session),
+                       ReasonCode::SessionInvalidActionForState);
+        }
+        other => panic!("Expected a session, got {:?}", other),
+    }
+}
+
+#[test]
+fn negotiated_encoding() {
+    let mut core = Core::new().unwrap();
+    let (server, client) = transport::memory::pair();
+    let mut handshake = TcpHandshake::new();
+    handshake.take_stream(server);
+
+    // The encoding of the `new` session is kept for the connection.
+    let client = client.framed(EnvelopeCodec::new(Encoding::Cbor))
+        .send(session(r#"{"id":1,"state":"new"}"#));
+    let (stream, client) = core.run(
+        future::poll_fn(move || handshake.update_handshake()).join(client))
+        .unwrap();
+    let received = client.into_future().map_err(|(err, _)| err);
+    let sent = stream.unwrap().send(message("Say my name."));
+    let ((received, _), _) = core.run(received.join(sent)).unwrap();
+    assert_eq!(content(received), "Say my name.");
+}
+
+#[test]
+fn plain_authentication() {
+    type Server = LimeServer<EnvelopeStream<MemoryStream>>;
+
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let server = Server::new(&"127.0.0.1:0".parse().unwrap());
+    server.registry().lock().unwrap().insert(
+        User::new("ww@breakingbad.com", Some("Say my name".to_owned())));
+    let listener = MemoryListener::bind();
+    let addr = listener.local_addr().unwrap();
+    handle.spawn(server.serve::<_, TcpHandshake<MemoryStream>>(listener,
+                                                              &handle)
+                 .map_err(|err| panic!("{}", err)));
+
+    let connect = |core: &mut Core| core.run(
+        MemoryStream::connect(&addr, &handle)).unwrap().framed(LimeCodec);
+    let client = connect(&mut core);
+    let (offer, client) = exchange(&mut core, client,
+                                   r#"{"id":1,"state":"new"}"#);
+    assert_eq!(offer.state, SessionState::Authenticating);
+    assert_eq!(offer.scheme_options, schemes(&["plain"]));
+    let (established, _) = exchange(&mut core, client, r#"{
+        "id": 1,
+        "from": "ww@breakingbad.com/lab",
+        "state": "authenticating",
+        "scheme": "plain",
+        "authentication": { "password": "U2F5IG15IG5hbWU=" }
+    }"#);
+    assert_eq!(established.state, SessionState::Established);
+    assert_eq!(established.to.as_ref().map(|to| &**to),
+               Some("ww@breakingbad.com/lab"));
+
+    // A wrong password, or a scheme the server can't verify, fails.
+    for auth in &[r#""scheme":"plain","authentication":{"password":"U2F5"}"#,
+                  r#""scheme":"plain""#,
+                  r#""scheme":"guest""#,
+                  r#""scheme":"key""#] {
+        let client = connect(&mut core);
+        let (_, client) = exchange(&mut core, client,
+                                   r#"{"id":2,"state":"new"}"#);
+        let (failed, _) = exchange(&mut core, client, &format!(
+            r#"{{"id":2,"from":"ww@breakingbad.com/lab",
+                 "state":"authenticating",{}}}"#, auth));
+        assert_eq!(failed.id, 2);
+        assert_eq!(failure(failed), ReasonCode::SessionAuthenticationFailed);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn transport_authentication() {
+    use std::env;
+
+    use tokio_uds::UnixStream;
+
+    type Server = LimeServer<EnvelopeStream<UnixStream>>;
+
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let path = env::temp_dir()
+        .join(format!("lime-auth-{}.sock", unsafe { libc::getpid() }));
+    let server = Server::unix(&path);
+    let mut walter = User::new("ww@breakingbad.com", None);
+    walter.local_uid = Some(unsafe { libc::getuid() });
+    server.registry().lock().unwrap().insert(walter);
+    server.registry().lock().unwrap()
+        .insert(User::new("jesse@breakingbad.com", None));
+    let listener = server.bind_unix(&handle).unwrap().unwrap();
+    let addr = Listener::local_addr(&listener).unwrap();
+    handle.spawn(server.serve::<_, TcpHandshake<UnixStream>>(listener,
+                                                            &handle)
+                 .map_err(|err| panic!("{}", err)));
+
+    let connect = |core: &mut Core| core.run(
+        <UnixStream as Transport>::connect(&addr, &handle)).unwrap()
+        .framed(LimeCodec);
+    let client = connect(&mut core);
+    let (offer, client) = exchange(&mut core, client,
+                                   r#"{"id":1,"state":"new"}"#);
+    assert_eq!(offer.scheme_options, schemes(&["plain", "transport"]));
+    let (established, _) = exchange(&mut core, client, r#"{
+        "id": 1,
+        "from": "ww@breakingbad.com/home",
+        "state": "authenticating",
+        "scheme": "transport"
+    }"#);
+    assert_eq!(established.state, SessionState::Established);
+
+    // Only the users run by the system user of the client.
+    let client = connect(&mut core);
+    let (_, client) = exchange(&mut core, client, r#"{"id":2,"state":"new"}"#);
+    let (failed, _) = exchange(&mut core, client, r#"{
+        "id": 2,
+        "from": "jesse@breakingbad.com/rv",
+        "state": "authenticating",
+        "scheme": "transport"
+    }"#);
+    assert_eq!(failure(failed), ReasonCode::SessionAuthenticationFailed);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn unix_socket() {
+    use std::env;
+
+    use tokio_uds::UnixStream;
+
+    use rust_lime::server::ClientConnection;
+    use rust_lime::transport::UnixAddr;
+
+    type Server = LimeServer<EnvelopeStream<UnixStream>>;
+
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let path = env::temp_dir()
+        .join(format!("lime-{}.sock", unsafe { libc::getpid() }));
+    let server = Server::unix(&path);
+    let listener = server.bind_unix(&handle).unwrap().unwrap();
+    let addr = Listener::local_addr(&listener).unwrap();
+    assert_eq!(addr, UnixAddr::new(&path));
+
+    let accepted = Listener::incoming(listener).into_future()
+        .map_err(|(err, _)| err);
+    let client = <UnixStream as Transport>::connect(&addr, &handle);
+    let ((conn, _), _client) = core.run(accepted.join(client)).unwrap();
+    let conn: ClientConnection<EnvelopeStream<UnixStream>> =
+        ClientConnection::from(conn.unwrap());
+
+    // Local clients are known by the system user running them.
+    let credentials = conn.credentials().unwrap();
+    assert_eq!(credentials.uid, unsafe { libc::getuid() });
+    let mut user = User::new("ww@breakingbad.com", None);
+    assert!(!user.verify_credentials(&credentials));
+    user.local_uid = Some(credentials.uid);
+    assert!(user.verify_credentials(&credentials));
+
+    // The socket left by the listener is replaced on the next bind.
+    assert!(server.bind_unix(&handle).unwrap().is_some());
+    std::fs::remove_file(&path).unwrap();
+}
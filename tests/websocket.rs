@@ -0,0 +1,185 @@
+extern crate futures;
+extern crate rust_lime;
+extern crate serde_json;
+extern crate tokio_core;
+
+use futures::{future, Future, Sink, Stream};
+use tokio_core::io::{Codec, EasyBuf, Io};
+use tokio_core::net::{TcpListener, TcpStream};
+use tokio_core::reactor::Core;
+
+use rust_lime::envelope::Envelope;
+use rust_lime::server::handshake::Handshake;
+use rust_lime::server::websocket::{accept_key, Role, WsCodec, WsConnect,
+    WsFrame, WsHandshake, WsStream};
+use rust_lime::transport::memory;
+use serde_json::from_str;
+
+fn message(text: &str) -> Envelope {
+    from_str(&format!(r#"{{
+            "id": 1,
+            "to": "ww@breakingbad.com",
+            "type": "text/plain",
+            "content": "{}"
+        }}"#, text)).unwrap()
+}
+
+fn content(envelope: Option<Envelope>) -> String {
+    match envelope {
+        Some(Envelope::Message(msg)) =>
+            msg.content.as_str().unwrap().to_owned(),
+        other => panic!("Expected a message, got {:?}", other),
+    }
+}
+
+fn frame_content(frame: Option<WsFrame>) -> String {
+    match frame {
+        Some(WsFrame::Envelope(envelope)) => content(Some(envelope)),
+        other => panic!("Expected an envelope, got {:?}", other),
+    }
+}
+
+#[test]
+fn accept_key_sample() {
+    // The example of RFC 6455.
+    assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+               "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+}
+
+#[test]
+fn codec_frames() {
+    let mut client = WsCodec::new(Role::Client);
+    let mut server = WsCodec::new(Role::Server);
+
+    let mut buf = Vec::new();
+    client.encode(WsFrame::Envelope(message("Say my name.")), &mut buf)
+        .unwrap();
+    assert_eq!(buf[1] & 0x80, 0x80);
+    // Nothing comes out until the whole frame arrived.
+    let mut partial = EasyBuf::from(buf[..buf.len() - 1].to_vec());
+    assert!(server.decode(&mut partial).unwrap().is_none());
+    let mut easy = EasyBuf::from(buf);
+    assert_eq!(frame_content(server.decode(&mut easy).unwrap()),
+               "Say my name.");
+    assert_eq!(easy.len(), 0);
+
+    // Unmasked client frames are refused.
+    let mut buf = Vec::new();
+    server.encode(WsFrame::Envelope(message("Heisenberg")), &mut buf)
+        .unwrap();
+    assert!(server.decode(&mut EasyBuf::from(buf.clone())).is_err());
+    assert_eq!(frame_content(client.decode(&mut EasyBuf::from(buf)).unwrap()),
+               "Heisenberg");
+
+    // A message split in two frames with a ping in between.
+    let json = serde_json::to_vec(&message("You're goddamn right.")).unwrap();
+    let (first, second) = json.split_at(10);
+    let mut buf = vec![0x01, first.len() as u8];
+    buf.extend_from_slice(first);
+    buf.extend_from_slice(&[0x89, 0x00]);
+    buf.extend_from_slice(&[0x80, second.len() as u8]);
+    buf.extend_from_slice(second);
+    let mut easy = EasyBuf::from(buf);
+    match client.decode(&mut easy).unwrap() {
+        Some(WsFrame::Ping(ref payload)) if payload.is_empty() => (),
+        other => panic!("Expected a ping, got {:?}", other),
+    }
+    assert_eq!(frame_content(client.decode(&mut easy).unwrap()),
+               "You're goddamn right.");
+}
+
+#[test]
+fn codec_violations() {
+    let fails = |frame: &[u8]| {
+        WsCodec::new(Role::Client).decode(&mut EasyBuf::from(frame.to_vec()))
+            .is_err()
+    };
+    // Reserved opcode, then reserved bit.
+    assert!(fails(&[0x83, 0x00]));
+    assert!(fails(&[0xc1, 0x00]));
+    // Continuation of nothing, then a new message before the last ended.
+    assert!(fails(&[0x80, 0x00]));
+    assert!(fails(&[0x01, 0x00, 0x81, 0x00]));
+    // Fragmented ping, then a ping over 125 bytes.
+    assert!(fails(&[0x09, 0x00]));
+    assert!(fails(&[0x89, 0x7e, 0x00, 0x7e]));
+}
+
+#[test]
+fn control_frames() {
+    let (server, client) = memory::pair();
+    let mut server = WsStream::new(server.framed(WsCodec::new(Role::Server)));
+    let client = client.framed(WsCodec::new(Role::Client));
+
+    // Pings are answered with their payload.
+    let client = client.send(WsFrame::Ping(b"Yo".to_vec())).wait().unwrap();
+    assert!(future::lazy(|| server.poll()).wait().unwrap().is_not_ready());
+    let (pong, client) = client.into_future().wait().ok().unwrap();
+    match pong {
+        Some(WsFrame::Pong(payload)) => assert_eq!(payload, b"Yo".to_vec()),
+        other => panic!("Expected a pong, got {:?}", other),
+    }
+
+    // Closing ends the stream once the close is echoed.
+    let client = client.send(WsFrame::Close(vec![0x03, 0xe8])).wait()
+        .unwrap();
+    assert!(future::lazy(|| server.poll()).wait().unwrap().is_ready());
+    match client.into_future().wait().ok().unwrap().0 {
+        Some(WsFrame::Close(payload)) => assert_eq!(payload, vec![0x03, 0xe8]),
+        other => panic!("Expected a close, got {:?}", other),
+    }
+}
+
+#[test]
+fn websocket_exchange() {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &handle)
+        .unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server = listener.incoming().into_future()
+        .map_err(|(err, _)| err)
+        .and_then(|(conn, _)| {
+            let (tcp, _) = conn.unwrap();
+            let mut handshake = WsHandshake::new();
+            handshake.take_stream(tcp);
+            future::poll_fn(move || handshake.update_handshake())
+        })
+        .and_then(|stream| stream.unwrap().into_future()
+                  .map_err(|(err, _)| err));
+    let client = TcpStream::connect(&addr, &handle)
+        .and_then(|tcp| WsConnect::new(tcp, "localhost", "/"))
+        .and_then(|stream| stream.send(message("Jesse, we need to cook.")));
+
+    let ((received, server), client) = core.run(server.join(client)).unwrap();
+    assert_eq!(content(received), "Jesse, we need to cook.");
+
+    let reply = server.send(message("Yeah, science!"));
+    let (_, (received, _)) = core.run(reply.join(
+        client.into_future().map_err(|(err, _)| err))).unwrap();
+    assert_eq!(content(received), "Yeah, science!");
+}
+
+#[test]
+fn websocket_versions() {
+    use std::io::{Read, Write};
+
+    let (server, mut client) = memory::pair();
+    let mut handshake = WsHandshake::new();
+    handshake.take_stream(server);
+    client.write_all(b"GET / HTTP/1.1\r\n\
+                       Host: localhost\r\n\
+                       Upgrade: websocket\r\n\
+                       Connection: Upgrade\r\n\
+                       Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                       Sec-WebSocket-Version: 8\r\n\r\n").unwrap();
+    assert!(future::lazy(|| handshake.update_handshake()).wait().is_err());
+
+    // The client is told which version to speak.
+    let mut response = [0; 512];
+    let len = client.read(&mut response).unwrap();
+    let response = String::from_utf8_lossy(&response[..len]);
+    assert!(response.starts_with("HTTP/1.1 426 Upgrade Required\r\n"));
+    assert!(response.contains("\r\nSec-WebSocket-Version: 13\r\n"));
+}